@@ -6,10 +6,10 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms
 
-use crate::Error;
+use crate::{Error, PAGE_SIZE};
+use core::fmt;
 use goblin::container::Ctx;
 use goblin::elf::header;
-use std::fmt;
 
 /// The architecture for an ELF file.
 ///
@@ -36,6 +36,28 @@ impl Arch {
     pub(crate) fn ctx(&self) -> Ctx {
         self.ctx
     }
+
+    pub(crate) fn machine(&self) -> u16 {
+        self.machine
+    }
+
+    /// The largest address that the program headers of this architecture can
+    /// hold.
+    pub(crate) fn max_address(&self) -> u64 {
+        if self.ctx.is_big() {
+            u64::MAX
+        } else {
+            u64::from(u32::MAX)
+        }
+    }
+
+    /// The default page size for this architecture.
+    pub(crate) fn page_size(&self) -> u64 {
+        match self.machine {
+            header::EM_PPC64 | header::EM_IA_64 => 0x10000,
+            _ => PAGE_SIZE as u64,
+        }
+    }
 }
 
 impl fmt::Display for Arch {
@@ -71,8 +93,12 @@ pub(crate) mod test {
     use goblin::elf::header::EM_ARM;
 
     pub(crate) fn create_arch(c: Container, e: Endian) -> Arch {
+        create_machine_arch(EM_ARM, c, e)
+    }
+
+    pub(crate) fn create_machine_arch(machine: u16, c: Container, e: Endian) -> Arch {
         Arch {
-            machine: EM_ARM,
+            machine,
             ctx: Ctx::new(c, e),
         }
     }
@@ -0,0 +1,63 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use crate::{Error, Result, ValidationError};
+use core::convert::TryFrom;
+use goblin::container::Ctx;
+use goblin::elf::{program_header, Header, ProgramHeader};
+use scroll::Pread;
+
+/// Zero the bss that was omitted from a preloaded image.
+///
+/// `image` is the memory into which the preloaded image was copied starting
+/// from the ELF header at the physical address of the first loadable segment.
+/// It must extend to the end of the bss of the last loadable segment. The rest
+/// of each loadable segment with a file size that is smaller than its memory
+/// size is zeroed. This is only needed if the output was laid out with
+/// [`LayoutConfig::elide_bss`][crate::LayoutConfig::elide_bss].
+///
+/// # Errors
+/// `zero_bss()` can return the following errors:
+/// * `Error::BadElf`: `image` does not start with an ELF header and program
+///   headers
+/// * `Error::InvalidElf`: the bss of a loadable segment extends past the end
+///   of the address space
+/// * `Error::OutputTooSmall`: `image` does not extend to the end of the bss
+// #SPC-elfpreload.elidebss
+pub fn zero_bss(image: &mut [u8]) -> Result<()> {
+    let header: Header = image.pread(0)?;
+    let ctx = Ctx::new(header.container()?, header.endianness()?);
+    let phdrs = ProgramHeader::parse(image, header.e_phoff as usize, header.e_phnum as usize, ctx)?;
+    let loads = || {
+        phdrs
+            .iter()
+            .enumerate()
+            .filter(|(_, ph)| ph.p_type == program_header::PT_LOAD)
+    };
+
+    let base = loads().map(|(_, ph)| ph.p_paddr).min().unwrap_or(0);
+    for (index, ph) in loads().filter(|(_, ph)| ph.p_filesz < ph.p_memsz) {
+        let out_of_range = || ValidationError::SegmentOutOfRange {
+            index,
+            memsz: ph.p_memsz,
+        };
+        let offset = ph.p_paddr - base; // base is the lowest p_paddr
+        let start = offset.checked_add(ph.p_filesz).ok_or_else(out_of_range)?;
+        let end = offset.checked_add(ph.p_memsz).ok_or_else(out_of_range)?;
+        let bss = match (usize::try_from(start), usize::try_from(end)) {
+            (Ok(start), Ok(end)) => image.get_mut(start..end),
+            _ => None,
+        }
+        .ok_or(Error::OutputTooSmall)?;
+        for elt in bss {
+            *elt = 0;
+        }
+    }
+
+    Ok(())
+}
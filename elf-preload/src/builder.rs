@@ -0,0 +1,363 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use crate::PAGE_SIZE;
+use alloc::vec;
+use alloc::vec::Vec;
+use goblin::container::{Container, Ctx, Endian};
+use goblin::elf::header::{self, Header};
+use goblin::elf::program_header::{self, ProgramHeader};
+use goblin::elf::r#dyn::Dyn;
+use scroll::ctx::SizeWith;
+use scroll::Pwrite;
+
+/// A builder for synthetic ELF executables.
+///
+/// `ElfBuilder` is for writing tests (and test fixtures) without needing a
+/// cross toolchain. It creates an executable with an ELF header, a program
+/// header table, and the contents of each of the segments that are added to
+/// it. Segments other than PT_LOAD segments that fall within the contents of a
+/// PT_LOAD segment (by vaddr) are placed within that segment in the file, as a
+/// linker would place them.
+///
+/// `ElfBuilder` is only available with the `test-support` cargo feature.
+///
+/// # Example
+/// ```
+/// use elf_preload::{ElfBuilder, Input, LayoutStrategy};
+/// use goblin::container::{Container, Endian};
+///
+/// let elf = ElfBuilder::new(Container::Big, Endian::Little)
+///     .load(0x40_0000, 0x40_0000, &[0x1f, 0x20, 0x03, 0xd5], 0x1000)
+///     .build();
+///
+/// let input = Input::new(&elf).unwrap();
+/// let layout = input.layout(LayoutStrategy::FromInput).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ElfBuilder {
+    ctx: Ctx,
+    e_type: u16,
+    machine: u16,
+    entry: Option<u64>,
+    segments: Vec<(ProgramHeader, Vec<u8>)>,
+}
+
+impl ElfBuilder {
+    /// Create a builder for an executable of the given class and endianness.
+    ///
+    /// The machine is EM_ARM for a 32 bit executable and EM_AARCH64 for a 64
+    /// bit one.
+    pub fn new(container: Container, endian: Endian) -> Self {
+        let machine = match container {
+            Container::Little => header::EM_ARM,
+            Container::Big => header::EM_AARCH64,
+        };
+
+        ElfBuilder {
+            ctx: Ctx::new(container, endian),
+            e_type: header::ET_EXEC,
+            machine,
+            entry: None,
+            segments: Vec::new(),
+        }
+    }
+
+    /// Set the machine (e_machine) of the executable.
+    pub fn machine(mut self, machine: u16) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    /// Set the type (e_type) of the ELF file. The default is ET_EXEC.
+    pub fn elf_type(mut self, e_type: u16) -> Self {
+        self.e_type = e_type;
+        self
+    }
+
+    /// Set the entry point of the executable.
+    ///
+    /// The default is the vaddr of the first executable PT_LOAD segment (or of
+    /// the first segment if none of them are executable).
+    pub fn entry(mut self, entry: u64) -> Self {
+        self.entry = Some(entry);
+        self
+    }
+
+    /// Add a readable and executable PT_LOAD segment.
+    ///
+    /// The segment has `memsz - contents.len()` bytes of bss and is page
+    /// aligned.
+    pub fn load(self, vaddr: u64, paddr: u64, contents: &[u8], memsz: u64) -> Self {
+        self.segment(
+            ProgramHeader {
+                p_type: program_header::PT_LOAD,
+                p_flags: program_header::PF_R | program_header::PF_X,
+                p_vaddr: vaddr,
+                p_paddr: paddr,
+                p_memsz: memsz,
+                p_align: PAGE_SIZE as u64,
+                ..ProgramHeader::new()
+            },
+            contents,
+        )
+    }
+
+    /// Add a segment with the given program header and contents.
+    ///
+    /// The p_offset and p_filesz of `header` are replaced by the place of
+    /// `contents` in the file; the rest of `header` is used as is.
+    pub fn segment(mut self, header: ProgramHeader, contents: &[u8]) -> Self {
+        self.segments.push((header, contents.to_vec()));
+        self
+    }
+
+    /// Add a PT_DYNAMIC segment with the given dynamic array entries.
+    pub fn dynamic(self, vaddr: u64, entries: &[Dyn]) -> Self {
+        let ctx = self.ctx;
+        let mut contents = vec![0; entries.len() * Dyn::size_with(&ctx)];
+        let mut offset = 0;
+        for entry in entries {
+            contents
+                .gwrite_with(entry.clone(), &mut offset, ctx)
+                .expect("dynamic contents too small");
+        }
+
+        self.segment(
+            segment_header(program_header::PT_DYNAMIC, vaddr, &contents),
+            &contents,
+        )
+    }
+
+    /// Add a PT_INTERP segment that names the given interpreter.
+    pub fn interpreter(self, vaddr: u64, path: &str) -> Self {
+        let mut contents = path.as_bytes().to_vec();
+        contents.push(0);
+
+        self.segment(
+            segment_header(program_header::PT_INTERP, vaddr, &contents),
+            &contents,
+        )
+    }
+
+    /// Add a PT_NOTE segment that holds a single note.
+    pub fn note(self, vaddr: u64, name: &str, n_type: u32, desc: &[u8]) -> Self {
+        let le = self.ctx.le;
+        let namesz = name.len() + 1;
+        let desc_offset = 12 + align4(namesz);
+        let mut contents = vec![0; desc_offset + align4(desc.len())];
+        contents
+            .pwrite_with(namesz as u32, 0, le)
+            .and_then(|_| contents.pwrite_with(desc.len() as u32, 4, le))
+            .and_then(|_| contents.pwrite_with(n_type, 8, le))
+            .expect("note contents too small");
+        contents[12..12 + name.len()].copy_from_slice(name.as_bytes());
+        contents[desc_offset..desc_offset + desc.len()].copy_from_slice(desc);
+
+        self.segment(
+            segment_header(program_header::PT_NOTE, vaddr, &contents),
+            &contents,
+        )
+    }
+
+    /// Build the ELF file.
+    pub fn build(&self) -> Vec<u8> {
+        let ctx = self.ctx;
+        let phoff = Header::size(&ctx);
+        let mut phdrs: Vec<_> = self.segments.iter().map(|(ph, _)| ph.clone()).collect();
+        let mut end = phoff + phdrs.len() * ProgramHeader::size(&ctx);
+
+        let is_load = |ph: &ProgramHeader| ph.p_type == program_header::PT_LOAD;
+        for (ph, (_, contents)) in phdrs.iter_mut().zip(&self.segments) {
+            if is_load(ph) {
+                let offset = align_to_vaddr(end as u64, ph.p_vaddr, ph.p_align);
+                ph.p_offset = offset;
+                ph.p_filesz = contents.len() as u64;
+                end = (offset + ph.p_filesz) as usize;
+            }
+        }
+        for index in 0..phdrs.len() {
+            let ph = &phdrs[index];
+            if is_load(ph) {
+                continue;
+            }
+
+            let filesz = self.segments[index].1.len() as u64;
+            let containing = phdrs.iter().filter(|load| is_load(load)).find(|load| {
+                ph.p_vaddr >= load.p_vaddr && ph.p_vaddr + filesz <= load.p_vaddr + load.p_filesz
+            });
+            let offset = match containing {
+                Some(load) => load.p_offset + (ph.p_vaddr - load.p_vaddr),
+                None => align_to_vaddr(end as u64, ph.p_vaddr, ph.p_align),
+            };
+            end = end.max((offset + filesz) as usize);
+
+            let ph = &mut phdrs[index];
+            ph.p_offset = offset;
+            ph.p_filesz = filesz;
+        }
+
+        let mut elf = vec![0; end];
+        let entry = self.entry.unwrap_or_else(|| {
+            phdrs
+                .iter()
+                .find(|ph| is_load(ph) && ph.is_executable())
+                .or_else(|| phdrs.first())
+                .map_or(0, |ph| ph.p_vaddr)
+        });
+        let header = Header {
+            e_type: self.e_type,
+            e_machine: self.machine,
+            e_entry: entry,
+            e_phoff: phoff as u64,
+            e_phnum: phdrs.len() as u16,
+            ..Header::new(ctx)
+        };
+        elf.pwrite(header, 0).expect("ELF header does not fit");
+
+        let mut offset = phoff;
+        for ph in phdrs.iter() {
+            elf.gwrite_with(ph.clone(), &mut offset, ctx)
+                .expect("program headers do not fit");
+        }
+
+        // Write the contents of the PT_LOAD segments first so that the other
+        // segments within them are not overwritten.
+        let (loads, others): (Vec<_>, Vec<_>) = phdrs
+            .iter()
+            .zip(&self.segments)
+            .partition(|(ph, _)| is_load(ph));
+        for (ph, (_, contents)) in loads.into_iter().chain(others) {
+            let start = ph.p_offset as usize;
+            elf[start..start + contents.len()].copy_from_slice(contents);
+        }
+
+        elf
+    }
+}
+
+fn segment_header(p_type: u32, vaddr: u64, contents: &[u8]) -> ProgramHeader {
+    ProgramHeader {
+        p_type,
+        p_flags: program_header::PF_R,
+        p_vaddr: vaddr,
+        p_paddr: vaddr,
+        p_memsz: contents.len() as u64,
+        p_align: 4,
+        ..ProgramHeader::new()
+    }
+}
+
+fn align4(size: usize) -> usize {
+    (size + 3) & !3
+}
+
+// The lowest offset at or above offset that is congruent to vaddr modulo align.
+fn align_to_vaddr(offset: u64, vaddr: u64, align: u64) -> u64 {
+    if align <= 1 {
+        return offset;
+    }
+
+    let adjustment = (vaddr % align + align - offset % align) % align;
+    offset + adjustment
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::verify_output;
+    use goblin::elf::r#dyn as dynamic;
+    use goblin::elf::Elf;
+
+    #[test]
+    fn elf_builder_build_gives_parsable_elf() {
+        let sut = ElfBuilder::new(Container::Little, Endian::Big)
+            .load(0x1000, 0x4000, b"Hello World!\0", 0x100)
+            .load(0x2000, 0x5000, &[0xdb; 0x20], 0x20);
+
+        let elf = sut.build();
+        let parsed = Elf::parse(&elf).expect("Invalid ELF file");
+
+        assert!(!parsed.is_64);
+        assert!(!parsed.little_endian);
+        assert_eq!(parsed.header.e_machine, header::EM_ARM);
+        assert_eq!(parsed.entry, 0x1000);
+        assert_eq!(parsed.program_headers.len(), 2);
+        let ph = &parsed.program_headers[0];
+        assert_eq!(&elf[ph.file_range()], b"Hello World!\0");
+        assert_eq!(
+            ph.p_offset % PAGE_SIZE as u64,
+            ph.p_vaddr % PAGE_SIZE as u64
+        );
+        assert_eq!(ph.p_memsz, 0x100);
+    }
+
+    #[test]
+    fn elf_builder_places_note_within_containing_load() {
+        let sut = ElfBuilder::new(Container::Big, Endian::Little)
+            .load(0x1000, 0x1000, &[0; 0x40], 0x40)
+            .note(0x1010, "GNU", 3, &[0xab; 5]);
+
+        let elf = sut.build();
+        let parsed = Elf::parse(&elf).expect("Invalid ELF file");
+
+        let load = &parsed.program_headers[0];
+        let note = &parsed.program_headers[1];
+        assert_eq!(note.p_type, program_header::PT_NOTE);
+        assert_eq!(note.p_offset, load.p_offset + 0x10);
+        assert_eq!(note.p_filesz, 12 + 4 + 8);
+        assert_eq!(&elf[note.p_offset as usize + 12..][..4], b"GNU\0");
+    }
+
+    #[test]
+    fn elf_builder_writes_dynamic_entries_and_interpreter() {
+        let sut = ElfBuilder::new(Container::Big, Endian::Big)
+            .machine(header::EM_X86_64)
+            .load(0x1000, 0x1000, &[0; 0x10], 0x10)
+            .interpreter(0x3000, "/lib/ld.so")
+            .dynamic(
+                0x4000,
+                &[
+                    Dyn {
+                        d_tag: dynamic::DT_INIT,
+                        d_val: 0x1000,
+                    },
+                    Dyn {
+                        d_tag: dynamic::DT_NULL,
+                        d_val: 0,
+                    },
+                ],
+            );
+
+        let elf = sut.build();
+        let parsed = Elf::parse(&elf).expect("Invalid ELF file");
+
+        assert_eq!(parsed.header.e_machine, header::EM_X86_64);
+        assert_eq!(parsed.interpreter, Some("/lib/ld.so"));
+        let dynamic = parsed.dynamic.expect("No dynamic array");
+        assert_eq!(dynamic.dyns[0].d_tag, dynamic::DT_INIT);
+    }
+
+    #[test]
+    fn elf_builder_output_lays_out_to_conforming_output() {
+        let elf = ElfBuilder::new(Container::Big, Endian::Big)
+            .load(0x8_0000, 0x8_0000, &[0xdb; 0x80], 0x80)
+            .load(0x8_1000, 0x8_1000, &[0xdb; 0x10], 0x400)
+            .build();
+        let input = crate::Input::new(&elf).expect("Invalid ELF file");
+        let layout = input
+            .layout(crate::LayoutStrategy::FromInput)
+            .expect("layout failed unexpectedly");
+        let mut output = vec![0; layout.required_size()];
+
+        layout.output(&mut output).unwrap().write().unwrap();
+        let report = verify_output(&output).unwrap();
+
+        assert!(report.is_conforming(), "{}", report);
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt::{self, Display, Formatter};
+
+pub(crate) const MAGIC: [u8; 4] = *b"EPCZ";
+pub(crate) const HEADER_SIZE: usize = 24;
+pub(crate) const ENTRY_SIZE: usize = 24;
+
+#[cfg(feature = "deflate")]
+const METHOD_DEFLATE: u32 = 1;
+#[cfg(feature = "lz4")]
+const METHOD_LZ4: u32 = 2;
+
+/// The compression method for the segments of a compressed output (see
+/// [`compressed_output`][crate::Layout::compressed_output]).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    /// Raw deflate (RFC 1951) compression.
+    #[cfg(feature = "deflate")]
+    Deflate,
+
+    /// LZ4 block compression.
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+impl Compression {
+    pub(crate) fn method(self) -> u32 {
+        match self {
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => METHOD_DEFLATE,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => METHOD_LZ4,
+        }
+    }
+
+    pub(crate) fn compress(self, input: &[u8]) -> Vec<u8> {
+        match self {
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => miniz_oxide::deflate::compress_to_vec(
+                input,
+                miniz_oxide::deflate::CompressionLevel::BestCompression as u8,
+            ),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => lz4_flex::block::compress(input),
+        }
+    }
+}
+
+/// The error type for [`unpack`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnpackError {
+    /// The container does not start with the expected magic bytes.
+    BadMagic,
+
+    /// The container uses a compression method that is unknown or that is not
+    /// enabled by a cargo feature.
+    UnsupportedMethod(u32),
+
+    /// The container is shorter than its header and table indicate.
+    Truncated,
+
+    /// A segment would be placed outside of the given memory.
+    OutOfMemory,
+
+    /// A segment failed to decompress to its recorded decompressed length.
+    Decompress,
+}
+
+impl Display for UnpackError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            UnpackError::BadMagic => write!(f, "The container has bad magic bytes."),
+            UnpackError::UnsupportedMethod(method) => write!(
+                f,
+                "The container uses an unsupported compression method: {}",
+                method
+            ),
+            UnpackError::Truncated => write!(f, "The container is truncated."),
+            UnpackError::OutOfMemory => {
+                write!(f, "A segment is outside of the memory for unpacking.")
+            }
+            UnpackError::Decompress => write!(f, "A segment failed to decompress."),
+        }
+    }
+}
+
+/// Decompress each of the segments of a compressed output (see
+/// [`compressed_output`][crate::Layout::compressed_output]) and place it at its
+/// physical address, returning the entry point.
+///
+/// `memory` is the memory into which to place the segments and `memory_start`
+/// is the physical address of its first byte. This function uses only `core`
+/// (it neither allocates nor uses `std`) so that it is suitable for use in a
+/// bootloader.
+///
+/// # Errors
+/// `unpack()` can return the following errors:
+/// * `UnpackError::BadMagic`: `container` is not a compressed output
+/// * `UnpackError::UnsupportedMethod`: the compression method is not enabled
+/// * `UnpackError::Truncated`: `container` is shorter than it should be
+/// * `UnpackError::OutOfMemory`: a segment does not fit in `memory`
+/// * `UnpackError::Decompress`: a segment is corrupt
+pub fn unpack(
+    container: &[u8],
+    memory: &mut [u8],
+    memory_start: u64,
+) -> core::result::Result<u64, UnpackError> {
+    if container.get(0..4) != Some(&MAGIC[..]) {
+        return Err(UnpackError::BadMagic);
+    }
+    let method = read_u32(container, 4)?;
+    let count = to_usize(read_u32(container, 8)?.into(), UnpackError::Truncated)?;
+    let entry = read_u64(container, 16)?;
+
+    let mut data_offset = count
+        .checked_mul(ENTRY_SIZE)
+        .and_then(|size| size.checked_add(HEADER_SIZE))
+        .ok_or(UnpackError::Truncated)?;
+    for index in 0..count {
+        let entry_offset = HEADER_SIZE + index * ENTRY_SIZE;
+        let paddr = read_u64(container, entry_offset)?;
+        let compressed_len = read_u64(container, entry_offset + 8)?;
+        let decompressed_len = read_u64(container, entry_offset + 16)?;
+
+        let data_end = to_usize(compressed_len, UnpackError::Truncated)?
+            .checked_add(data_offset)
+            .ok_or(UnpackError::Truncated)?;
+        let data = container
+            .get(data_offset..data_end)
+            .ok_or(UnpackError::Truncated)?;
+        let start = paddr
+            .checked_sub(memory_start)
+            .ok_or(UnpackError::OutOfMemory)?;
+        let start = to_usize(start, UnpackError::OutOfMemory)?;
+        let end = to_usize(decompressed_len, UnpackError::OutOfMemory)?
+            .checked_add(start)
+            .ok_or(UnpackError::OutOfMemory)?;
+        let output = memory.get_mut(start..end).ok_or(UnpackError::OutOfMemory)?;
+
+        decompress(method, data, output)?;
+        data_offset = data_end;
+    }
+
+    Ok(entry)
+}
+
+// Decompress all of input into exactly all of output.
+#[allow(unused_variables)]
+fn decompress(
+    method: u32,
+    input: &[u8],
+    output: &mut [u8],
+) -> core::result::Result<(), UnpackError> {
+    match method {
+        #[cfg(feature = "deflate")]
+        METHOD_DEFLATE => {
+            use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+            use miniz_oxide::inflate::TINFLStatus;
+
+            let mut decompressor = DecompressorOxide::new();
+            let flags = inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+            match decompress(&mut decompressor, input, output, 0, flags) {
+                (TINFLStatus::Done, _, written) if written == output.len() => Ok(()),
+                _ => Err(UnpackError::Decompress),
+            }
+        }
+
+        #[cfg(feature = "lz4")]
+        METHOD_LZ4 => match lz4_flex::block::decompress_into(input, output) {
+            Ok(written) if written == output.len() => Ok(()),
+            _ => Err(UnpackError::Decompress),
+        },
+
+        _ => Err(UnpackError::UnsupportedMethod(method)),
+    }
+}
+
+// Convert a value read from the container to a usize, failing with error if
+// it does not fit (i.e. on a target with a usize narrower than 64 bits).
+fn to_usize(value: u64, error: UnpackError) -> core::result::Result<usize, UnpackError> {
+    usize::try_from(value).map_err(|_| error)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> core::result::Result<u32, UnpackError> {
+    let mut value = [0; 4];
+    value.copy_from_slice(
+        bytes
+            .get(offset..offset + 4)
+            .ok_or(UnpackError::Truncated)?,
+    );
+    Ok(u32::from_le_bytes(value))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> core::result::Result<u64, UnpackError> {
+    let mut value = [0; 8];
+    value.copy_from_slice(
+        bytes
+            .get(offset..offset + 8)
+            .ok_or(UnpackError::Truncated)?,
+    );
+    Ok(u64::from_le_bytes(value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::test::create_arch;
+    use crate::layout::test::create_layout;
+    use crate::Layout;
+    use goblin::container::{Container, Endian};
+    use scroll::{Pwrite, LE};
+
+    const START: u64 = 0x8000;
+
+    #[test]
+    fn compressed_output_has_segment_table() {
+        let input = vec![0xdb; 0x20];
+        let sut = make_layout(&input);
+
+        let output = sut
+            .compressed_output(any_method())
+            .expect("compressed_output failed unexpectedly");
+
+        assert_eq!(&output[0..4], b"EPCZ");
+        assert_eq!(read_u32(&output, 8), Ok(3));
+        assert_eq!(read_u64(&output, 16), Ok(0x1000));
+        for segment in 0..3 {
+            let offset = HEADER_SIZE + segment * ENTRY_SIZE;
+            assert_eq!(
+                read_u64(&output, offset + 16),
+                Ok(sut.segment_size(segment) as u64)
+            );
+        }
+    }
+
+    #[test]
+    fn unpack_places_segments_as_elf_output() {
+        let input = vec![0xdb; 0x20];
+        let sut = make_layout(&input);
+        let mut expected = vec![0; sut.required_size()];
+        sut.output(&mut expected).unwrap().write().unwrap();
+        let mut memory = vec![0xc0; 0x10000];
+
+        for method in all_methods() {
+            let container = sut.compressed_output(method).unwrap();
+            let entry = unpack(&container, &mut memory, START).expect("unpack failed unexpectedly");
+
+            assert_eq!(entry, 0x1000);
+            assert_eq!(&memory[..expected.len()], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn unpack_with_bad_magic_is_error() {
+        let mut memory = vec![0; 0x100];
+
+        let result = unpack(b"ELF\x7f and some more bytes", &mut memory, START);
+
+        assert_eq!(result, Err(UnpackError::BadMagic));
+    }
+
+    #[test]
+    fn unpack_with_truncated_container_is_error() {
+        let input = vec![0xdb; 0x20];
+        let sut = make_layout(&input);
+        let container = sut.compressed_output(any_method()).unwrap();
+        let mut memory = vec![0; 0x10000];
+
+        let result = unpack(&container[..container.len() - 1], &mut memory, START);
+
+        assert_eq!(result, Err(UnpackError::Truncated));
+    }
+
+    #[test]
+    fn unpack_with_small_memory_is_error() {
+        let input = vec![0xdb; 0x20];
+        let sut = make_layout(&input);
+        let container = sut.compressed_output(any_method()).unwrap();
+        let mut memory = vec![0; 0x100];
+
+        let result = unpack(&container, &mut memory, START);
+
+        assert_eq!(result, Err(UnpackError::OutOfMemory));
+    }
+
+    #[test]
+    fn unpack_with_hostile_compressed_len_is_error() {
+        let input = vec![0xdb; 0x20];
+        let sut = make_layout(&input);
+        let mut container = sut.compressed_output(any_method()).unwrap();
+        container
+            .pwrite_with(u64::MAX, HEADER_SIZE + 8, LE)
+            .unwrap();
+        let mut memory = vec![0; 0x10000];
+
+        let result = unpack(&container, &mut memory, START);
+
+        assert_eq!(result, Err(UnpackError::Truncated));
+    }
+
+    #[test]
+    fn unpack_with_hostile_decompressed_len_is_error() {
+        let input = vec![0xdb; 0x20];
+        let sut = make_layout(&input);
+        let mut container = sut.compressed_output(any_method()).unwrap();
+        container
+            .pwrite_with(u64::MAX, HEADER_SIZE + 16, LE)
+            .unwrap();
+        let mut memory = vec![0; 0x10000];
+
+        let result = unpack(&container, &mut memory, START);
+
+        assert_eq!(result, Err(UnpackError::OutOfMemory));
+    }
+
+    #[test]
+    fn unpack_with_unknown_method_is_error() {
+        let input = vec![0xdb; 0x20];
+        let sut = make_layout(&input);
+        let mut container = sut.compressed_output(any_method()).unwrap();
+        container.pwrite_with(99u32, 4, LE).unwrap();
+        let mut memory = vec![0; 0x10000];
+
+        let result = unpack(&container, &mut memory, START);
+
+        assert_eq!(result, Err(UnpackError::UnsupportedMethod(99)));
+    }
+
+    fn all_methods() -> Vec<Compression> {
+        vec![
+            #[cfg(feature = "deflate")]
+            Compression::Deflate,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+        ]
+    }
+
+    fn any_method() -> Compression {
+        all_methods()[0]
+    }
+
+    fn make_layout(input: &[u8]) -> Layout<'_> {
+        let arch = create_arch(Container::Little, Endian::Little);
+
+        create_layout(arch, input, 0x1000, 0x1000, START)
+    }
+}
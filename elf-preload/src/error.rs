@@ -6,31 +6,581 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms
 
-use std::fmt::{Debug, Display, Formatter};
+use core::fmt::{self, Debug, Display, Formatter};
 
-use failure::*;
+use goblin::elf::{header, reloc};
 use goblin::error::Error as GoblinError;
 
 /// The error type for the different stages of preloading an elf file.
-#[derive(Fail, Debug)]
+#[derive(Debug)]
 pub enum Error {
     /// The input bytes are not a proper ELF file.
-    #[fail(display = "The input bytes are not a proper ELF file.")]
-    BadElf(#[cause] BadElfError),
+    BadElf(BadElfError),
 
     /// The input ELF file has failed a constraint validation
-    #[fail(
-        display = "The input ELF file does not satisfy a required constraint: {}",
-        message
-    )]
-    InvalidElf {
-        /// The error message that describes the failed constraint.
-        message: String,
-    },
+    InvalidElf(ValidationError),
 
     /// The output bytes are too small for the layout of the output ELF file.
-    #[fail(display = "The output bytes are too small for the layout of the output ELF file.")]
     OutputTooSmall,
+
+    /// An address of the output does not fit in the addresses of the output
+    /// format.
+    AddressOutOfRange {
+        /// The address that does not fit.
+        address: u64,
+    },
+
+    /// A page size or alignment of a [`LayoutConfig`][crate::LayoutConfig] is
+    /// not a power of two.
+    BadAlignment {
+        /// The page size or alignment that is not a power of two.
+        align: u64,
+    },
+
+    /// A segment of a preloaded image does not match its digest.
+    #[cfg(feature = "integrity")]
+    DigestMismatch {
+        /// The physical address of the segment that does not match.
+        paddr: u64,
+    },
+
+    /// Writing the output to a [`Write`][std::io::Write] sink failed.
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use Error::*;
+
+        match self {
+            BadElf(_) => write!(f, "The input bytes are not a proper ELF file."),
+            InvalidElf(inner) => write!(
+                f,
+                "The input ELF file does not satisfy a required constraint: {}",
+                inner
+            ),
+            OutputTooSmall => write!(
+                f,
+                "The output bytes are too small for the layout of the output ELF file."
+            ),
+            AddressOutOfRange { address } => write!(
+                f,
+                "The address {:#x} does not fit in the output format.",
+                address
+            ),
+            BadAlignment { align } => write!(
+                f,
+                "The page size or alignment {:#x} is not a power of two.",
+                align
+            ),
+            #[cfg(feature = "integrity")]
+            DigestMismatch { paddr } => write!(
+                f,
+                "The segment at physical address {:#x} does not match its digest.",
+                paddr
+            ),
+            #[cfg(feature = "std")]
+            Io(_) => write!(f, "Unable to write the output."),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::BadElf(inner) => Some(inner),
+            Error::Io(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+/// The constraint that an input ELF file (or the layout of its output) has
+/// failed to satisfy.
+///
+/// Program header indices are indices into the program header table of the
+/// input ELF file unless noted otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    /// The input is not an executable ELF file.
+    NotExecutable {
+        /// The `e_type` of the input.
+        e_type: u16,
+    },
+
+    /// The input is not a position independent executable ELF file.
+    NotPositionIndependent {
+        /// The `e_type` of the input.
+        e_type: u16,
+    },
+
+    /// The input contains a dynamic array.
+    DynamicArray,
+
+    /// The input contains an interpreter reference.
+    Interpreter,
+
+    /// The input depends on shared libraries.
+    SharedLibraries {
+        /// The number of shared libraries.
+        count: usize,
+    },
+
+    /// There is a gap larger than the page size between the physical addresses
+    /// of two consecutive loadable segments.
+    SparseSegments {
+        /// The index of the program header that follows the gap.
+        index: usize,
+        /// The physical address of the end of the segment before the gap.
+        end: u64,
+        /// The physical address of the segment that follows the gap.
+        paddr: u64,
+        /// The size of the gap.
+        gap: u64,
+    },
+
+    /// The physical address of the first loadable segment is too close to zero
+    /// to leave room for the headers.
+    FirstSegmentNearZero {
+        /// The index of the program header for the first loadable segment.
+        index: usize,
+        /// The physical address of the first loadable segment.
+        paddr: u64,
+    },
+
+    /// The physical address derived from the virtual address of the first
+    /// loadable segment does not leave room for the headers below it.
+    HeadersCollision {
+        /// The index of the program header for the first loadable segment.
+        index: usize,
+        /// The derived physical address of the first loadable segment.
+        paddr: u64,
+    },
+
+    /// The virtual address of a loadable segment is below the offset from which
+    /// its physical address is to be derived.
+    VaddrBelowOffset {
+        /// The index of the program header for the segment.
+        index: usize,
+        /// The virtual address of the segment.
+        vaddr: u64,
+    },
+
+    /// A program header to be preserved is not within a loadable segment.
+    PreservedOutsideLoads {
+        /// The index of the program header to be preserved.
+        index: usize,
+        /// The virtual address of the program header to be preserved.
+        vaddr: u64,
+        /// The memory size of the program header to be preserved.
+        memsz: u64,
+    },
+
+    /// A loadable segment extends past the end of the address space in either
+    /// its virtual or its physical addresses.
+    SegmentOutOfRange {
+        /// The index of the program header for the segment.
+        index: usize,
+        /// The memory size of the segment.
+        memsz: u64,
+    },
+
+    /// The file size of a loadable segment is larger than its memory size.
+    FileSizeExceedsMemorySize {
+        /// The index of the program header for the segment.
+        index: usize,
+        /// The file size of the segment.
+        filesz: u64,
+        /// The memory size of the segment.
+        memsz: u64,
+    },
+
+    /// Two loadable segments of the input overlap in physical memory.
+    OverlappingPhysicalAddresses {
+        /// The index of the program header for the first segment.
+        index: usize,
+        /// The index of the program header for the segment that it overlaps.
+        other: usize,
+    },
+
+    /// Two loadable segments of the input overlap in virtual memory.
+    OverlappingVirtualAddresses {
+        /// The index of the program header for the first segment.
+        index: usize,
+        /// The index of the program header for the segment that it overlaps.
+        other: usize,
+    },
+
+    /// The entry point is not in an executable loadable segment of the input.
+    EntryNotInExecutableSegment {
+        /// The entry point.
+        entry: u64,
+    },
+
+    /// Relocations are not supported for the machine of the input.
+    UnsupportedMachine {
+        /// The `e_machine` of the input.
+        machine: u16,
+    },
+
+    /// The input contains a relocation other than an `R_*_RELATIVE` relocation.
+    UnsupportedRelocation {
+        /// The `e_machine` of the input.
+        machine: u16,
+        /// The type of the relocation.
+        r_type: u32,
+    },
+
+    /// The input contains a relocation of a location outside of the contents of
+    /// its segments.
+    RelocationOutsideFile {
+        /// The virtual address of the location to relocate.
+        r_offset: u64,
+    },
+
+    /// The base address for relocating the input moves its entry point or one
+    /// of its segments past the end of the address space.
+    BaseOutOfRange {
+        /// The base address.
+        base: u64,
+    },
+
+    /// The output has no loadable segments.
+    NoLoadableSegments,
+
+    /// The entry point is not in a loadable segment.
+    EntryNotInLoadableSegment {
+        /// The entry point.
+        entry: u64,
+    },
+
+    /// Two loadable segments of the output overlap in physical memory.
+    OverlappingSegments {
+        /// The index of the second of the overlapping program headers in the
+        /// output.
+        index: usize,
+        /// The physical address of the end of the first segment.
+        end: u64,
+        /// The physical address of the second segment.
+        paddr: u64,
+    },
+
+    /// The machine of the input does not match the requested boot image header.
+    BootImageMachine {
+        /// The `e_machine` of the input.
+        machine: u16,
+    },
+
+    /// The flat binary output is too close to zero to leave room for the boot
+    /// image header below it.
+    BootImageNoRoom {
+        /// The load address of the flat binary output.
+        load_address: u64,
+    },
+
+    /// The entry point of a zImage boot image is a Thumb entry point.
+    ThumbEntry {
+        /// The entry point.
+        entry: u64,
+    },
+
+    /// The first program header of a preloaded image is not a PT_PHDR.
+    NoProgramHeaderSegment,
+
+    /// A program header of a preloaded image is out of place (a PT_LOAD after
+    /// a program header of another type, or a second PT_PHDR). The index is an
+    /// index into the program header table of the preloaded image.
+    UnexpectedProgramHeader {
+        /// The index of the program header.
+        index: usize,
+        /// The type of the program header.
+        p_type: u32,
+    },
+
+    /// A loadable segment of a preloaded image does not immediately follow the
+    /// previous one in the file (or, for the first one, does not start with
+    /// the ELF header and program headers). The index is an index into the
+    /// program header table of the preloaded image.
+    NotPlenum {
+        /// The index of the program header for the segment.
+        index: usize,
+    },
+
+    /// A loadable segment of a preloaded image with bss is followed by a
+    /// loadable segment with contents. The index is an index into the program
+    /// header table of the preloaded image.
+    InteriorBss {
+        /// The index of the program header for the segment with bss.
+        index: usize,
+    },
+
+    /// EFI applications are not supported for the machine of the input.
+    EfiMachine {
+        /// The `e_machine` of the input.
+        machine: u16,
+    },
+
+    /// The input is big endian but an EFI application must be little endian.
+    EfiBigEndian,
+
+    /// The flat binary output is too close to zero to leave room for the EFI
+    /// headers and entry stub below it.
+    EfiNoRoom {
+        /// The load address of the flat binary output.
+        load_address: u64,
+    },
+
+    /// The flat binary output has too many sections for the EFI headers.
+    EfiTooManySections {
+        /// The number of sections.
+        sections: usize,
+    },
+
+    /// A loadable segment would start on the page that holds the end of the
+    /// previous loadable segment of the output although their permissions
+    /// differ.
+    SharedPermissionPage {
+        /// The index of the program header for the segment.
+        index: usize,
+        /// The physical address of the segment in the output.
+        paddr: u64,
+    },
+
+    /// The output does not fit below the end physical address of an `EndAt`
+    /// strategy.
+    OutputPastEnd {
+        /// The end physical address.
+        end: u64,
+        /// The size of the output in physical memory.
+        size: u64,
+    },
+
+    /// The start physical address of a `SpecifiedStart` strategy cannot be
+    /// aligned up to the output alignment without passing the end of the
+    /// address space.
+    StartNotAlignable {
+        /// The start physical address.
+        start: u64,
+        /// The output alignment.
+        align: u64,
+    },
+
+    /// The placements of the images given to a planner refer to each other in
+    /// a cycle.
+    PlacementCycle,
+
+    /// An image given to a planner is placed relative to an image that is
+    /// itself or that was not added. Images are indices returned from
+    /// [`Planner::add`][crate::Planner::add].
+    InvalidImageReference {
+        /// The image that is placed.
+        index: usize,
+        /// The image that it is placed relative to.
+        image: usize,
+    },
+
+    /// The start of an image given to a planner cannot be aligned after the
+    /// image it follows without passing the end of the address space.
+    ImageNotAlignable {
+        /// The image that is placed.
+        index: usize,
+        /// The image that it follows.
+        image: usize,
+        /// The alignment of its start.
+        align: u64,
+    },
+
+    /// An image given to a planner is not entirely within one of the RAM
+    /// regions.
+    ImageOutsideRam {
+        /// The image.
+        index: usize,
+        /// The start physical address of the image.
+        start: u64,
+        /// The end physical address of the image.
+        end: u64,
+    },
+
+    /// Two images given to a planner overlap in their physical addresses.
+    OverlappingImages {
+        /// The image with the lower index.
+        first: usize,
+        /// The image with the higher index.
+        second: usize,
+    },
+
+    /// The image does not contain a segment digests note.
+    #[cfg(feature = "integrity")]
+    NoDigestsNote,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use ValidationError::*;
+
+        match self {
+            NotExecutable { .. } => write!(f, "Elf file not an executable file."),
+            NotPositionIndependent { .. } => {
+                write!(f, "Elf file not a position independent executable file.")
+            }
+            DynamicArray => write!(f, "Elf file contains a dynamic array."),
+            Interpreter => write!(f, "Elf file contains an interpretor."),
+            SharedLibraries { .. } => write!(f, "Elf file depends on shared libraries."),
+            SparseSegments { .. } => write!(
+                f,
+                "ELF file segments are sparse with large gaps in their physical layout"
+            ),
+            FirstSegmentNearZero { .. } => write!(
+                f,
+                "ELF file's first segment physical address does not leave room for headers"
+            ),
+            HeadersCollision { .. } => write!(
+                f,
+                "ELF file's first segment physical address derived from its virtual address \
+                 collides with the ELF header and program headers"
+            ),
+            VaddrBelowOffset { .. } => write!(
+                f,
+                "ELF file has a segment whose virtual address is below the offset to its \
+                 physical address"
+            ),
+            PreservedOutsideLoads { .. } => write!(
+                f,
+                "ELF file has a preserved program header outside of its loadable segments"
+            ),
+            SegmentOutOfRange { .. } => write!(
+                f,
+                "ELF file has a segment that extends past the end of the address space"
+            ),
+            FileSizeExceedsMemorySize { .. } => write!(
+                f,
+                "ELF file has a segment whose file size is larger than its memory size"
+            ),
+            OverlappingPhysicalAddresses { .. } => {
+                write!(f, "ELF file segments overlap in their physical addresses")
+            }
+            OverlappingVirtualAddresses { .. } => {
+                write!(f, "ELF file segments overlap in their virtual addresses")
+            }
+            EntryNotInExecutableSegment { .. } => {
+                write!(f, "ELF file's entry point is not in an executable segment")
+            }
+            UnsupportedMachine { machine } => write!(
+                f,
+                "relocations are not supported for {} ELF files",
+                header::machine_to_str(*machine)
+            ),
+            UnsupportedRelocation { machine, r_type } => write!(
+                f,
+                "ELF file contains an unsupported relocation: {}",
+                reloc::r_to_str(*r_type, *machine)
+            ),
+            RelocationOutsideFile { .. } => write!(
+                f,
+                "ELF file contains a relocation outside of the file contents"
+            ),
+            BaseOutOfRange { base } => write!(
+                f,
+                "ELF file relocated to {:#x} does not fit in the address space",
+                base
+            ),
+            NoLoadableSegments => write!(f, "ELF file has no loadable segments"),
+            EntryNotInLoadableSegment { .. } => {
+                write!(f, "ELF file's entry point is not in a loadable segment")
+            }
+            OverlappingSegments { .. } => {
+                write!(f, "ELF file segments overlap in their physical layout")
+            }
+            BootImageMachine { machine } => write!(
+                f,
+                "boot image headers are not supported for {} ELF files",
+                header::machine_to_str(*machine)
+            ),
+            BootImageNoRoom { .. } => write!(
+                f,
+                "ELF file's flat binary output does not leave room for a boot image header"
+            ),
+            ThumbEntry { .. } => write!(
+                f,
+                "ELF file's entry point is a Thumb entry point which a zImage cannot branch to"
+            ),
+            NoProgramHeaderSegment => {
+                write!(f, "preloaded image's first program header is not a PT_PHDR")
+            }
+            UnexpectedProgramHeader { .. } => {
+                write!(f, "preloaded image has an out of place program header")
+            }
+            NotPlenum { .. } => write!(f, "preloaded image's segments do not cover the whole file"),
+            InteriorBss { .. } => write!(
+                f,
+                "preloaded image has bss that is not at the end of its segments"
+            ),
+            EfiMachine { machine } => write!(
+                f,
+                "EFI applications are not supported for {} ELF files",
+                header::machine_to_str(*machine)
+            ),
+            EfiBigEndian => write!(
+                f,
+                "EFI applications are not supported for big endian ELF files"
+            ),
+            EfiNoRoom { .. } => write!(
+                f,
+                "ELF file's flat binary output does not leave room for the EFI headers"
+            ),
+            EfiTooManySections { sections } => write!(
+                f,
+                "ELF file's flat binary output has too many sections for the EFI headers: {}",
+                sections
+            ),
+            SharedPermissionPage { .. } => write!(
+                f,
+                "ELF file segments with different permissions would share a page"
+            ),
+            OutputPastEnd { end, .. } => {
+                write!(f, "the output does not fit below {:#x}", end)
+            }
+            StartNotAlignable { start, align } => write!(
+                f,
+                "the start {:#x} cannot be aligned to {:#x}",
+                start, align
+            ),
+            PlacementCycle => write!(f, "image placements refer to each other in a cycle"),
+            InvalidImageReference { index, image } => write!(
+                f,
+                "image {} is placed relative to an invalid image {}",
+                index, image
+            ),
+            ImageNotAlignable {
+                index,
+                image,
+                align,
+            } => write!(
+                f,
+                "image {} cannot be aligned to {:#x} after image {}",
+                index, align, image
+            ),
+            ImageOutsideRam { index, start, end } => write!(
+                f,
+                "image {} at {:#x}..{:#x} is not within RAM",
+                index, start, end
+            ),
+            OverlappingImages { first, second } => {
+                write!(f, "images {} and {} overlap", first, second)
+            }
+            #[cfg(feature = "integrity")]
+            NoDigestsNote => write!(f, "Elf file does not contain a segment digests note."),
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<ValidationError> for Error {
+    fn from(inner: ValidationError) -> Self {
+        Error::InvalidElf(inner)
+    }
 }
 
 #[doc(hidden)]
@@ -40,21 +590,63 @@ impl From<GoblinError> for Error {
     }
 }
 
+#[doc(hidden)]
+impl From<scroll::Error> for Error {
+    fn from(inner: scroll::Error) -> Self {
+        GoblinError::from(inner).into()
+    }
+}
+
+#[cfg(feature = "std")]
+#[doc(hidden)]
+impl From<std::io::Error> for Error {
+    fn from(inner: std::io::Error) -> Self {
+        Error::Io(inner)
+    }
+}
+
 /// A specilized Result type for elf preloading operations.
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 /// A new type to wrap errors in parsing an ELF file.
-#[derive(Fail)]
-pub struct BadElfError(#[cause] GoblinError);
+pub struct BadElfError(GoblinError);
 
 impl Display for BadElfError {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "Error parsing the input bytes as an ELF file.")
     }
 }
 
 impl Debug for BadElfError {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "goblin error parsing input bytes: {}", self.0)
     }
 }
+
+#[cfg(feature = "std")]
+impl std::error::Error for BadElfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn invalid_elf_display_keeps_constraint_message() {
+        let error = Error::InvalidElf(ValidationError::SparseSegments {
+            index: 2,
+            end: 0x5000,
+            paddr: 0x8000,
+            gap: 0x3000,
+        });
+
+        assert_eq!(
+            error.to_string(),
+            "The input ELF file does not satisfy a required constraint: \
+             ELF file segments are sparse with large gaps in their physical layout"
+        );
+    }
+}
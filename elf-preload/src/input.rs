@@ -6,7 +6,11 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms
 
-use crate::{Arch, Error, Layout, LayoutStrategy, Result, PAGE_SIZE};
+use crate::layout::{within_load, Sections};
+use crate::{reloc, Arch, Error, Layout, LayoutConfig, LayoutStrategy, Result, ValidationError};
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::ops::Range;
 use goblin::elf::{header, program_header, Elf, ProgramHeader};
 use itertools::Itertools;
 
@@ -15,12 +19,23 @@ use itertools::Itertools;
 /// The constraints that the input ELF file must satisfy are:
 /// * it must be an executable ELF file (not a shared library)
 /// * it must contain neither a dynamic array nor an interpreter reference
+/// * its entry point must be within an executable loadable segment
+/// * its loadable segments must not overlap in their virtual addresses (nor in
+///   their physical addresses when these are used for the layout)
+/// * the file size of each loadable segment must not be larger than its memory
+///   size
+///
+/// Alternatively, a position independent executable (static-pie) can be relocated
+/// to a chosen base address with [`with_base`][Input::with_base].
 #[derive(Debug)]
 pub struct Input<'a> {
     arch: Arch,
-    phdr: Vec<ProgramHeader>,
+    // each program header is paired with its index in the input
+    phdr: Vec<(usize, ProgramHeader)>,
+    other_phdr: Vec<(usize, ProgramHeader)>,
     entry: u64,
-    input: &'a [u8],
+    input: Cow<'a, [u8]>,
+    sections: Sections,
 }
 
 impl<'a> Input<'a> {
@@ -30,117 +45,510 @@ impl<'a> Input<'a> {
     /// `new()` can return the following errors:
     /// * `Error::BadElf`: `input` is not an ELF file
     /// * `Error::InvalidElf`: `input` does not satisfy the required constraints
+    ///   (including a segment that extends past the end of the address space)
     pub fn new(input: &'a [u8]) -> Result<Self> {
         let elf = Elf::parse(input)?;
         let arch = Arch::new(&elf.header)?;
         let entry = elf.header.e_entry;
         verify(&elf)?;
+        let sections = Sections::new(&elf, 0);
+        let other_phdr = other_headers(&elf.program_headers);
+        let phdr: Vec<_> = sort_loadable_headers(elf.program_headers).collect();
+        verify_segments(&phdr, entry)?;
+
+        Ok(Input {
+            arch,
+            entry,
+            sections,
+            input: Cow::Borrowed(input),
+            other_phdr,
+            phdr,
+        })
+    }
+
+    /// Create a new `Input` from the given input bytes of a position independent
+    /// executable (static-pie) relocated to the given base address.
+    ///
+    /// The `R_*_RELATIVE` relocations (both RELA and REL) in the input are applied
+    /// for `base` and the virtual and physical addresses of the segments and the
+    /// entry point are offset by `base`. The output of the resulting `Input` is
+    /// a fixed position ELF file. Relocations are supported for x86_64, aarch64,
+    /// and arm ELF files.
+    ///
+    /// # Errors
+    /// `with_base()` can return the following errors:
+    /// * `Error::BadElf`: `input` is not an ELF file
+    /// * `Error::InvalidElf`: `input` is not a position independent executable or
+    ///   contains relocations other than `R_*_RELATIVE` relocations
+    /// * `Error::InvalidElf`: `base` moves the entry point or a segment of
+    ///   `input` past the end of the address space (which ends at 4 GiB for a
+    ///   32 bit `input`)
+    pub fn with_base(input: &'a [u8], base: u64) -> Result<Self> {
+        let elf = Elf::parse(input)?;
+        let arch = Arch::new(&elf.header)?;
+        let relocate = |address: u64| {
+            address
+                .checked_add(base)
+                .filter(|address| *address <= arch.max_address())
+                .ok_or_else(|| Error::from(ValidationError::BaseOutOfRange { base }))
+        };
+        let entry = relocate(elf.header.e_entry)?;
+        verify_position_independent(&elf)?;
+        let relocated = reloc::relocate(&elf, arch, input, base)?;
+        let sections = Sections::new(&elf, base);
+        let phdr = elf
+            .program_headers
+            .into_iter()
+            .map(|ph| match ph.p_memsz {
+                0 => Ok(ph),
+                _ => Ok(ProgramHeader {
+                    p_vaddr: relocate(ph.p_vaddr)?,
+                    p_paddr: relocate(ph.p_paddr)?,
+                    ..ph
+                }),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let other_phdr = other_headers(&phdr);
+        let phdr: Vec<_> = sort_loadable_headers(phdr).collect();
+        verify_segments(&phdr, entry)?;
 
         Ok(Input {
             arch,
-            input,
             entry,
-            phdr: sort_loadable_headers(elf.program_headers).collect(),
+            sections,
+            input: Cow::Owned(relocated),
+            other_phdr,
+            phdr,
         })
     }
 
     /// Layout the output file using the given strategy for selecting the
     /// starting physical address.
     ///
+    /// This is the same as [`layout_with`][Input::layout_with] using the
+    /// default [`LayoutConfig`].
+    ///
     /// # Errors
     /// `layout()` can return the following errors:
-    /// * `Error::InvalidElf`: `start` is `FromInput` and the input contains sparse
-    ///     segments with large gaps between their physical addresses
+    /// * `Error::InvalidElf`: `start` is `FromInput` (or derives the physical
+    ///   addresses from the virtual addresses) and the input contains sparse
+    ///   or overlapping segments in their physical addresses
+    /// * `Error::InvalidElf`: `start` derives the physical addresses from the
+    ///   virtual addresses and the derived physical address of the first
+    ///   segment collides with the headers, or a virtual address is below the
+    ///   offset for `VirtOffset`
+    /// * `Error::InvalidElf`: `start` is `EndAt` and the output is larger than
+    ///   its `end` physical address
     pub fn layout(&'a self, start: LayoutStrategy) -> Result<Layout<'a>> {
-        if start == LayoutStrategy::FromInput {
-            verify_dense_segments(self.phdr.iter())?;
-            verify_first_segment_not_near_zero(self.phdr.iter())?;
+        self.layout_with(start, &LayoutConfig::default())
+    }
+
+    /// Layout the output file using the given strategy for selecting the
+    /// starting physical address and the given options.
+    ///
+    /// # Errors
+    /// `layout_with()` can return the same errors as [`layout`][Input::layout]
+    /// as well as the following errors:
+    /// * `Error::InvalidElf`: a program header that `config` preserves is not
+    ///   within a loadable segment of the input
+    /// * `Error::InvalidElf`: `config` separates permissions and a loadable
+    ///   segment would still share a page with a segment with different
+    ///   permissions
+    /// * `Error::BadAlignment`: the page size or alignment of `config` is not a
+    ///   power of two
+    /// * `Error::InvalidElf`: `start` is `SpecifiedStart` and its address
+    ///   cannot be aligned up to the output alignment of `config` (a start that
+    ///   can be is aligned up)
+    pub fn layout_with(
+        &'a self,
+        start: LayoutStrategy,
+        config: &LayoutConfig,
+    ) -> Result<Layout<'a>> {
+        verify_alignments(config, self.arch)?;
+        let start = align_start(start, config.resolve_align(self.arch))?;
+        let page_size = config.resolve_page_size(self.arch);
+        let phdr = if start.derives_paddr() {
+            Cow::Owned(derive_paddrs(&self.phdr, &start)?)
+        } else {
+            Cow::Borrowed(&self.phdr[..])
+        };
+        if start == LayoutStrategy::FromInput || start.derives_paddr() {
+            verify_no_physical_overlap(&phdr)?;
+            verify_dense_segments(&phdr, page_size)?;
         }
+        let end = match start {
+            LayoutStrategy::EndAt { end, .. } => Some(end),
+            _ => None,
+        };
+
+        let layout = self.layout_segments(&phdr, start, config)?;
 
-        Ok(Layout::new(
+        match end {
+            Some(end) if layout.paddr_range().end > end => {
+                let range = layout.paddr_range();
+                Err(ValidationError::OutputPastEnd {
+                    end,
+                    size: range.end - range.start,
+                }
+                .into())
+            }
+            _ => Ok(layout),
+        }
+    }
+
+    /// Layout the output as one output file for each dense run of segments in
+    /// the input, keeping the physical addresses of the segments from the input.
+    ///
+    /// A run ends where the gap between the physical addresses of two
+    /// consecutive segments is larger than the page size, so the sparse inputs
+    /// that [`FromInput`][LayoutStrategy::FromInput] rejects can still be laid
+    /// out. The layouts are in order of their physical addresses and the
+    /// [`paddr_range`][Layout::paddr_range] of each says where it loads. Every
+    /// layout has the entry point of the input, but only the layout for the run
+    /// that contains the entry point has a
+    /// [`binary_image`][Layout::binary_image].
+    ///
+    /// A preserved program header goes with the run that contains it. One
+    /// without contents (like PT_GNU_STACK) goes with every run.
+    ///
+    /// # Errors
+    /// `layout_runs()` can return the following errors:
+    /// * `Error::InvalidElf`: the segments of the input overlap in physical
+    ///   memory, the first segment of a run is too close to zero to leave room
+    ///   for the headers, a program header that `config` preserves is not
+    ///   within a loadable segment of the input, or `config` separates
+    ///   permissions and a loadable segment shares a page with a segment with
+    ///   different permissions
+    /// * `Error::BadAlignment`: the page size or alignment of `config` is not a
+    ///   power of two
+    pub fn layout_runs(&'a self, config: &LayoutConfig) -> Result<Vec<Layout<'a>>> {
+        verify_alignments(config, self.arch)?;
+        verify_no_physical_overlap(&self.phdr)?;
+        dense_runs(&self.phdr, config.resolve_page_size(self.arch))
+            .into_iter()
+            .map(|run| self.layout_segments(run, LayoutStrategy::FromInput, config))
+            .collect()
+    }
+
+    // Layout the loadable segments in phdr (which are either all of the
+    // loadable segments of the input or one dense run of them).
+    fn layout_segments(
+        &'a self,
+        phdr: &[(usize, ProgramHeader)],
+        start: LayoutStrategy,
+        config: &LayoutConfig,
+    ) -> Result<Layout<'a>> {
+        let preserved: Vec<_> = self
+            .other_phdr
+            .iter()
+            .filter(|(_, ph)| config.preserves(ph.p_type))
+            .collect();
+        verify_preserved_within_loads(&preserved, &self.phdr)?;
+        let preserved: Vec<_> = preserved
+            .into_iter()
+            .filter(|(_, ph)| within_loads(ph, phdr))
+            .collect();
+
+        if start == LayoutStrategy::FromInput || start.derives_paddr() {
+            verify_room_for_headers(phdr, preserved.len(), &start, self.arch, config)?;
+        }
+
+        let layout = Layout::new(
             self.arch,
-            self.phdr.iter(),
-            self.input,
+            phdr.iter().chain(preserved).map(|(_, ph)| ph),
+            &self.input,
+            &self.sections,
             self.entry,
             start,
-        ))
+            config,
+        );
+        if config.separates_permissions() {
+            verify_separate_permissions(&layout, phdr, config.resolve_page_size(self.arch))?;
+        }
+
+        Ok(layout)
     }
 }
 
 fn sort_loadable_headers(
     phdr: impl IntoIterator<Item = ProgramHeader>,
-) -> impl Iterator<Item = ProgramHeader> {
-    phdr.into_iter()
-        .filter(|ph| ph.p_type == program_header::PT_LOAD)
-        .sorted_by_key(|ph| (ph.p_paddr, ph.p_vaddr))
+) -> impl Iterator<Item = (usize, ProgramHeader)> {
+    let mut loads: Vec<_> = phdr
+        .into_iter()
+        .enumerate()
+        .filter(|(_, ph)| ph.p_type == program_header::PT_LOAD)
+        .collect();
+    loads.sort_by_key(|(_, ph)| (ph.p_paddr, ph.p_vaddr));
+
+    loads.into_iter()
+}
+
+fn other_headers(phdr: &[ProgramHeader]) -> Vec<(usize, ProgramHeader)> {
+    phdr.iter()
+        .cloned()
+        .enumerate()
+        .filter(|(_, ph)| {
+            ph.p_type != program_header::PT_LOAD && ph.p_type != program_header::PT_PHDR
+        })
+        .collect()
 }
 
 fn verify(elf: &Elf) -> Result<()> {
-    use Error::InvalidElf;
+    use ValidationError::*;
 
-    let message = if elf.header.e_type != header::ET_EXEC {
-        Some("Elf file not an executable file.")
+    let error = if elf.header.e_type != header::ET_EXEC {
+        Some(NotExecutable {
+            e_type: elf.header.e_type,
+        })
     } else if elf.dynamic.is_some() {
-        Some("Elf file contains a dynamic array.")
+        Some(DynamicArray)
+    } else if elf.interpreter.is_some() {
+        Some(Interpreter)
+    } else {
+        None
+    };
+
+    error.map_or(Ok(()), |error| Err(error.into()))
+}
+
+fn verify_position_independent(elf: &Elf) -> Result<()> {
+    use ValidationError::*;
+
+    let error = if elf.header.e_type != header::ET_DYN {
+        Some(NotPositionIndependent {
+            e_type: elf.header.e_type,
+        })
     } else if elf.interpreter.is_some() {
-        Some("Elf file contains an interpretor.")
+        Some(Interpreter)
+    } else if !elf.libraries.is_empty() {
+        Some(SharedLibraries {
+            count: elf.libraries.len(),
+        })
     } else {
         None
     };
 
-    message.map_or(Ok(()), |message| {
-        Err(InvalidElf {
-            message: message.to_owned(),
+    error.map_or(Ok(()), |error| Err(error.into()))
+}
+
+// #SPC-elfpreload.sanity
+fn verify_segments(phdr: &[(usize, ProgramHeader)], entry: u64) -> Result<()> {
+    use ValidationError::*;
+
+    let executable = |ph: &ProgramHeader| {
+        ph.p_flags & program_header::PF_X != 0
+            && entry >= ph.p_vaddr
+            && entry < ph.p_vaddr + ph.p_memsz
+    };
+    let out_of_range = |ph: &ProgramHeader| {
+        ph.p_vaddr.checked_add(ph.p_memsz).is_none() || ph.p_paddr.checked_add(ph.p_memsz).is_none()
+    };
+
+    // The other checks rely on the segments being within the address space.
+    let error = if let Some((index, ph)) = phdr.iter().find(|(_, ph)| out_of_range(ph)) {
+        Some(SegmentOutOfRange {
+            index: *index,
+            memsz: ph.p_memsz,
         })
-    })
+    } else if let Some((index, ph)) = phdr.iter().find(|(_, ph)| ph.p_filesz > ph.p_memsz) {
+        Some(FileSizeExceedsMemorySize {
+            index: *index,
+            filesz: ph.p_filesz,
+            memsz: ph.p_memsz,
+        })
+    } else if let Some((index, other)) = overlapping(phdr, |ph| ph.p_vaddr..ph.p_vaddr + ph.p_memsz)
+    {
+        Some(OverlappingVirtualAddresses { index, other })
+    } else if !phdr.iter().any(|(_, ph)| executable(ph)) {
+        Some(EntryNotInExecutableSegment { entry })
+    } else {
+        None
+    };
+
+    error.map_or(Ok(()), |error| Err(error.into()))
 }
 
-fn verify_dense_segments<'a>(phdr: impl Iterator<Item = &'a ProgramHeader>) -> Result<()> {
-    let max_segment_gap = phdr
+fn verify_dense_segments(phdr: &[(usize, ProgramHeader)], page_size: u64) -> Result<()> {
+    let sparse = phdr
+        .iter()
         .tuple_windows::<(_, _)>()
-        .map(|(ph1, ph2)| ph2.p_paddr - (ph1.p_paddr + ph1.p_memsz))
-        .max();
-
-    match max_segment_gap {
-        Some(max) if max as usize > PAGE_SIZE => {
-            let message = "ELF file segments are sparse with large gaps in their physical layout";
-            Err(Error::InvalidElf {
-                message: message.to_owned(),
-            })
+        .map(|((_, ph1), (index, ph2))| (*index, ph1.p_paddr + ph1.p_memsz, ph2.p_paddr))
+        .find(|(_, end, paddr)| paddr - end > page_size);
+
+    match sparse {
+        Some((index, end, paddr)) => Err(ValidationError::SparseSegments {
+            index,
+            end,
+            paddr,
+            gap: paddr - end,
         }
-        _ => Ok(()),
+        .into()),
+        None => Ok(()),
     }
 }
 
-fn verify_first_segment_not_near_zero<'a>(
-    phdr: impl Iterator<Item = &'a ProgramHeader>,
+fn overlapping(
+    phdr: &[(usize, ProgramHeader)],
+    range: fn(&ProgramHeader) -> Range<u64>,
+) -> Option<(usize, usize)> {
+    phdr.iter()
+        .tuple_combinations::<(_, _)>()
+        .find(|((_, ph1), (_, ph2))| {
+            let (range1, range2) = (range(ph1), range(ph2));
+            range1.start < range2.end && range2.start < range1.end
+        })
+        .map(|((index, _), (other, _))| (*index, *other))
+}
+
+// #SPC-elfpreload.v2p
+fn derive_paddrs(
+    phdr: &[(usize, ProgramHeader)],
+    start: &LayoutStrategy,
+) -> Result<Vec<(usize, ProgramHeader)>> {
+    let mut derived = phdr
+        .iter()
+        .map(|(index, ph)| match start.derive_paddr(ph.p_vaddr) {
+            Some(paddr) => Ok((
+                *index,
+                ProgramHeader {
+                    p_paddr: paddr,
+                    ..ph.clone()
+                },
+            )),
+            None => Err(ValidationError::VaddrBelowOffset {
+                index: *index,
+                vaddr: ph.p_vaddr,
+            }
+            .into()),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    derived.sort_by_key(|(_, ph)| (ph.p_paddr, ph.p_vaddr));
+
+    Ok(derived)
+}
+
+fn verify_no_physical_overlap(phdr: &[(usize, ProgramHeader)]) -> Result<()> {
+    match overlapping(phdr, |ph| ph.p_paddr..ph.p_paddr + ph.p_memsz) {
+        Some((index, other)) => {
+            Err(ValidationError::OverlappingPhysicalAddresses { index, other }.into())
+        }
+        None => Ok(()),
+    }
+}
+
+// phdr is sorted by paddr so its first segment is the lowest in physical
+// memory; the headers go in the first PT_LOAD of the output just below it.
+fn verify_room_for_headers(
+    phdr: &[(usize, ProgramHeader)],
+    preserved: usize,
+    start: &LayoutStrategy,
+    arch: Arch,
+    config: &LayoutConfig,
 ) -> Result<()> {
-    let min_paddr = phdr.map(|ph| ph.p_paddr).min();
-
-    match min_paddr {
-        Some(min) if (min as usize) < PAGE_SIZE => {
-            let message =
-                "ELF file's first segment physical address does not leave room for headers";
-            Err(Error::InvalidElf {
-                message: message.to_owned(),
-            })
+    let (index, first) = match phdr.first() {
+        Some((index, first)) => (*index, first),
+        None => return Ok(()),
+    };
+    let room = LayoutStrategy::header_load_size(first, phdr.len(), preserved, arch, config)
+        .max(config.resolve_page_size(arch));
+
+    match first.p_paddr {
+        paddr if paddr >= room => Ok(()),
+        paddr if start.derives_paddr() => {
+            Err(ValidationError::HeadersCollision { index, paddr }.into())
+        }
+        paddr => Err(ValidationError::FirstSegmentNearZero { index, paddr }.into()),
+    }
+}
+
+// #SPC-elfpreload.runs
+fn dense_runs(phdr: &[(usize, ProgramHeader)], page_size: u64) -> Vec<&[(usize, ProgramHeader)]> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+
+    for (index, ((_, ph1), (_, ph2))) in phdr.iter().tuple_windows::<(_, _)>().enumerate() {
+        if ph2.p_paddr - (ph1.p_paddr + ph1.p_memsz) > page_size {
+            runs.push(&phdr[start..=index]);
+            start = index + 1;
         }
-        _ => Ok(()),
+    }
+    if start < phdr.len() {
+        runs.push(&phdr[start..]);
+    }
+
+    runs
+}
+
+fn within_loads(ph: &ProgramHeader, loads: &[(usize, ProgramHeader)]) -> bool {
+    ph.p_memsz == 0 || loads.iter().any(|(_, load)| within_load(ph, load))
+}
+
+fn verify_preserved_within_loads(
+    preserved: &[&(usize, ProgramHeader)],
+    loads: &[(usize, ProgramHeader)],
+) -> Result<()> {
+    match preserved.iter().find(|(_, ph)| !within_loads(ph, loads)) {
+        Some((index, ph)) => Err(ValidationError::PreservedOutsideLoads {
+            index: *index,
+            vaddr: ph.p_vaddr,
+            memsz: ph.p_memsz,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+// #SPC-elfpreload.permissions
+fn verify_separate_permissions(
+    layout: &Layout,
+    phdr: &[(usize, ProgramHeader)],
+    page_size: u64,
+) -> Result<()> {
+    match layout.shared_permission_page(page_size) {
+        Some((segment, paddr)) => Err(ValidationError::SharedPermissionPage {
+            index: phdr[segment].0,
+            paddr,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+fn verify_alignments(config: &LayoutConfig, arch: Arch) -> Result<()> {
+    let page_size = config.resolve_page_size(arch);
+    let align = config.resolve_align(arch);
+
+    match [page_size, align]
+        .iter()
+        .find(|align| !align.is_power_of_two())
+    {
+        Some(&align) => Err(Error::BadAlignment { align }),
+        None => Ok(()),
+    }
+}
+
+// Align the start of a SpecifiedStart strategy up to the output alignment.
+fn align_start(start: LayoutStrategy, align: u64) -> Result<LayoutStrategy> {
+    match start {
+        LayoutStrategy::SpecifiedStart(start) => start
+            .checked_add(align - 1)
+            .map(|start| LayoutStrategy::SpecifiedStart(start & !(align - 1)))
+            .ok_or_else(|| ValidationError::StartNotAlignable { start, align }.into()),
+        _ => Ok(start),
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::Error;
+    use crate::builder::ElfBuilder;
+    use crate::{Error, ValidationError, PAGE_SIZE};
     use dynamic::Dyn;
-    use goblin::container::Ctx;
-    use goblin::elf::header::{self, Header};
+    use goblin::elf::header;
     use goblin::elf::program_header::{self, ProgramHeader};
     use goblin::elf::r#dyn as dynamic;
-    use scroll::ctx::SizeWith;
-    use scroll::Pwrite;
-    use std::ffi::CString;
+
+    static HELLO: &[u8] = b"Hello World!\0";
 
     #[test]
     fn new_input_on_bad_bytes_is_error() {
@@ -153,249 +561,710 @@ mod test {
 
     #[test]
     fn new_input_with_shared_library_is_error() {
-        let mut input = vec![0; 512];
-        let ctx = get_ctx();
-        let mut offset: usize = 0;
-        input
-            .gwrite(
-                Header {
-                    e_type: header::ET_DYN,
-                    e_phoff: Header::size(&ctx) as u64,
-                    e_phnum: 1,
-                    ..Header::new(ctx)
-                },
-                &mut offset,
-            )
-            .unwrap();
-        input
-            .gwrite_with(
-                ProgramHeader {
-                    p_offset: (Header::size(&ctx) + ProgramHeader::size(&ctx)) as u64,
-                    p_vaddr: 0x1000,
-                    p_filesz: 13,
-                    p_memsz: 16,
-                    ..ProgramHeader::new()
-                },
-                &mut offset,
-                ctx,
-            )
-            .unwrap();
-        input
-            .gwrite(
-                CString::new("Hello World!").expect("Bad CString"),
-                &mut offset,
-            )
-            .unwrap();
+        let input = create_builder()
+            .elf_type(header::ET_DYN)
+            .load(0x1000, 0x1000, HELLO, 16)
+            .build();
 
         let result = Input::new(&input);
 
-        assert_matches!(result, Err(Error::InvalidElf { message: _ }));
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::NotExecutable {
+                e_type: header::ET_DYN
+            }))
+        );
     }
 
     #[test]
     fn new_input_with_dynamic_array_is_error() {
-        let hello = CString::new("Hello World!").expect("Bad CString");
-        let hello_len = hello.as_bytes_with_nul().len();
-        let mut input = vec![0; 512];
-        let ctx = get_ctx();
-        let mut offset: usize = 0;
-        write_header(&ctx, &mut input, &mut offset, 2);
-        input
-            .gwrite_with(
-                ProgramHeader {
-                    p_offset: (Header::size(&ctx) + 2 * ProgramHeader::size(&ctx)) as u64,
-                    p_vaddr: 0x1000,
-                    p_filesz: 100,
-                    p_memsz: 100,
-                    ..ProgramHeader::new()
-                },
-                &mut offset,
-                ctx,
+        let input = create_builder()
+            .load(0x1000, 0x1000, &[0; 100], 100)
+            .dynamic(
+                0x2000,
+                &[
+                    Dyn {
+                        d_tag: dynamic::DT_INIT,
+                        d_val: 0x1000,
+                    },
+                    Dyn {
+                        d_tag: dynamic::DT_NULL,
+                        d_val: 0,
+                    },
+                ],
             )
-            .unwrap();
-        input
-            .gwrite_with(
-                ProgramHeader {
-                    p_type: program_header::PT_DYNAMIC,
-                    p_offset: (Header::size(&ctx) + 2 * ProgramHeader::size(&ctx) + hello_len)
-                        as u64,
-                    p_vaddr: 0x2000,
-                    p_filesz: 2 * Dyn::size_with(&ctx) as u64,
-                    p_memsz: 2 * Dyn::size_with(&ctx) as u64,
-                    ..ProgramHeader::new()
-                },
-                &mut offset,
-                ctx,
-            )
-            .unwrap();
-        input
-            .gwrite(
-                CString::new("Hello World!").expect("Bad CString"),
-                &mut offset,
-            )
-            .unwrap();
-        input
-            .gwrite_with(
-                Dyn {
-                    d_tag: dynamic::DT_INIT,
-                    d_val: 0x1000,
-                },
-                &mut offset,
-                ctx,
-            )
-            .unwrap();
-        input
-            .gwrite_with(
-                Dyn {
-                    d_tag: dynamic::DT_NULL,
-                    d_val: 0,
-                },
-                &mut offset,
-                ctx,
-            )
-            .unwrap();
+            .build();
 
         let result = Input::new(&input);
 
-        assert_matches!(result, Err(Error::InvalidElf { message: _ }));
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::DynamicArray))
+        );
     }
 
     #[test]
     fn new_input_with_interpretor_is_error() {
-        let hello = CString::new("Hello World!").expect("Bad CString");
-        let hello_len = hello.as_bytes_with_nul().len();
-        let mut input = vec![0; 512];
-        let ctx = get_ctx();
-        let mut offset: usize = 0;
-        write_header(&ctx, &mut input, &mut offset, 2);
-        input
-            .gwrite_with(
-                ProgramHeader {
-                    p_type: program_header::PT_INTERP,
-                    p_offset: (Header::size(&ctx) + 2 * ProgramHeader::size(&ctx)) as u64,
-                    p_vaddr: 0x1000,
-                    p_filesz: hello_len as u64,
-                    p_memsz: hello_len as u64,
-                    ..ProgramHeader::new()
-                },
-                &mut offset,
-                ctx,
-            )
-            .unwrap();
-        input
-            .gwrite_with(
-                ProgramHeader {
-                    p_offset: (Header::size(&ctx) + 2 * ProgramHeader::size(&ctx)) as u64,
-                    p_vaddr: 0x1000,
-                    p_filesz: 100,
-                    p_memsz: 100,
-                    ..ProgramHeader::new()
-                },
-                &mut offset,
-                ctx,
-            )
-            .unwrap();
-        input.gwrite(hello, &mut offset).unwrap();
+        let input = create_builder()
+            .interpreter(0x1000, "Hello World!")
+            .load(0x1000, 0x1000, &[0; 100], 100)
+            .build();
 
         let result = Input::new(&input);
 
-        assert_matches!(result, Err(Error::InvalidElf { message: _ }));
+        assert_matches!(result, Err(Error::InvalidElf(ValidationError::Interpreter)));
     }
 
     #[test]
     fn input_layout_with_from_input_start_and_sparse_segements_is_error() {
-        let hello = CString::new("Hello World!").expect("Bad CString");
-        let hello_len = hello.as_bytes_with_nul().len();
-        let mut buffer = vec![0; 4 * PAGE_SIZE];
-        let ctx = get_ctx();
-        let mut offset: usize = 0;
-        write_header(&ctx, &mut buffer, &mut offset, 2);
-        buffer
-            .gwrite_with(
-                ProgramHeader {
-                    p_offset: (Header::size(&ctx) + 2 * ProgramHeader::size(&ctx)) as u64,
-                    p_vaddr: 0x1000,
-                    p_paddr: 0x4000,
-                    p_filesz: hello_len as u64,
-                    p_memsz: 100,
-                    ..ProgramHeader::new()
-                },
-                &mut offset,
-                ctx,
-            )
-            .unwrap();
-        buffer
-            .gwrite_with(
-                ProgramHeader {
-                    p_offset: (Header::size(&ctx) + 2 * ProgramHeader::size(&ctx) + hello_len)
-                        as u64,
-                    p_vaddr: 0x1000 + 2 * PAGE_SIZE as u64,
-                    p_paddr: 0x4000 + 2 * PAGE_SIZE as u64,
-                    p_filesz: hello_len as u64,
-                    p_memsz: 100,
-                    ..ProgramHeader::new()
-                },
-                &mut offset,
-                ctx,
-            )
-            .unwrap();
-        buffer.gwrite(hello.clone(), &mut offset).unwrap();
-        buffer.gwrite(hello, &mut offset).unwrap();
+        let buffer = create_sparse_input();
 
         let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
         let result = input.layout(LayoutStrategy::FromInput);
 
-        assert_matches!(result, Err(Error::InvalidElf { message: _ }));
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::SparseSegments {
+                index: 1,
+                ..
+            }))
+        );
+    }
+
+    #[test]
+    fn input_layout_with_from_input_start_and_large_page_size_allows_sparse_segments() {
+        let buffer = create_sparse_input();
+        let config = LayoutConfig::new().page_size(4 * PAGE_SIZE as u64);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let result = input.layout_with(LayoutStrategy::FromInput, &config);
+
+        assert_matches!(result, Ok(_));
+    }
+
+    #[test]
+    fn input_layout_with_page_size_not_power_of_two_is_error() {
+        let buffer = create_builder().load(0x1000, 0x4000, HELLO, 100).build();
+        let config = LayoutConfig::new().page_size(0x1800);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let result = input.layout_with(LayoutStrategy::FromInput, &config);
+
+        assert_matches!(result, Err(Error::BadAlignment { align: 0x1800 }));
+    }
+
+    #[test]
+    fn input_layout_with_align_not_power_of_two_is_error() {
+        let buffer = create_builder().load(0x1000, 0x4000, HELLO, 100).build();
+        let config = LayoutConfig::new().align(0x30_0000);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let result = input.layout_runs(&config);
+
+        assert_matches!(result, Err(Error::BadAlignment { align: 0x30_0000 }));
+    }
+
+    #[test]
+    fn input_layout_with_misaligned_specified_start_aligns_start() {
+        let buffer = create_builder().load(0x1000, 0x4000, HELLO, 100).build();
+        let config = LayoutConfig::new().align(0x20_0000);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let layout = input
+            .layout_with(LayoutStrategy::SpecifiedStart(0x10_1000), &config)
+            .expect("layout failed unexpectedly");
+
+        assert_eq!(layout.paddr_range().start, 0x20_0000);
+    }
+
+    #[test]
+    fn input_layout_with_specified_start_past_end_of_address_space_is_error() {
+        let buffer = create_builder().load(0x1000, 0x4000, HELLO, 100).build();
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let result = input.layout(LayoutStrategy::SpecifiedStart(u64::MAX - 0x10));
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::StartNotAlignable {
+                start: 0xffff_ffff_ffff_ffef,
+                ..
+            }))
+        );
     }
 
     #[test]
     fn input_layout_with_from_input_start_and_first_segment_near_zero_is_error() {
-        let hello = CString::new("Hello World!").expect("Bad CString");
-        let hello_len = hello.as_bytes_with_nul().len();
-        let mut buffer = vec![0; 4 * PAGE_SIZE];
-        let ctx = get_ctx();
-        let mut offset: usize = 0;
-        write_header(&ctx, &mut buffer, &mut offset, 2);
-        buffer
-            .gwrite_with(
+        let buffer = create_builder().load(0x1000, 0x10, HELLO, 100).build();
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let result = input.layout(LayoutStrategy::FromInput);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::FirstSegmentNearZero {
+                index: 0,
+                paddr: 0x10
+            }))
+        );
+    }
+
+    #[test]
+    fn input_with_base_for_executable_is_error() {
+        let input = create_builder().build();
+
+        let result = Input::with_base(&input, 0x10000);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(
+                ValidationError::NotPositionIndependent { .. }
+            ))
+        );
+    }
+
+    #[test]
+    fn input_with_base_offsets_segment_addresses() {
+        use crate::reloc::test::create_pie;
+        use goblin::container::Container;
+        let base = 0x10000;
+        let input = create_pie(Container::Big, header::EM_AARCH64, true);
+
+        let sut = Input::with_base(&input, base).expect("with_base failed unexpectedly");
+
+        assert_eq!(sut.entry, base);
+        assert_eq!(sut.phdr[0].1.p_vaddr, base);
+        assert_eq!(sut.phdr[0].1.p_paddr, base);
+    }
+
+    #[test]
+    fn input_with_base_past_end_of_address_space_is_error() {
+        use crate::reloc::test::create_pie;
+        use goblin::container::Container;
+        let base = u64::MAX - 0x10;
+        let input = create_pie(Container::Big, header::EM_AARCH64, true);
+
+        let result = Input::with_base(&input, base);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::BaseOutOfRange { base: b })) if b == base
+        );
+    }
+
+    #[test]
+    fn input_with_base_past_4g_for_elf32_is_error() {
+        use crate::reloc::test::create_pie;
+        use goblin::container::Container;
+        let base = 0x1_0000_0000;
+        let input = create_pie(Container::Little, header::EM_ARM, false);
+
+        let result = Input::with_base(&input, base);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::BaseOutOfRange { base: b })) if b == base
+        );
+    }
+
+    #[test]
+    fn input_layout_end_at_below_output_size_is_error() {
+        use crate::reloc::test::create_pie;
+        use goblin::container::Container;
+        let input = create_pie(Container::Big, header::EM_AARCH64, true);
+        let sut = Input::with_base(&input, 0x10000).expect("with_base failed unexpectedly");
+
+        let result = sut.layout(LayoutStrategy::EndAt {
+            end: 0x100,
+            align: 0,
+        });
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::OutputPastEnd {
+                end: 0x100,
+                ..
+            }))
+        );
+    }
+
+    #[test]
+    fn input_layout_preserving_tls_with_tbss_past_loads_keeps_tls() {
+        let buffer = create_builder()
+            .load(0x1000, 0x4000, HELLO, 0x100)
+            .segment(
                 ProgramHeader {
-                    p_offset: (Header::size(&ctx) + ProgramHeader::size(&ctx)) as u64,
-                    p_vaddr: 0x1000,
-                    p_paddr: 0x0010,
-                    p_filesz: hello_len as u64,
-                    p_memsz: 100,
+                    p_type: program_header::PT_TLS,
+                    p_flags: program_header::PF_R,
+                    p_vaddr: 0x10f0,
+                    p_memsz: 0x1000,
+                    p_align: 0x8,
                     ..ProgramHeader::new()
                 },
-                &mut offset,
-                ctx,
+                &HELLO[..8],
             )
-            .unwrap();
-        buffer.gwrite(hello, &mut offset).unwrap();
+            .build();
+        let config = LayoutConfig::new().preserve(program_header::PT_TLS);
 
         let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
-        let result = input.layout(LayoutStrategy::FromInput);
+        let layout = input
+            .layout_with(LayoutStrategy::FromInput, &config)
+            .expect("layout failed unexpectedly");
+
+        let tls = &layout.program_headers()[3];
+        assert_eq!(tls.p_type, program_header::PT_TLS);
+        assert_eq!(tls.p_paddr, 0x40f0);
+    }
+
+    #[test]
+    fn input_layout_preserving_header_past_end_of_address_space_is_error() {
+        let buffer = create_sparse_input();
+        let config = LayoutConfig::new().preserve(program_header::PT_NOTE);
+
+        let mut input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        input.other_phdr.push((
+            2,
+            ProgramHeader {
+                p_type: program_header::PT_NOTE,
+                p_vaddr: 0x1000,
+                p_filesz: 0x10,
+                p_memsz: u64::MAX,
+                ..ProgramHeader::new()
+            },
+        ));
+        let result = input.layout_with(LayoutStrategy::SpecifiedStart(0x10000), &config);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::PreservedOutsideLoads {
+                vaddr: 0x1000,
+                ..
+            }))
+        );
+    }
+
+    #[test]
+    fn input_layout_preserving_header_outside_loads_is_error() {
+        let buffer = create_sparse_input();
+        let config = LayoutConfig::new().preserve(program_header::PT_NOTE);
 
-        assert_matches!(result, Err(Error::InvalidElf { message: _ }));
+        let mut input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        input.other_phdr.push((
+            2,
+            ProgramHeader {
+                p_type: program_header::PT_NOTE,
+                p_vaddr: 0x8000,
+                p_filesz: 0x10,
+                p_memsz: 0x10,
+                ..ProgramHeader::new()
+            },
+        ));
+        let result = input.layout_with(LayoutStrategy::SpecifiedStart(0x10000), &config);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::PreservedOutsideLoads {
+                vaddr: 0x8000,
+                ..
+            }))
+        );
+    }
+
+    #[test]
+    fn input_layout_without_preserve_drops_other_headers() {
+        let buffer = create_sparse_input();
+
+        let mut input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        input.other_phdr.push((
+            2,
+            ProgramHeader {
+                p_type: program_header::PT_GNU_STACK,
+                p_memsz: 0,
+                ..ProgramHeader::new()
+            },
+        ));
+        let layout = input
+            .layout(LayoutStrategy::SpecifiedStart(0x10000))
+            .expect("layout failed unexpectedly");
+
+        assert!(layout
+            .program_headers()
+            .iter()
+            .all(|ph| ph.p_type != program_header::PT_GNU_STACK));
+    }
+
+    #[test]
+    fn input_layout_runs_splits_sparse_segments() {
+        let buffer = create_sparse_input();
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let layouts = input
+            .layout_runs(&LayoutConfig::new())
+            .expect("layout_runs failed unexpectedly");
+
+        assert_eq!(layouts.len(), 2);
+        assert_eq!(layouts[0].program_headers()[2].p_paddr, 0x4000);
+        assert_eq!(layouts[0].paddr_range().end, 0x4000 + 100);
+        assert_eq!(
+            layouts[1].program_headers()[2].p_paddr,
+            0x4000 + 2 * PAGE_SIZE as u64
+        );
+        assert_eq!(
+            layouts[1].paddr_range().end,
+            0x4000 + 2 * PAGE_SIZE as u64 + 100
+        );
     }
 
-    fn get_ctx() -> Ctx {
+    #[test]
+    fn input_layout_runs_for_dense_input_is_single_layout() {
+        let buffer = create_input(&[create_load(0x1000, 0x4000), create_load(0x2000, 0x4800)]);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let layouts = input
+            .layout_runs(&LayoutConfig::new())
+            .expect("layout_runs failed unexpectedly");
+        let expected = input
+            .layout(LayoutStrategy::FromInput)
+            .expect("layout failed unexpectedly");
+
+        assert_eq!(layouts.len(), 1);
+        assert_eq!(layouts[0].program_headers(), expected.program_headers());
+    }
+
+    #[test]
+    fn input_layout_runs_preserves_header_with_containing_run() {
+        let buffer = create_sparse_input();
+        let config = LayoutConfig::new().preserve(program_header::PT_NOTE);
+
+        let mut input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        input.other_phdr.push((
+            2,
+            ProgramHeader {
+                p_type: program_header::PT_NOTE,
+                p_vaddr: 0x1000 + 2 * PAGE_SIZE as u64,
+                p_filesz: 0x10,
+                p_memsz: 0x10,
+                ..ProgramHeader::new()
+            },
+        ));
+        let layouts = input
+            .layout_runs(&config)
+            .expect("layout_runs failed unexpectedly");
+        let has_note = |layout: &Layout| {
+            layout
+                .program_headers()
+                .iter()
+                .any(|ph| ph.p_type == program_header::PT_NOTE)
+        };
+
+        assert!(!has_note(&layouts[0]));
+        assert!(has_note(&layouts[1]));
+    }
+
+    #[test]
+    fn new_input_with_filesz_larger_than_memsz_is_error() {
+        let buffer = create_input(&[ProgramHeader {
+            p_filesz: 0x200,
+            ..create_load(0x1000, 0x4000)
+        }]);
+
+        let result = Input::new(&buffer);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(
+                ValidationError::FileSizeExceedsMemorySize {
+                    index: 0,
+                    filesz: 0x200,
+                    memsz: 0x100,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn new_input_with_segment_past_end_of_address_space_is_error() {
         use goblin::container::{Container, Endian};
 
-        Ctx::new(Container::Little, Endian::Little)
+        let buffer = ElfBuilder::new(Container::Big, Endian::Little)
+            .load(0x1000, 0x4000, HELLO, 0x100)
+            .load(0xffff_ffff_ffff_f000, 0x8000, HELLO, 0x2000)
+            .build();
+
+        let result = Input::new(&buffer);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::SegmentOutOfRange {
+                index: 1,
+                memsz: 0x2000,
+            }))
+        );
     }
 
-    fn write_header(ctx: &Ctx, input: &mut [u8], offset: &mut usize, phnum: u16) {
-        input
-            .gwrite(
-                Header {
-                    e_type: header::ET_EXEC,
-                    e_phoff: Header::size(ctx) as u64,
-                    e_phnum: phnum,
-                    ..Header::new(*ctx)
+    #[test]
+    fn input_layout_with_from_input_start_and_overlapping_paddr_is_error() {
+        let buffer = create_input(&[create_load(0x1000, 0x4000), create_load(0x2000, 0x4080)]);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let result = input.layout(LayoutStrategy::FromInput);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(
+                ValidationError::OverlappingPhysicalAddresses { index: 0, other: 1 }
+            ))
+        );
+    }
+
+    #[test]
+    fn input_layout_with_identity_start_derives_paddr_from_vaddr() {
+        let buffer = create_input(&[create_load(0x10000, 0), create_load(0x10100, 0)]);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let layout = input
+            .layout(LayoutStrategy::Identity)
+            .expect("layout failed unexpectedly");
+
+        let loads = &layout.program_headers()[2..4];
+        assert!(loads.iter().all(|ph| ph.p_paddr == ph.p_vaddr));
+    }
+
+    #[test]
+    fn input_layout_with_virt_offset_start_derives_paddr_from_vaddr() {
+        let buffer = create_input(&[create_load(0xc001_0000, 0xc001_0000)]);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let layout = input
+            .layout(LayoutStrategy::VirtOffset(0xc000_0000))
+            .expect("layout failed unexpectedly");
+
+        assert_eq!(layout.program_headers()[2].p_paddr, 0x1_0000);
+    }
+
+    #[test]
+    fn input_layout_with_high_half_start_derives_paddr_from_vaddr() {
+        let buffer = create_input(&[create_load(0xc010_0000, 0)]);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let layout = input
+            .layout(LayoutStrategy::HighHalf { mask: 0xf000_0000 })
+            .expect("layout failed unexpectedly");
+
+        assert_eq!(layout.program_headers()[2].p_paddr, 0x0010_0000);
+    }
+
+    #[test]
+    fn input_layout_with_virt_offset_start_above_vaddr_is_error() {
+        let buffer = create_input(&[create_load(0x1000, 0x4000)]);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let result = input.layout(LayoutStrategy::VirtOffset(0x2000));
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::VaddrBelowOffset {
+                index: 0,
+                vaddr: 0x1000,
+            }))
+        );
+    }
+
+    #[test]
+    fn input_layout_with_from_input_start_and_first_segment_below_header_load_is_error() {
+        let buffer = create_input(&[ProgramHeader {
+            p_align: 0x20_0000,
+            ..create_load(0x40_0000, 0x1000)
+        }]);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let result = input.layout(LayoutStrategy::FromInput);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::FirstSegmentNearZero {
+                index: 0,
+                paddr: 0x1000,
+            }))
+        );
+    }
+
+    #[test]
+    fn input_layout_with_virt_offset_start_and_paddr_below_header_load_is_headers_collision() {
+        let buffer = create_input(&[ProgramHeader {
+            p_align: 0x20_0000,
+            ..create_load(0x40_0000, 0x40_0000)
+        }]);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let result = input.layout(LayoutStrategy::VirtOffset(0x3f_f000));
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::HeadersCollision {
+                index: 0,
+                paddr: 0x1000,
+            }))
+        );
+    }
+
+    #[test]
+    fn input_layout_with_identity_start_and_low_vaddr_is_headers_collision() {
+        let buffer = create_input(&[create_load(0x800, 0x4000)]);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let result = input.layout(LayoutStrategy::Identity);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::HeadersCollision {
+                index: 0,
+                paddr: 0x800,
+            }))
+        );
+    }
+
+    #[test]
+    fn new_input_with_overlapping_vaddr_is_error() {
+        let buffer = create_input(&[create_load(0x1000, 0x4000), create_load(0x1080, 0x5000)]);
+
+        let result = Input::new(&buffer);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(
+                ValidationError::OverlappingVirtualAddresses { index: 0, other: 1 }
+            ))
+        );
+    }
+
+    #[test]
+    fn new_input_with_entry_in_non_executable_segment_is_error() {
+        let buffer = create_input(&[ProgramHeader {
+            p_flags: program_header::PF_R | program_header::PF_W,
+            ..create_load(0x1000, 0x4000)
+        }]);
+
+        let result = Input::new(&buffer);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(
+                ValidationError::EntryNotInExecutableSegment { entry: 0x1000 }
+            ))
+        );
+    }
+
+    #[test]
+    fn new_input_with_adjacent_segments_is_ok() {
+        let buffer = create_input(&[create_load(0x1000, 0x4000), create_load(0x1100, 0x4100)]);
+
+        let result = Input::new(&buffer);
+
+        assert_matches!(result, Ok(_));
+    }
+
+    #[test]
+    fn input_layout_with_from_input_start_and_shared_permission_page_is_error() {
+        let buffer = create_mixed_permissions_input(0x100, 0x10100, 0x10);
+        let config = LayoutConfig::new().separate_permissions(true);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let result = input.layout_with(LayoutStrategy::FromInput, &config);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::SharedPermissionPage {
+                index: 1,
+                paddr: 0x10100,
+            }))
+        );
+    }
+
+    #[test]
+    fn input_layout_with_specified_start_and_separate_permissions_pads_output() {
+        let buffer = create_mixed_permissions_input(0x100, 0x11000, 0x10);
+        let config = LayoutConfig::new().separate_permissions(true);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let layout = input
+            .layout_with(LayoutStrategy::SpecifiedStart(0x10_0000), &config)
+            .expect("layout failed unexpectedly");
+
+        let data = &layout.program_headers()[3];
+        assert_eq!(data.p_flags, program_header::PF_R | program_header::PF_W);
+        assert_eq!(data.p_paddr % PAGE_SIZE as u64, 0);
+    }
+
+    #[test]
+    fn input_layout_with_separate_permissions_and_segments_on_separate_pages_is_ok() {
+        let buffer = create_mixed_permissions_input(0xf00, 0x11db8, 0x1000);
+        let config = LayoutConfig::new().separate_permissions(true);
+
+        let input = Input::new(&buffer).expect("Invalid ELF file passed to Input::new()");
+        let from_input = input.layout_with(LayoutStrategy::FromInput, &config);
+        let specified = input.layout_with(LayoutStrategy::SpecifiedStart(0x10_0000), &config);
+
+        assert_matches!(from_input, Ok(_));
+        assert_matches!(specified, Ok(ref layout) if layout.program_headers()[3].p_paddr % PAGE_SIZE as u64 == 0xdb8);
+    }
+
+    fn create_input(phdr: &[ProgramHeader]) -> Vec<u8> {
+        phdr.iter()
+            .fold(create_builder().entry(phdr[0].p_vaddr), |builder, ph| {
+                builder.segment(ph.clone(), &vec![0; ph.p_filesz as usize])
+            })
+            .build()
+    }
+
+    fn create_load(vaddr: u64, paddr: u64) -> ProgramHeader {
+        ProgramHeader {
+            p_type: program_header::PT_LOAD,
+            p_flags: program_header::PF_R | program_header::PF_X,
+            p_vaddr: vaddr,
+            p_paddr: paddr,
+            p_memsz: 0x100,
+            ..ProgramHeader::new()
+        }
+    }
+
+    fn create_sparse_input() -> Vec<u8> {
+        create_builder()
+            .load(0x1000, 0x4000, HELLO, 100)
+            .load(
+                0x1000 + 2 * PAGE_SIZE as u64,
+                0x4000 + 2 * PAGE_SIZE as u64,
+                HELLO,
+                100,
+            )
+            .build()
+    }
+
+    fn create_mixed_permissions_input(
+        text_memsz: u64,
+        data_vaddr: u64,
+        data_align: u64,
+    ) -> Vec<u8> {
+        create_builder()
+            .load(0x10000, 0x10000, HELLO, text_memsz)
+            .segment(
+                ProgramHeader {
+                    p_type: program_header::PT_LOAD,
+                    p_flags: program_header::PF_R | program_header::PF_W,
+                    p_vaddr: data_vaddr,
+                    p_paddr: data_vaddr,
+                    p_memsz: 0x100,
+                    p_align: data_align,
+                    ..ProgramHeader::new()
                 },
-                offset,
+                HELLO,
             )
-            .unwrap();
+            .build()
     }
 
+    fn create_builder() -> ElfBuilder {
+        use goblin::container::{Container, Endian};
+
+        ElfBuilder::new(Container::Little, Endian::Little)
+    }
 }
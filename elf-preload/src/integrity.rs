@@ -0,0 +1,158 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use crate::{Error, Result, ValidationError};
+use goblin::container::Ctx;
+use goblin::elf::{program_header, Header, ProgramHeader};
+use scroll::{Endian, Pread};
+use sha2::{Digest, Sha256};
+
+/// The name (including the terminating nul) of the segment digests note.
+pub(crate) const NOTE_NAME: &[u8; 12] = b"elf-preload\0";
+/// The type of the segment digests note.
+pub(crate) const NT_SEGMENT_DIGESTS: u32 = 1;
+
+// The note header (namesz, descsz, type) and name are followed by the
+// descriptor: a count of entries, a reserved word, and then the entries.
+pub(crate) const DESC_OFFSET: usize = 12 + NOTE_NAME.len();
+pub(crate) const DESC_HEADER_SIZE: usize = 8;
+// Each entry is paddr (u64), size (u64), crc32 (u32), reserved (u32), and
+// sha256 ([u8; 32]).
+pub(crate) const ENTRY_SIZE: usize = 56;
+
+const ZEROS: [u8; 4096] = [0; 4096];
+
+/// The size of the segment digests note for the given number of segments.
+pub(crate) fn note_size(segments: usize) -> u64 {
+    (DESC_OFFSET + DESC_HEADER_SIZE + segments * ENTRY_SIZE) as u64
+}
+
+/// Compute the CRC32 and SHA-256 digests of the given contents followed by
+/// `zeros` zero bytes.
+pub(crate) fn digest(contents: &[u8], zeros: usize) -> (u32, [u8; 32]) {
+    let mut crc32 = crc32fast::Hasher::new();
+    let mut sha256 = Sha256::new();
+
+    crc32.update(contents);
+    sha256.update(contents);
+    let mut remaining = zeros;
+    while remaining > 0 {
+        let len = remaining.min(ZEROS.len());
+        crc32.update(&ZEROS[..len]);
+        sha256.update(&ZEROS[..len]);
+        remaining -= len;
+    }
+
+    (crc32.finalize(), sha256.finalize().into())
+}
+
+/// Verify each of the loadable segments of a preloaded image against the
+/// digests in its segment digests note.
+///
+/// `image` is the preloaded image as it was loaded into memory (or the output
+/// file itself) starting from the ELF header at the physical address of the
+/// first loadable segment. The note is only present if the output was laid out
+/// with [`LayoutConfig::digests`][crate::LayoutConfig::digests]. The first
+/// loadable segment (the ELF header, program headers, and the note) is not
+/// covered by a digest.
+///
+/// Each digest covers the memory size of its segment, including the bss. If
+/// the output was laid out with
+/// [`LayoutConfig::elide_bss`][crate::LayoutConfig::elide_bss] then the output
+/// file itself does not verify: it must first be loaded into memory that
+/// extends to the end of the bss and the bss zeroed with
+/// [`zero_bss`][crate::zero_bss].
+///
+/// # Errors
+/// `verify_digests()` can return the following errors:
+/// * `Error::BadElf`: `image` does not start with an ELF header and program
+///   headers
+/// * `Error::InvalidElf`: `image` does not contain a segment digests note
+/// * `Error::DigestMismatch`: a segment does not match its digests (or does not
+///   fit within `image`)
+pub fn verify_digests(image: &[u8]) -> Result<()> {
+    let header: Header = image.pread(0)?;
+    let ctx = Ctx::new(header.container()?, header.endianness()?);
+    let phdrs = ProgramHeader::parse(image, header.e_phoff as usize, header.e_phnum as usize, ctx)?;
+
+    let base = phdrs
+        .iter()
+        .filter(|ph| ph.p_type == program_header::PT_LOAD)
+        .map(|ph| ph.p_paddr)
+        .min()
+        .unwrap_or(0);
+    let note = phdrs
+        .iter()
+        .filter(|ph| ph.p_type == program_header::PT_NOTE)
+        .filter_map(|ph| image.get(ph.file_range()))
+        .find(|note| is_digests_note(note, ctx.le))
+        .ok_or(ValidationError::NoDigestsNote)?;
+
+    let count: u32 = note.pread_with(DESC_OFFSET, ctx.le)?;
+    for index in 0..count as usize {
+        let offset = DESC_OFFSET + DESC_HEADER_SIZE + index * ENTRY_SIZE;
+        let paddr: u64 = note.pread_with(offset, ctx.le)?;
+        let size: u64 = note.pread_with(offset + 8, ctx.le)?;
+        let crc32: u32 = note.pread_with(offset + 16, ctx.le)?;
+        let sha256 = note
+            .get(offset + 24..offset + 24 + 32)
+            .ok_or(scroll::Error::BadOffset(offset))?;
+
+        let start = paddr.wrapping_sub(base) as usize;
+        let contents = image.get(start..start.saturating_add(size as usize));
+        match contents.map(|contents| digest(contents, 0)) {
+            Some((actual_crc32, actual_sha256))
+                if actual_crc32 == crc32 && actual_sha256[..] == *sha256 => {}
+            _ => return Err(Error::DigestMismatch { paddr }),
+        }
+    }
+
+    Ok(())
+}
+
+fn is_digests_note(note: &[u8], le: Endian) -> bool {
+    let namesz = note.pread_with::<u32>(0, le).ok();
+    let n_type = note.pread_with::<u32>(8, le).ok();
+    let name = note.get(12..DESC_OFFSET);
+
+    namesz == Some(NOTE_NAME.len() as u32)
+        && n_type == Some(NT_SEGMENT_DIGESTS)
+        && name == Some(&NOTE_NAME[..])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn digest_of_trailing_zeros_is_digest_of_zero_bytes() {
+        let mut contents = vec![0xdb; 0x10];
+        let expected_digest = digest(&contents, 0x2345);
+        contents.resize(0x10 + 0x2345, 0);
+
+        let result = digest(&contents, 0);
+
+        assert_eq!(result, expected_digest);
+    }
+
+    #[test]
+    fn digest_computes_known_crc32() {
+        let (crc32, _) = digest(b"123456789", 0);
+
+        assert_eq!(crc32, 0xcbf4_3926);
+    }
+
+    #[test]
+    fn verify_digests_on_bad_bytes_is_error() {
+        let image = [0xba, 0xdd, 0x00, 0xd8, 0xde, 0xad, 0xbe, 0xef];
+
+        let result = verify_digests(&image);
+
+        assert_matches!(result, Err(Error::BadElf(_)));
+    }
+}
@@ -7,11 +7,34 @@
 // except according to those terms
 
 use crate::{Arch, Error, OutputWriter, Result};
+use alloc::vec::Vec;
+use core::ops::Range;
 use goblin::elf::{header, program_header, Header, ProgramHeader};
 use scroll::Pwrite;
 
+mod binary;
+#[cfg(feature = "std")]
+mod boot;
+#[cfg(any(feature = "deflate", feature = "lz4"))]
+mod compress;
+mod config;
+mod debug;
+#[cfg(feature = "std")]
+mod efi;
+#[cfg(feature = "integrity")]
+mod integrity;
+#[cfg(feature = "std")]
+mod records;
 mod strategy;
-
+#[cfg(feature = "std")]
+mod stream;
+
+pub use binary::BinaryImage;
+#[cfg(feature = "std")]
+pub use boot::BootImage;
+pub use config::LayoutConfig;
+pub(crate) use debug::Sections;
+pub(crate) use strategy::within_load;
 pub use strategy::LayoutStrategy;
 
 /// The layout of the output file. Created by the [`layout`][Input::layout] method.
@@ -22,6 +45,7 @@ pub struct Layout<'a> {
     arch: Arch,
     entry: u64,
     input: &'a [u8],
+    sections: &'a Sections,
 }
 
 impl<'a> Layout<'a> {
@@ -29,20 +53,28 @@ impl<'a> Layout<'a> {
         arch: Arch,
         phdr: I,
         input: &'a [u8],
+        sections: &'a Sections,
         entry: u64,
         start: LayoutStrategy,
+        config: &LayoutConfig,
     ) -> Self
     where
-        I: ExactSizeIterator<Item = &'b ProgramHeader>,
+        I: Iterator<Item = &'b ProgramHeader>,
     {
-        let in_phdr: Vec<_> = phdr.cloned().collect();
-        let out_phdr = start.layout(in_phdr.iter(), arch.ctx());
+        let (in_phdr, preserved): (Vec<_>, Vec<_>) = phdr
+            .cloned()
+            .partition(|ph| ph.p_type == program_header::PT_LOAD);
+        let mut out_phdr = start.layout(in_phdr.iter(), &preserved, arch, config);
+        if config.elides_bss() {
+            LayoutStrategy::elide_trailing_bss(&mut out_phdr, &in_phdr);
+        }
 
         Layout {
             in_phdr,
             out_phdr,
             arch,
             input,
+            sections,
             entry,
         }
     }
@@ -51,6 +83,38 @@ impl<'a> Layout<'a> {
         LayoutStrategy::out_segments(&self.out_phdr)
     }
 
+    /// The first loadable segment from the input (if any) whose first page (in
+    /// either paddr or vaddr) holds the last byte of the previous loadable
+    /// segment of the output although their p_flags differ. This is the index
+    /// of the segment among the loadable segments from the input, and its paddr
+    /// in the output.
+    pub(crate) fn shared_permission_page(&self, page_size: u64) -> Option<(usize, u64)> {
+        (1..self.out_segments())
+            .map(|segment| {
+                (
+                    segment,
+                    self.memsz(segment - 1),
+                    &self.out_phdr[LayoutStrategy::out_index(segment - 1)],
+                    &self.out_phdr[LayoutStrategy::out_index(segment)],
+                )
+            })
+            .find(|(_, size, previous, ph)| {
+                ph.p_flags != previous.p_flags
+                    && (strategy::shares_page(previous.p_paddr, *size, ph.p_paddr, page_size)
+                        || strategy::shares_page(previous.p_vaddr, *size, ph.p_vaddr, page_size))
+            })
+            .map(|(segment, _, _, ph)| (LayoutStrategy::in_index(segment), ph.p_paddr))
+    }
+
+    // The memory size of a loadable segment without the padding that the
+    // output adds after it (the segment for the headers has no input).
+    fn memsz(&self, segment: usize) -> u64 {
+        match segment {
+            0 => self.out_phdr[LayoutStrategy::out_index(0)].p_memsz,
+            _ => self.in_phdr[LayoutStrategy::in_index(segment)].p_memsz,
+        }
+    }
+
     pub(crate) fn segment_size(&self, segment: usize) -> usize {
         let real_segment = LayoutStrategy::out_index(segment);
         self.out_phdr[real_segment].file_range().len()
@@ -69,6 +133,8 @@ impl<'a> Layout<'a> {
             for phdr in &self.out_phdr {
                 output.gwrite_with(phdr.clone(), &mut phoff, self.arch.ctx())?;
             }
+            #[cfg(feature = "integrity")]
+            self.write_digests_note(output)?;
         } else {
             let in_seg = LayoutStrategy::in_index(segment);
             let out_seg = LayoutStrategy::out_index(segment);
@@ -87,6 +153,30 @@ impl<'a> Layout<'a> {
         Ok(())
     }
 
+    /// The program headers of the output represented by this layout.
+    ///
+    /// The first program header is the PT_PHDR and the remaining ones are the
+    /// PT_LOAD program headers in the order they appear in the output, followed
+    /// by any program headers preserved from the input and the PT_NOTE for the
+    /// segment digests if the layout has one.
+    pub fn program_headers(&self) -> &[ProgramHeader] {
+        &self.out_phdr
+    }
+
+    /// The range of physical addresses occupied by the output represented by
+    /// this layout, including the bss of each segment.
+    pub fn paddr_range(&self) -> Range<u64> {
+        let loads = || {
+            self.out_phdr
+                .iter()
+                .filter(|ph| ph.p_type == program_header::PT_LOAD)
+        };
+        let start = loads().map(|ph| ph.p_paddr).min().unwrap_or(0);
+        let end = loads().map(|ph| ph.p_paddr + ph.p_memsz).max().unwrap_or(0);
+
+        start..end
+    }
+
     /// The required size of the output represented by this layout.
     pub fn required_size(&self) -> usize {
         self.out_phdr
@@ -105,10 +195,30 @@ impl<'a> Layout<'a> {
             Ok(OutputWriter::new(self, output))
         }
     }
+
+    /// Write the output to the given output bytes in parallel, using [rayon][rayon]
+    /// to drive repeated calls to [`OutputWriter::split`]. The output bytes must
+    /// be at least [`required_size`][Layout::required_size] in length.
+    ///
+    /// The result is identical to writing the whole output with a single
+    /// [`OutputWriter::write`].
+    ///
+    /// [rayon]: https://crates.io/crates/rayon
+    #[cfg(feature = "rayon")]
+    pub fn write_all(&'a self, output: &mut [u8]) -> Result<()> {
+        use rayon::iter::ParallelIterator;
+
+        let writer = self.output(output)?;
+        rayon::iter::split(writer, OutputWriter::split)
+            .map(|mut writer| writer.write())
+            .collect()
+    }
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
+    pub(crate) use super::debug::test::NO_SECTIONS;
+
     use super::*;
     use crate::arch::test::create_arch;
     use crate::PAGE_SIZE;
@@ -118,7 +228,72 @@ mod test {
         Elf,
     };
     use scroll::Pread;
-    use std::ops::Range;
+
+    /// Lays out a PT_LOAD of code followed by a PT_LOAD of data and bss. Each
+    /// takes 0x10 bytes of `input` and is aligned to `align`.
+    pub(crate) fn create_layout(
+        arch: Arch,
+        input: &[u8],
+        align: u64,
+        entry: u64,
+        start: u64,
+    ) -> Layout<'_> {
+        Layout::new(
+            arch,
+            create_loads(align).iter(),
+            input,
+            &NO_SECTIONS,
+            entry,
+            LayoutStrategy::SpecifiedStart(start),
+            &LayoutConfig::new(),
+        )
+    }
+
+    /// The input program headers of [`create_layout`].
+    pub(crate) fn create_loads(align: u64) -> [ProgramHeader; 2] {
+        [
+            ProgramHeader {
+                p_type: PT_LOAD,
+                p_flags: program_header::PF_R | program_header::PF_X,
+                p_offset: 0,
+                p_vaddr: 0x1000,
+                p_filesz: 0x10,
+                p_memsz: 0x10,
+                p_align: align,
+                ..ProgramHeader::new()
+            },
+            ProgramHeader {
+                p_type: PT_LOAD,
+                p_flags: program_header::PF_R | program_header::PF_W,
+                p_offset: 0x10,
+                p_vaddr: 0x2000,
+                p_filesz: 0x10,
+                p_memsz: 0x3000,
+                p_align: align,
+                ..ProgramHeader::new()
+            },
+        ]
+    }
+
+    /// A layout of already placed output program headers after a PT_PHDR.
+    pub(crate) fn create_placed_layout<'a>(
+        loads: Vec<ProgramHeader>,
+        input: &'a [u8],
+        sections: &'a Sections,
+        entry: u64,
+    ) -> Layout<'a> {
+        let mut out_phdr = vec![make_fake_pt_phdr()];
+        out_phdr.extend(loads);
+
+        Layout {
+            in_phdr: Vec::new(),
+            out_phdr,
+            arch: create_arch(Container::Little, Endian::Little),
+            input,
+            entry,
+            sections,
+        }
+    }
 
     #[test]
     fn specified_layout_required_size_is_header_and_loadables() {
@@ -133,13 +308,36 @@ mod test {
             ..ProgramHeader::new()
         }];
         let strategy = LayoutStrategy::SpecifiedStart(0);
+        let config = LayoutConfig::new();
 
-        let sut = Layout::new(arch, phdr.iter(), &[], 0, strategy);
+        let sut = Layout::new(arch, phdr.iter(), &[], &NO_SECTIONS, 0, strategy, &config);
         let size = sut.required_size();
 
         assert_eq!(size, PAGE_SIZE + (offset as usize) + (memsz as usize));
     }
 
+    #[test]
+    fn specified_layout_paddr_range_is_start_to_end_of_bss() {
+        let arch = create_arch(Container::Little, Endian::Little);
+        let memsz = 100;
+        let offset = 100;
+        let phdr = [ProgramHeader {
+            p_filesz: memsz / 2,
+            p_memsz: memsz,
+            p_align: PAGE_SIZE as u64,
+            p_vaddr: offset + 4 * PAGE_SIZE as u64,
+            p_offset: offset,
+            ..ProgramHeader::new()
+        }];
+        let strategy = LayoutStrategy::SpecifiedStart(0x8000);
+        let config = LayoutConfig::new();
+
+        let sut = Layout::new(arch, phdr.iter(), &[], &NO_SECTIONS, 0, strategy, &config);
+        let range = sut.paddr_range();
+
+        assert_eq!(range, 0x8000..0x8000 + PAGE_SIZE as u64 + offset + memsz);
+    }
+
     #[test]
     fn layout_segment_size_reads_n_plus_1_out_phdr_filesz() {
         let filesz = 500;
@@ -150,6 +348,7 @@ mod test {
             arch: create_arch(Container::Little, Endian::Little),
             input: &[],
             entry: 0,
+            sections: &NO_SECTIONS,
         };
         let result = sut.segment_size(0);
 
@@ -177,6 +376,7 @@ mod test {
             input: &input,
             arch: create_arch(Container::Little, Endian::Little),
             entry: 0,
+            sections: &NO_SECTIONS,
         };
         sut.write_segment(1, &mut output)
             .expect("write_segement failed unexpectedly");
@@ -211,6 +411,7 @@ mod test {
             input: &input,
             arch: create_arch(Container::Little, Endian::Little),
             entry: 0,
+            sections: &NO_SECTIONS,
         };
         sut.write_segment(1, &mut output)
             .expect("write_segement failed unexpectedly");
@@ -233,6 +434,7 @@ mod test {
             in_phdr: Vec::new(),
             input: &[],
             entry: 0,
+            sections: &NO_SECTIONS,
         };
         sut.write_segment(0, &mut output)
             .expect("write_segement failed unexpectedly");
@@ -257,6 +459,7 @@ mod test {
             in_phdr: Vec::new(),
             input: &[],
             entry,
+            sections: &NO_SECTIONS,
         };
         sut.write_segment(0, &mut output)
             .expect("write_segement failed unexpectedly");
@@ -278,6 +481,7 @@ mod test {
             in_phdr: Vec::new(),
             input: &[],
             entry: 0,
+            sections: &NO_SECTIONS,
         };
         sut.write_segment(0, &mut output)
             .expect("write_segement failed unexpectedly");
@@ -0,0 +1,279 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::{Layout, LayoutStrategy};
+use crate::{Error, OutputWriter, Result, ValidationError};
+use goblin::elf::ProgramHeader;
+use itertools::Itertools;
+
+/// The placement in physical memory of the flat binary output of a [`Layout`].
+/// Created by the [`binary_image`][Layout::binary_image] method.
+///
+/// The flat binary output is a headerless memory image (similar to the output
+/// of `objcopy -O binary`). It starts at the lowest physical address of the
+/// loadable segments, the gaps between the segments are zero filled, and the
+/// bss of each segment is included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BinaryImage {
+    load_address: u64,
+    entry_offset: u64,
+    size: usize,
+}
+
+impl BinaryImage {
+    /// The physical address at which to load the flat binary output.
+    pub fn load_address(&self) -> u64 {
+        self.load_address
+    }
+
+    /// The offset of the entry point from the start of the flat binary output.
+    pub fn entry_offset(&self) -> u64 {
+        self.entry_offset
+    }
+
+    /// The size of the flat binary output.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl<'a> Layout<'a> {
+    /// The placement in physical memory of the flat binary output represented
+    /// by this layout.
+    ///
+    /// # Errors
+    /// `binary_image()` can return the following errors:
+    /// * `Error::InvalidElf`: the layout has no loadable segments, has loadable
+    ///   segments that overlap in physical memory, or has an entry point that is
+    ///   not in a loadable segment
+    pub fn binary_image(&self) -> Result<BinaryImage> {
+        let loads = self.binary_segments();
+        verify_no_overlap(loads)?;
+
+        let load_address = loads
+            .first()
+            .map(|ph| ph.p_paddr)
+            .ok_or(ValidationError::NoLoadableSegments)?;
+        let entry_paddr = loads
+            .iter()
+            .find(|ph| self.entry >= ph.p_vaddr && self.entry - ph.p_vaddr < ph.p_memsz)
+            .map(|ph| ph.p_paddr + (self.entry - ph.p_vaddr))
+            .ok_or(ValidationError::EntryNotInLoadableSegment { entry: self.entry })?;
+
+        Ok(BinaryImage {
+            load_address,
+            entry_offset: entry_paddr - load_address,
+            size: self.binary_size(),
+        })
+    }
+
+    /// Prepare to write the flat binary output to the given output bytes, which
+    /// must be at least [`size`][BinaryImage::size] in length.
+    ///
+    /// # Errors
+    /// `binary_output()` can return the following errors:
+    /// * `Error::InvalidElf`: see [`binary_image`][Layout::binary_image]
+    /// * `Error::OutputTooSmall`: `output` is too small for the flat binary output
+    pub fn binary_output<'b>(&'a self, output: &'b mut [u8]) -> Result<OutputWriter<'a, 'b>> {
+        let image = self.binary_image()?;
+
+        if output.len() < image.size() {
+            Err(Error::OutputTooSmall)
+        } else {
+            Ok(OutputWriter::new_binary(self, output))
+        }
+    }
+
+    pub(crate) fn binary_segment_size(&self, segment: usize) -> usize {
+        let phdr = &self.out_phdr[LayoutStrategy::out_index(segment)];
+
+        if segment + 1 < self.out_segments() {
+            let next = &self.out_phdr[LayoutStrategy::out_index(segment + 1)];
+            (next.p_paddr - phdr.p_paddr) as usize
+        } else {
+            phdr.p_memsz as usize
+        }
+    }
+
+    pub(crate) fn write_binary_segment(&self, segment: usize, output: &mut [u8]) -> Result<()> {
+        let (contents, gap) = output.split_at_mut(self.segment_size(segment));
+
+        self.write_segment(segment, contents)?;
+        for elt in gap {
+            *elt = 0;
+        }
+
+        Ok(())
+    }
+
+    fn binary_size(&self) -> usize {
+        let loads = self.binary_segments();
+
+        match (loads.first(), loads.last()) {
+            (Some(first), Some(last)) => (last.p_paddr + last.p_memsz - first.p_paddr) as usize,
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn binary_segments(&self) -> &[ProgramHeader] {
+        let start = LayoutStrategy::out_index(1);
+        let end = LayoutStrategy::out_index(self.out_segments());
+
+        &self.out_phdr[start..end]
+    }
+}
+
+fn verify_no_overlap(loads: &[ProgramHeader]) -> Result<()> {
+    let overlap = loads
+        .iter()
+        .enumerate()
+        .tuple_windows::<(_, _)>()
+        .find(|((_, ph1), (_, ph2))| ph2.p_paddr < ph1.p_paddr + ph1.p_memsz);
+
+    match overlap {
+        Some(((_, ph1), (index, ph2))) => Err(ValidationError::OverlappingSegments {
+            index: LayoutStrategy::out_index(index + 1),
+            end: ph1.p_paddr + ph1.p_memsz,
+            paddr: ph2.p_paddr,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::layout::test::{create_placed_layout, NO_SECTIONS};
+    use crate::Error;
+
+    #[test]
+    fn binary_image_load_address_is_lowest_segment_paddr() {
+        let sut = make_layout(
+            vec![make_load(0x8000, 0x100), make_load(0x9000, 0x100)],
+            0x8000,
+        );
+
+        let image = sut
+            .binary_image()
+            .expect("binary_image failed unexpectedly");
+
+        assert_eq!(image.load_address(), 0x8000);
+    }
+
+    #[test]
+    fn binary_image_entry_offset_is_from_load_address() {
+        let sut = make_layout(
+            vec![make_load(0x8000, 0x100), make_load(0x9000, 0x100)],
+            0x9010,
+        );
+
+        let image = sut
+            .binary_image()
+            .expect("binary_image failed unexpectedly");
+
+        assert_eq!(image.entry_offset(), 0x1010);
+    }
+
+    #[test]
+    fn binary_image_size_includes_gaps_and_bss() {
+        let sut = make_layout(
+            vec![make_load(0x8000, 0x100), make_load(0x9000, 0x100)],
+            0x8000,
+        );
+
+        let image = sut
+            .binary_image()
+            .expect("binary_image failed unexpectedly");
+
+        assert_eq!(image.size(), 0x1100);
+    }
+
+    #[test]
+    fn binary_image_with_overlapping_segments_is_error() {
+        let sut = make_layout(
+            vec![make_load(0x8000, 0x100), make_load(0x8080, 0x100)],
+            0x8000,
+        );
+
+        let result = sut.binary_image();
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::OverlappingSegments {
+                index: 3,
+                end: 0x8100,
+                paddr: 0x8080
+            }))
+        );
+    }
+
+    #[test]
+    fn binary_image_with_entry_outside_segments_is_error() {
+        let sut = make_layout(vec![make_load(0x8000, 0x100)], 0x9000);
+
+        let result = sut.binary_image();
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(
+                ValidationError::EntryNotInLoadableSegment { entry: 0x9000 }
+            ))
+        );
+    }
+
+    #[test]
+    fn layout_write_binary_segment_zero_fills_gap() {
+        let in_char = 0xdb;
+        let in_phdr = vec![make_in_load(0..0x10), make_in_load(0x10..0x20)];
+        let input = vec![in_char; 0x20];
+        let mut output = vec![0xc0; 0x1000];
+
+        let mut sut = make_layout(
+            vec![make_load(0x8000, 0x10), make_load(0x9000, 0x10)],
+            0x8000,
+        );
+        sut.in_phdr = in_phdr;
+        sut.input = &input;
+        sut.write_binary_segment(1, &mut output)
+            .expect("write_binary_segment failed unexpectedly");
+
+        for out_char in &output[0..0x10] {
+            assert_eq!(*out_char, in_char);
+        }
+        for out_char in &output[0x10..0x1000] {
+            assert_eq!(*out_char, 0);
+        }
+    }
+
+    fn make_layout(loads: Vec<ProgramHeader>, entry: u64) -> Layout<'static> {
+        let mut out_load = vec![make_load(0, 0x40)];
+        out_load.extend(loads);
+
+        create_placed_layout(out_load, &[], &NO_SECTIONS, entry)
+    }
+
+    fn make_load(paddr: u64, size: u64) -> ProgramHeader {
+        ProgramHeader {
+            p_paddr: paddr,
+            p_vaddr: paddr,
+            p_filesz: size,
+            p_memsz: size,
+            ..ProgramHeader::new()
+        }
+    }
+
+    fn make_in_load(file_range: std::ops::Range<u64>) -> ProgramHeader {
+        ProgramHeader {
+            p_offset: file_range.start,
+            p_filesz: file_range.end - file_range.start,
+            p_memsz: file_range.end - file_range.start,
+            ..ProgramHeader::new()
+        }
+    }
+}
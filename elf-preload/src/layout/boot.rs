@@ -0,0 +1,329 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::Layout;
+use crate::{BinaryImage, Error, Result, ValidationError};
+use goblin::elf::header;
+use scroll::{Endian, Pwrite, LE};
+use std::io::{Seek, Write};
+
+const ARM64_HEADER_SIZE: u64 = 64;
+const ARM64_MAGIC: u32 = 0x644d_5241; // "ARM\x64"
+const ARM64_TEXT_ALIGN: u64 = 0x20_0000;
+const ARM64_FLAG_BE: u64 = 1;
+
+const ZIMAGE_HEADER_SIZE: u64 = 0x40;
+const ZIMAGE_MAGIC: u32 = 0x016f_2818;
+const ZIMAGE_ENDIAN: u32 = 0x0403_0201;
+const ARM_NOP: u32 = 0xe1a0_0000; // mov r0, r0
+
+/// The Linux boot image header with which to wrap the flat binary output of a
+/// [`Layout`] (see [`write_boot_image_to`][Layout::write_boot_image_to]).
+///
+/// These headers let U-Boot boot the output directly with `booti` or `bootz`
+/// (e.g. on boards whose U-Boot lacks FIT support).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BootImage {
+    /// The arm64 `Image` header for U-Boot's `booti`.
+    ///
+    /// `booti` places the image at `text_offset` from a 2 MiB aligned address
+    /// of its choosing, so the output should either be position independent
+    /// or laid out at the address to which `booti` will move it.
+    Arm64,
+
+    /// The arm32 `zImage` header for U-Boot's `bootz`.
+    ///
+    /// The header records the physical address of the image so `bootz` moves
+    /// it to that address. The entry point must be an ARM (not Thumb) entry
+    /// point.
+    Arm32,
+}
+
+impl BootImage {
+    fn machine(self) -> u16 {
+        match self {
+            BootImage::Arm64 => header::EM_AARCH64,
+            BootImage::Arm32 => header::EM_ARM,
+        }
+    }
+
+    fn header_size(self) -> u64 {
+        match self {
+            BootImage::Arm64 => ARM64_HEADER_SIZE,
+            BootImage::Arm32 => ZIMAGE_HEADER_SIZE,
+        }
+    }
+}
+
+impl<'a> Layout<'a> {
+    /// Write the flat binary output represented by this layout to `sink`
+    /// (starting at the current position of `sink`) wrapped in the given Linux
+    /// boot image header.
+    ///
+    /// The header immediately precedes the flat binary output in memory, so
+    /// the boot image is to be loaded at the
+    /// [`load_address`][BinaryImage::load_address] of the flat binary output
+    /// less the size of the header (64 bytes for either header). The header
+    /// starts with a branch to the entry point.
+    ///
+    /// # Errors
+    /// `write_boot_image_to()` can return the following errors:
+    /// * `Error::InvalidElf`: see [`binary_image`][Layout::binary_image], the
+    ///   machine of the input does not match `kind`, there is no room for the
+    ///   header below the flat binary output, or the entry point of an `Arm32`
+    ///   image is a Thumb entry point
+    /// * `Error::AddressOutOfRange`: the entry point is too far from the header
+    ///   for its branch instruction to reach
+    /// * `Error::Io`: writing to or seeking `sink` failed
+    pub fn write_boot_image_to<W: Write + Seek>(
+        &self,
+        kind: BootImage,
+        sink: &mut W,
+    ) -> Result<()> {
+        if self.arch.machine() != kind.machine() {
+            return Err(ValidationError::BootImageMachine {
+                machine: self.arch.machine(),
+            }
+            .into());
+        }
+
+        let image = self.binary_image()?;
+        let start = image.load_address().checked_sub(kind.header_size()).ok_or(
+            ValidationError::BootImageNoRoom {
+                load_address: image.load_address(),
+            },
+        )?;
+
+        let header = match kind {
+            BootImage::Arm64 => self.arm64_header(start, &image)?,
+            BootImage::Arm32 => self.zimage_header(start, &image)?,
+        };
+        sink.write_all(&header)?;
+        self.write_binary_to(sink)
+    }
+
+    // #SPC-elfpreload.bootimage
+    fn arm64_header(&self, start: u64, image: &BinaryImage) -> Result<Vec<u8>> {
+        let mut header = vec![0; ARM64_HEADER_SIZE as usize];
+        let flags = match self.arch.ctx().le {
+            Endian::Little => 0,
+            Endian::Big => ARM64_FLAG_BE,
+        };
+
+        let branch = arm64_branch(ARM64_HEADER_SIZE + image.entry_offset()).ok_or(
+            Error::AddressOutOfRange {
+                address: self.entry,
+            },
+        )?;
+
+        header.pwrite_with(branch, 0, LE)?;
+        header.pwrite_with(start % ARM64_TEXT_ALIGN, 8, LE)?;
+        // the effective size includes the bss (which may be elided)
+        header.pwrite_with(self.paddr_range().end - start, 16, LE)?;
+        header.pwrite_with(flags, 24, LE)?;
+        header.pwrite_with(ARM64_MAGIC, 56, LE)?;
+
+        Ok(header)
+    }
+
+    // #SPC-elfpreload.bootimage
+    fn zimage_header(&self, start: u64, image: &BinaryImage) -> Result<Vec<u8>> {
+        let mut header = vec![0; ZIMAGE_HEADER_SIZE as usize];
+        let le = self.arch.ctx().le;
+        let entry = ZIMAGE_HEADER_SIZE + image.entry_offset();
+        if entry & 1 != 0 {
+            return Err(ValidationError::ThumbEntry { entry: self.entry }.into());
+        }
+        let branch = arm_branch(0x20, entry).ok_or(Error::AddressOutOfRange {
+            address: self.entry,
+        })?;
+        let end = start + ZIMAGE_HEADER_SIZE + image.size() as u64;
+
+        for offset in (0..0x20).step_by(4) {
+            header.pwrite_with(ARM_NOP, offset, le)?;
+        }
+        header.pwrite_with(branch, 0x20, le)?;
+        header.pwrite_with(ZIMAGE_MAGIC, 0x24, le)?;
+        header.pwrite_with(start as u32, 0x28, le)?;
+        header.pwrite_with(end as u32, 0x2c, le)?;
+        header.pwrite_with(ZIMAGE_ENDIAN, 0x30, le)?;
+
+        Ok(header)
+    }
+}
+
+// An aarch64 "b" from offset 0 to the given offset, or None if the target is
+// not a word offset within the 26 bit (word) range of the branch.
+fn arm64_branch(target: u64) -> Option<u32> {
+    match target {
+        target if target % 4 != 0 || target / 4 > 0x01ff_ffff => None,
+        target => Some(0x1400_0000 | (target / 4) as u32),
+    }
+}
+
+// An arm "b" from the given offset to the given target offset, or None if the
+// target is not a word offset within the 24 bit (word) range of the branch.
+fn arm_branch(offset: u64, target: u64) -> Option<u32> {
+    match target.checked_sub(offset + 8) {
+        Some(delta) if delta % 4 == 0 && delta / 4 <= 0x007f_ffff => {
+            Some(0xea00_0000 | (delta / 4) as u32)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::test::create_machine_arch;
+    use crate::layout::test::{create_layout, create_loads, NO_SECTIONS};
+    use crate::{LayoutConfig, LayoutStrategy};
+    use goblin::container::{Container, Endian};
+    use scroll::Pread;
+    use std::io::Cursor;
+
+    #[test]
+    fn layout_arm64_boot_image_has_image_header() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, header::EM_AARCH64, Container::Big);
+        let image = sut.binary_image().unwrap();
+
+        let output = write_boot_image(&sut, BootImage::Arm64).unwrap();
+
+        let start = image.load_address() - 64;
+        assert_eq!(output.pread_with::<u32>(56, LE).unwrap(), ARM64_MAGIC);
+        assert_eq!(output.pread_with::<u64>(8, LE).unwrap(), start % 0x20_0000);
+        assert_eq!(
+            output.pread_with::<u64>(16, LE).unwrap(),
+            output.len() as u64
+        );
+        assert_eq!(
+            output.pread_with::<u32>(0, LE).unwrap(),
+            arm64_branch(64 + image.entry_offset()).unwrap()
+        );
+    }
+
+    #[test]
+    fn layout_arm64_boot_image_size_with_elided_bss_includes_bss() {
+        let input = [0xdb; 0x20];
+        let sut = Layout::new(
+            create_machine_arch(header::EM_AARCH64, Container::Big, Endian::Little),
+            create_loads(0x10).iter(),
+            &input,
+            &NO_SECTIONS,
+            0x1004,
+            LayoutStrategy::SpecifiedStart(0x8000_0000),
+            &LayoutConfig::new().elide_bss(true),
+        );
+        let image = sut.binary_image().unwrap();
+
+        let output = write_boot_image(&sut, BootImage::Arm64).unwrap();
+
+        let start = image.load_address() - 64;
+        let image_size = output.pread_with::<u64>(16, LE).unwrap();
+        assert_eq!(image_size, sut.paddr_range().end - start);
+    }
+
+    #[test]
+    fn layout_arm32_boot_image_has_zimage_header() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, header::EM_ARM, Container::Little);
+        let image = sut.binary_image().unwrap();
+
+        let output = write_boot_image(&sut, BootImage::Arm32).unwrap();
+
+        let start = image.load_address() - 0x40;
+        assert_eq!(output.pread_with::<u32>(0x24, LE).unwrap(), ZIMAGE_MAGIC);
+        assert_eq!(output.pread_with::<u32>(0x28, LE).unwrap(), start as u32);
+        assert_eq!(
+            output.pread_with::<u32>(0x2c, LE).unwrap(),
+            (start + output.len() as u64) as u32
+        );
+        assert_eq!(output.pread_with::<u32>(0x30, LE).unwrap(), ZIMAGE_ENDIAN);
+    }
+
+    #[test]
+    fn layout_boot_image_is_header_and_binary_output() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, header::EM_ARM, Container::Little);
+        let mut expected = vec![0; sut.binary_image().unwrap().size()];
+        sut.binary_output(&mut expected).unwrap().write().unwrap();
+
+        let output = write_boot_image(&sut, BootImage::Arm32).unwrap();
+
+        assert_eq!(&output[0x40..], &expected[..]);
+    }
+
+    #[test]
+    fn layout_boot_image_for_other_machine_is_error() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, header::EM_ARM, Container::Little);
+
+        let result = write_boot_image(&sut, BootImage::Arm64);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::BootImageMachine {
+                machine: header::EM_ARM
+            }))
+        );
+    }
+
+    #[test]
+    fn layout_arm32_boot_image_with_thumb_entry_is_error() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout_with_entry(&input, header::EM_ARM, Container::Little, 0x1005);
+
+        let result = write_boot_image(&sut, BootImage::Arm32);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::ThumbEntry {
+                entry: 0x1005
+            }))
+        );
+    }
+
+    #[test]
+    fn arm_branch_encodes_known_instruction() {
+        // b 0x40 at 0x20
+        assert_eq!(arm_branch(0x20, 0x40), Some(0xea00_0006));
+        // b 0x44 at 0
+        assert_eq!(arm64_branch(0x44), Some(0x1400_0011));
+    }
+
+    #[test]
+    fn arm_branch_out_of_range_is_none() {
+        assert_eq!(arm_branch(0x20, 0x28 + 0x200_0000), None);
+        assert_eq!(arm_branch(0x20, 0x28 + 0x1ff_fffc), Some(0xea7f_ffff));
+        assert_eq!(arm64_branch(0x800_0000), None);
+        assert_eq!(arm64_branch(0x7ff_fffc), Some(0x15ff_ffff));
+    }
+
+    fn write_boot_image(layout: &Layout, kind: BootImage) -> Result<Vec<u8>> {
+        let mut sink = Cursor::new(Vec::new());
+        layout.write_boot_image_to(kind, &mut sink)?;
+
+        Ok(sink.into_inner())
+    }
+
+    fn make_layout(input: &[u8], machine: u16, container: Container) -> Layout<'_> {
+        make_layout_with_entry(input, machine, container, 0x1004)
+    }
+
+    fn make_layout_with_entry(
+        input: &[u8],
+        machine: u16,
+        container: Container,
+        entry: u64,
+    ) -> Layout<'_> {
+        let arch = create_machine_arch(machine, container, Endian::Little);
+
+        create_layout(arch, input, 0x10, entry, 0x8000_0000)
+    }
+}
@@ -0,0 +1,66 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::{Layout, LayoutStrategy};
+use crate::compress::{Compression, ENTRY_SIZE, HEADER_SIZE, MAGIC};
+use crate::Result;
+use alloc::vec;
+use alloc::vec::Vec;
+use scroll::{Pwrite, LE};
+
+impl<'a> Layout<'a> {
+    /// Create a compressed output that can be placed in memory with [`unpack`].
+    ///
+    /// The compressed output is a container that starts with a 24 byte header
+    /// followed by a 24 byte table entry for each loadable segment of the
+    /// output and then the compressed segments themselves. All of the fields are
+    /// little endian.
+    ///
+    /// The header is:
+    /// * the magic bytes `EPCZ`
+    /// * the compression method as a `u32` (1 for deflate, 2 for LZ4)
+    /// * the number of segments as a `u32`
+    /// * a reserved `u32`
+    /// * the entry point as a `u64`
+    ///
+    /// Each table entry is the physical address, the compressed length and the
+    /// decompressed length of a segment as `u64`'s. Once decompressed, the
+    /// segments are identical to the corresponding segments of the ELF output.
+    pub fn compressed_output(&self, method: Compression) -> Result<Vec<u8>> {
+        let count = self.out_segments();
+        let mut segments = Vec::with_capacity(count);
+        for segment in 0..count {
+            let mut contents = vec![0; self.segment_size(segment)];
+            self.write_segment(segment, &mut contents)?;
+            segments.push((
+                self.out_phdr[LayoutStrategy::out_index(segment)].p_paddr,
+                contents.len(),
+                method.compress(&contents),
+            ));
+        }
+
+        let data_size: usize = segments.iter().map(|(_, _, data)| data.len()).sum();
+        let mut output = vec![0; HEADER_SIZE + count * ENTRY_SIZE + data_size];
+        let mut offset = 0;
+        output.gwrite(&MAGIC[..], &mut offset)?;
+        output.gwrite_with(method.method(), &mut offset, LE)?;
+        output.gwrite_with(count as u32, &mut offset, LE)?;
+        output.gwrite_with(0u32, &mut offset, LE)?;
+        output.gwrite_with(self.entry, &mut offset, LE)?;
+        for (paddr, size, data) in &segments {
+            output.gwrite_with(*paddr, &mut offset, LE)?;
+            output.gwrite_with(data.len() as u64, &mut offset, LE)?;
+            output.gwrite_with(*size as u64, &mut offset, LE)?;
+        }
+        for (_, _, data) in &segments {
+            output.gwrite(&data[..], &mut offset)?;
+        }
+
+        Ok(output)
+    }
+}
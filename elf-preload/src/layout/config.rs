@@ -0,0 +1,153 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use crate::Arch;
+use alloc::vec::Vec;
+
+/// Options for laying out the output file. Used by the
+/// [`layout_with`][crate::Input::layout_with] method.
+///
+/// The default options give the same output as [`layout`][crate::Input::layout].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LayoutConfig {
+    page_size: Option<u64>,
+    align: Option<u64>,
+    preserve: Vec<u32>,
+    elide_bss: bool,
+    separate_permissions: bool,
+    #[cfg(feature = "integrity")]
+    digests: bool,
+}
+
+impl LayoutConfig {
+    /// Create a new `LayoutConfig` with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the given page size instead of the default page size for the
+    /// architecture of the input (e.g. 0x4000 for a 16K granule on aarch64).
+    ///
+    /// The page size is the alignment of the PT_PHDR program header, and the
+    /// largest gap between the segments of an input that is laid out with
+    /// [`FromInput`][crate::LayoutStrategy::FromInput]. A page size of zero
+    /// selects the default page size; any other page size must be a power of
+    /// two.
+    pub fn page_size(mut self, page_size: u64) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Align the first loadable segment to the given alignment instead of to
+    /// the page size (e.g. 2 MiB so that a loader can map the output with block
+    /// mappings).
+    ///
+    /// This is also the minimum alignment of the starting physical address of
+    /// an output that is laid out with [`EndAt`][crate::LayoutStrategy::EndAt].
+    /// The output alignment is never less than the page size and must be a
+    /// power of two.
+    pub fn align(mut self, align: u64) -> Self {
+        self.align = Some(align);
+        self
+    }
+
+    /// Carry the program headers of the given type (e.g. PT_NOTE, PT_TLS, or
+    /// PT_GNU_STACK) from the input into the output with their offsets and
+    /// physical addresses rewritten to match the output.
+    ///
+    /// Each of these program headers must either have no contents (like
+    /// PT_GNU_STACK) or be within a loadable segment of the input. PT_LOAD and
+    /// PT_PHDR program headers are always laid out anew and are never carried
+    /// from the input.
+    pub fn preserve(mut self, p_type: u32) -> Self {
+        if !self.preserve.contains(&p_type) {
+            self.preserve.push(p_type);
+        }
+        self
+    }
+
+    /// Omit the trailing bss of the last loadable segments from the output file
+    /// (e.g. to keep a kernel with a large bss small).
+    ///
+    /// The PT_LOAD program headers for these segments have a file size that is
+    /// smaller than their memory size, so the output can no longer be loaded
+    /// by copying it alone. After copying it the loader must zero the rest of
+    /// each segment, which [`zero_bss`][crate::zero_bss] does.
+    pub fn elide_bss(mut self, elide_bss: bool) -> Self {
+        self.elide_bss = elide_bss;
+        self
+    }
+
+    /// Keep loadable segments with different permissions (p_flags) out of each
+    /// other's pages (e.g. for seL4, which maps the rootserver with page
+    /// granularity, so that no page is both writable and executable).
+    ///
+    /// With [`SpecifiedStart`][crate::LayoutStrategy::SpecifiedStart] (or
+    /// [`EndAt`][crate::LayoutStrategy::EndAt]) the output is padded so that
+    /// such a segment starts after the page that holds the end of the previous
+    /// segment. The other strategies keep the physical addresses of the input
+    /// so they cannot pad the output; with them (or if the virtual address of
+    /// such a segment is on the page that holds the end of the previous
+    /// segment) the layout is an error instead.
+    pub fn separate_permissions(mut self, separate_permissions: bool) -> Self {
+        self.separate_permissions = separate_permissions;
+        self
+    }
+
+    /// Embed a CRC32 and a SHA-256 digest of each loadable segment in a PT_NOTE
+    /// within the first loadable segment (see
+    /// [`verify_digests`][crate::verify_digests]).
+    #[cfg(feature = "integrity")]
+    pub fn digests(mut self, digests: bool) -> Self {
+        self.digests = digests;
+        self
+    }
+
+    /// The page size for the given architecture.
+    pub(crate) fn resolve_page_size(&self, arch: Arch) -> u64 {
+        match self.page_size {
+            Some(page_size) if page_size > 0 => page_size,
+            _ => arch.page_size(),
+        }
+    }
+
+    /// The output alignment for the given architecture.
+    pub(crate) fn resolve_align(&self, arch: Arch) -> u64 {
+        self.align.unwrap_or(0).max(self.resolve_page_size(arch))
+    }
+
+    /// Whether program headers of the given type are carried into the output.
+    pub(crate) fn preserves(&self, p_type: u32) -> bool {
+        self.preserve.contains(&p_type)
+    }
+
+    /// Whether the trailing bss of the last loadable segments is omitted.
+    pub(crate) fn elides_bss(&self) -> bool {
+        self.elide_bss
+    }
+
+    /// Whether loadable segments with different permissions are kept out of
+    /// each other's pages.
+    pub(crate) fn separates_permissions(&self) -> bool {
+        self.separate_permissions
+    }
+
+    /// The size of the note to reserve in the first loadable segment for an
+    /// output with the given number of loadable segments from the input.
+    #[allow(unused_variables)]
+    pub(super) fn note_size(&self, segments: usize) -> Option<u64> {
+        #[cfg(feature = "integrity")]
+        {
+            if self.digests {
+                return Some(crate::integrity::note_size(segments));
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::Layout;
+use crate::Result;
+use alloc::vec;
+use alloc::vec::Vec;
+use goblin::elf::section_header::{self, SectionHeader};
+use goblin::elf::{header, Elf, Header, ProgramHeader, Sym};
+use scroll::{Pread, Pwrite};
+
+/// The sections of an input ELF file. These are only needed to write the debug
+/// ELF file for an output.
+#[derive(Debug, Default)]
+pub(crate) struct Sections {
+    shdr: Vec<SectionHeader>,
+    shstrndx: u16,
+    flags: u32,
+    base: u64,
+}
+
+impl Sections {
+    /// The sections of `elf` which has been relocated to `base`.
+    pub(crate) fn new(elf: &Elf, base: u64) -> Self {
+        Sections {
+            shdr: elf.section_headers.clone(),
+            shstrndx: elf.header.e_shstrndx,
+            flags: elf.header.e_flags,
+            base,
+        }
+    }
+}
+
+impl<'a> Layout<'a> {
+    /// Create a debug ELF file that corresponds to the output represented by
+    /// this layout.
+    ///
+    /// The debug ELF file is intended to be loaded into a debugger (e.g. with
+    /// `add-symbol-file` in GDB) alongside the output. It has the program headers
+    /// of the output (without any segment contents) and the section headers of
+    /// the input. The contents of the non-allocated sections of the input (which
+    /// include `.symtab`, `.strtab` and the DWARF `.debug_*` sections) are kept
+    /// but the allocated sections are converted to `SHT_NOBITS` sections.
+    ///
+    /// If the input was relocated with [`with_base`][crate::Input::with_base]
+    /// then the addresses of the allocated sections and the values of the
+    /// symbols in `.symtab` are offset by the base address. The DWARF sections
+    /// are not changed.
+    ///
+    /// # Errors
+    /// `debug_output()` can return the following errors:
+    /// * `Error::BadElf`: a section or the symbol table of the input extends past
+    ///   the end of the input
+    pub fn debug_output(&self) -> Result<Vec<u8>> {
+        let ctx = self.arch.ctx();
+        let phoff = Header::size(&ctx);
+        let mut offset = phoff + self.out_phdr.len() * ProgramHeader::size(&ctx);
+
+        let mut shdrs = self.sections.shdr.clone();
+        for shdr in shdrs.iter_mut().skip(1) {
+            if shdr.is_alloc() {
+                shdr.sh_addr += self.sections.base;
+                shdr.sh_type = section_header::SHT_NOBITS;
+            }
+            if shdr.sh_type == section_header::SHT_NOBITS {
+                shdr.sh_offset = offset as u64;
+            } else {
+                offset = align_up(offset, shdr.sh_addralign as usize);
+                shdr.sh_offset = offset as u64;
+                offset += shdr.sh_size as usize;
+            }
+        }
+        let shoff = align_up(offset, 8);
+        let mut output = vec![0; shoff + shdrs.len() * SectionHeader::size(&ctx)];
+
+        let mut header: Header = self.arch.into();
+        header.e_type = header::ET_EXEC;
+        header.e_entry = self.entry;
+        header.e_flags = self.sections.flags;
+        header.e_phoff = phoff as u64;
+        header.e_phnum = self.out_phdr.len() as u16;
+        header.e_shoff = if shdrs.is_empty() { 0 } else { shoff as u64 };
+        header.e_shentsize = SectionHeader::size(&ctx) as u16;
+        header.e_shnum = shdrs.len() as u16;
+        header.e_shstrndx = self.sections.shstrndx;
+        output.pwrite(header, 0)?;
+
+        let mut phoff = phoff;
+        for phdr in &self.out_phdr {
+            let phdr = ProgramHeader {
+                p_offset: 0,
+                p_filesz: 0,
+                ..phdr.clone()
+            };
+            output.gwrite_with(phdr, &mut phoff, ctx)?;
+        }
+
+        for (in_shdr, out_shdr) in self.sections.shdr.iter().zip(&shdrs).skip(1) {
+            if out_shdr.sh_type != section_header::SHT_NOBITS {
+                let contents: &[u8] = self
+                    .input
+                    .pread_with(in_shdr.sh_offset as usize, in_shdr.sh_size as usize)?;
+                let start = out_shdr.sh_offset as usize;
+                output[start..start + contents.len()].copy_from_slice(contents);
+            }
+            if out_shdr.sh_type == section_header::SHT_SYMTAB && self.sections.base != 0 {
+                self.rebase_symbols(&mut output, out_shdr, &shdrs)?;
+            }
+        }
+
+        let mut shoff = shoff;
+        for shdr in shdrs {
+            output.gwrite_with(shdr, &mut shoff, ctx)?;
+        }
+
+        Ok(output)
+    }
+
+    fn rebase_symbols(
+        &self,
+        output: &mut [u8],
+        symtab: &SectionHeader,
+        shdrs: &[SectionHeader],
+    ) -> Result<()> {
+        let ctx = self.arch.ctx();
+        let count = symtab.sh_size as usize / Sym::size(ctx.container);
+
+        for index in 0..count {
+            let offset = symtab.sh_offset as usize + index * Sym::size(ctx.container);
+            let mut sym: Sym = output.pread_with(offset, ctx)?;
+            let in_alloc_section = match shdrs.get(sym.st_shndx) {
+                Some(shdr) => sym.st_shndx != 0 && shdr.is_alloc(),
+                None => false,
+            };
+            if in_alloc_section {
+                sym.st_value += self.sections.base;
+                output.pwrite_with(sym, offset, ctx)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// ELF section alignments are powers of two.
+fn align_up(offset: usize, align: usize) -> usize {
+    if align <= 1 {
+        offset
+    } else {
+        (offset + align - 1) & !(align - 1)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::layout::test::create_placed_layout;
+
+    pub(crate) static NO_SECTIONS: Sections = Sections {
+        shdr: Vec::new(),
+        shstrndx: 0,
+        flags: 0,
+        base: 0,
+    };
+
+    #[test]
+    fn debug_output_has_output_program_headers_without_contents() {
+        let sut = make_layout(&[], &NO_SECTIONS);
+
+        let output = sut
+            .debug_output()
+            .expect("debug_output failed unexpectedly");
+
+        let elf = Elf::parse(&output).expect("Invalid Elf file");
+        assert_eq!(elf.program_headers.len(), 2);
+        assert_eq!(elf.program_headers[1].p_paddr, 0x8000);
+        assert_eq!(elf.program_headers[1].p_filesz, 0);
+    }
+
+    #[test]
+    fn debug_output_keeps_non_alloc_section_contents() {
+        let input = vec![0xdb; 0x100];
+        let sections = make_sections(0);
+        let sut = make_layout(&input, &sections);
+
+        let output = sut
+            .debug_output()
+            .expect("debug_output failed unexpectedly");
+
+        let elf = Elf::parse(&output).expect("Invalid Elf file");
+        let debug = &elf.section_headers[2];
+        assert_eq!(debug.sh_type, section_header::SHT_PROGBITS);
+        let start = debug.sh_offset as usize;
+        for out_char in &output[start..start + 0x20] {
+            assert_eq!(*out_char, 0xdb);
+        }
+    }
+
+    #[test]
+    fn debug_output_converts_alloc_sections_to_nobits() {
+        let input = vec![0xdb; 0x100];
+        let sections = make_sections(0);
+        let sut = make_layout(&input, &sections);
+
+        let output = sut
+            .debug_output()
+            .expect("debug_output failed unexpectedly");
+
+        let elf = Elf::parse(&output).expect("Invalid Elf file");
+        let text = &elf.section_headers[1];
+        assert_eq!(text.sh_type, section_header::SHT_NOBITS);
+        assert_eq!(text.sh_size, 0x40);
+    }
+
+    #[test]
+    fn debug_output_offsets_alloc_sections_by_base() {
+        let input = vec![0xdb; 0x100];
+        let sections = make_sections(0x10000);
+        let sut = make_layout(&input, &sections);
+
+        let output = sut
+            .debug_output()
+            .expect("debug_output failed unexpectedly");
+
+        let elf = Elf::parse(&output).expect("Invalid Elf file");
+        assert_eq!(elf.section_headers[1].sh_addr, 0x18000);
+        assert_eq!(elf.section_headers[2].sh_addr, 0);
+    }
+
+    fn make_layout<'a>(input: &'a [u8], sections: &'a Sections) -> Layout<'a> {
+        let load = ProgramHeader {
+            p_paddr: 0x8000,
+            p_vaddr: 0x8000,
+            p_filesz: 0x100,
+            p_memsz: 0x100,
+            ..ProgramHeader::new()
+        };
+
+        create_placed_layout(vec![load], input, sections, 0)
+    }
+
+    fn make_sections(base: u64) -> Sections {
+        Sections {
+            shdr: vec![
+                SectionHeader::default(),
+                SectionHeader {
+                    sh_type: section_header::SHT_PROGBITS,
+                    sh_flags: u64::from(section_header::SHF_ALLOC),
+                    sh_addr: 0x8000,
+                    sh_offset: 0x40,
+                    sh_size: 0x40,
+                    ..SectionHeader::default()
+                },
+                SectionHeader {
+                    sh_type: section_header::SHT_PROGBITS,
+                    sh_offset: 0x80,
+                    sh_size: 0x20,
+                    sh_addralign: 1,
+                    ..SectionHeader::default()
+                },
+            ],
+            shstrndx: 0,
+            flags: 0,
+            base,
+        }
+    }
+}
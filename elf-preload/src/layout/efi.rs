@@ -0,0 +1,439 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::Layout;
+use crate::{Error, Result, ValidationError};
+use goblin::elf::{header, program_header};
+use scroll::{Endian, Pwrite, LE};
+use std::io::{Seek, Write};
+
+const EFI_PAGE_SIZE: u64 = 0x1000;
+
+const PE_OFFSET: usize = 0x40;
+const COFF_OFFSET: usize = PE_OFFSET + 4;
+const OPTIONAL_OFFSET: usize = COFF_OFFSET + 20;
+const OPTIONAL_SIZE: usize = 240;
+const SECTION_TABLE_OFFSET: usize = OPTIONAL_OFFSET + OPTIONAL_SIZE;
+const SECTION_HEADER_SIZE: usize = 40;
+
+const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+const IMAGE_FILE_MACHINE_ARM64: u16 = 0xaa64;
+// RELOCS_STRIPPED | EXECUTABLE_IMAGE | LARGE_ADDRESS_AWARE | DEBUG_STRIPPED
+const IMAGE_FILE_CHARACTERISTICS: u16 = 0x0223;
+const PE32_PLUS_MAGIC: u16 = 0x020b;
+const IMAGE_SUBSYSTEM_EFI_APPLICATION: u16 = 10;
+const IMAGE_NUMBEROF_DIRECTORY_ENTRIES: u32 = 16;
+
+const IMAGE_SCN_CNT_CODE: u32 = 0x0000_0020;
+const IMAGE_SCN_CNT_INITIALIZED_DATA: u32 = 0x0000_0040;
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const IMAGE_SCN_MEM_READ: u32 = 0x4000_0000;
+const IMAGE_SCN_MEM_WRITE: u32 = 0x8000_0000;
+
+// A section of the EFI application: its RVA range and the union of the ELF
+// p_flags of the segments within it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Section {
+    start: u64,
+    end: u64,
+    flags: u32,
+}
+
+impl Section {
+    fn name(&self, stub: bool) -> &'static [u8; 8] {
+        match (stub, self.flags & program_header::PF_X != 0) {
+            (true, _) => b".stub\0\0\0",
+            (false, true) => b".text\0\0\0",
+            (false, false) => b".data\0\0\0",
+        }
+    }
+
+    fn characteristics(&self) -> u32 {
+        let mut characteristics = IMAGE_SCN_MEM_READ;
+        if self.flags & program_header::PF_X != 0 {
+            characteristics |= IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE;
+        } else {
+            characteristics |= IMAGE_SCN_CNT_INITIALIZED_DATA;
+        }
+        if self.flags & program_header::PF_W != 0 {
+            characteristics |= IMAGE_SCN_MEM_WRITE;
+        }
+
+        characteristics
+    }
+}
+
+impl<'a> Layout<'a> {
+    /// Write the output represented by this layout to `sink` (starting at the
+    /// current position of `sink`) as a PE32+ EFI application.
+    ///
+    /// The EFI application has a `.text` section for the executable loadable
+    /// segments of the output and a `.data` section for the others (segments
+    /// that share a page share a section). It is entered through a `.stub`
+    /// section that passes the image handle and system table on to the entry
+    /// point of the input in the first two arguments of its native calling
+    /// convention, so the entry point should not return to the firmware.
+    ///
+    /// The image base of the EFI application is two pages below the (page
+    /// aligned) [`load_address`][crate::BinaryImage::load_address] of the flat
+    /// binary output. The application has no base relocations so firmware
+    /// that honours `IMAGE_FILE_RELOCS_STRIPPED` (e.g. edk2) loads it at its
+    /// image base; other firmware (e.g. U-Boot's `bootefi`) requires a
+    /// position independent input.
+    ///
+    /// # Errors
+    /// `write_efi_to()` can return the following errors:
+    /// * `Error::InvalidElf`: see [`binary_image`][Layout::binary_image], the
+    ///   machine of the input is not aarch64 or x86_64, the input is big
+    ///   endian, there is no room for the headers and the stub below the flat
+    ///   binary output, or there are too many sections for the headers
+    /// * `Error::AddressOutOfRange`: the EFI application does not fit in 32 bit
+    ///   relative addresses
+    /// * `Error::Io`: writing to or seeking `sink` failed
+    pub fn write_efi_to<W: Write + Seek>(&self, sink: &mut W) -> Result<()> {
+        let machine = match self.arch.machine() {
+            header::EM_AARCH64 => IMAGE_FILE_MACHINE_ARM64,
+            header::EM_X86_64 => IMAGE_FILE_MACHINE_AMD64,
+            machine => return Err(ValidationError::EfiMachine { machine }.into()),
+        };
+        if self.arch.ctx().le == Endian::Big {
+            return Err(ValidationError::EfiBigEndian.into());
+        }
+
+        let image = self.binary_image()?;
+        let image_start = image.load_address() & !(EFI_PAGE_SIZE - 1);
+        let image_base =
+            image_start
+                .checked_sub(2 * EFI_PAGE_SIZE)
+                .ok_or(ValidationError::EfiNoRoom {
+                    load_address: image.load_address(),
+                })?;
+        let sections = self.efi_sections(image_base);
+        let image_size = sections.last().map_or(2 * EFI_PAGE_SIZE, |s| s.end);
+        if image_size > u64::from(u32::MAX) {
+            return Err(Error::AddressOutOfRange {
+                address: image_base + image_size,
+            });
+        }
+
+        let entry = image.load_address() + image.entry_offset() - image_base;
+        let mut headers = self.efi_headers(machine, image_base, image_size, &sections)?;
+        write_entry_stub(machine, entry, &mut headers[EFI_PAGE_SIZE as usize..])?;
+        headers.resize((image.load_address() - image_base) as usize, 0);
+
+        sink.write_all(&headers)?;
+        self.write_binary_to(sink)?;
+        let tail = image_size - (image.load_address() - image_base) - image.size() as u64;
+        sink.write_all(&vec![0; tail as usize])?;
+
+        Ok(())
+    }
+
+    // #SPC-elfpreload.efi
+    fn efi_sections(&self, image_base: u64) -> Vec<Section> {
+        let mut sections = vec![Section {
+            start: EFI_PAGE_SIZE,
+            end: 2 * EFI_PAGE_SIZE,
+            flags: program_header::PF_R | program_header::PF_X,
+        }];
+
+        for ph in self.binary_segments() {
+            let start = (ph.p_paddr - image_base) & !(EFI_PAGE_SIZE - 1);
+            let end = align_up(ph.p_paddr + ph.p_memsz - image_base);
+
+            match sections.last_mut() {
+                Some(last) if start < last.end => {
+                    last.end = last.end.max(end);
+                    last.flags |= ph.p_flags;
+                }
+                _ => sections.push(Section {
+                    start,
+                    end,
+                    flags: ph.p_flags,
+                }),
+            }
+        }
+
+        sections
+    }
+
+    fn efi_headers(
+        &self,
+        machine: u16,
+        image_base: u64,
+        image_size: u64,
+        sections: &[Section],
+    ) -> Result<Vec<u8>> {
+        let headers_size = SECTION_TABLE_OFFSET + sections.len() * SECTION_HEADER_SIZE;
+        if headers_size > EFI_PAGE_SIZE as usize {
+            return Err(ValidationError::EfiTooManySections {
+                sections: sections.len(),
+            }
+            .into());
+        }
+        let size_of = |code: bool| -> u32 {
+            sections
+                .iter()
+                .filter(|s| (s.flags & program_header::PF_X != 0) == code)
+                .map(|s| (s.end - s.start) as u32)
+                .sum()
+        };
+        let mut headers = vec![0; 2 * EFI_PAGE_SIZE as usize];
+
+        // DOS header
+        headers[..2].copy_from_slice(b"MZ");
+        headers.pwrite_with(PE_OFFSET as u32, 0x3c, LE)?;
+
+        // PE signature and COFF file header
+        headers[PE_OFFSET..COFF_OFFSET].copy_from_slice(b"PE\0\0");
+        headers.pwrite_with(machine, COFF_OFFSET, LE)?;
+        headers.pwrite_with(sections.len() as u16, COFF_OFFSET + 2, LE)?;
+        headers.pwrite_with(OPTIONAL_SIZE as u16, COFF_OFFSET + 16, LE)?;
+        headers.pwrite_with(IMAGE_FILE_CHARACTERISTICS, COFF_OFFSET + 18, LE)?;
+
+        // PE32+ optional header (the data directories are all empty)
+        let optional = OPTIONAL_OFFSET;
+        headers.pwrite_with(PE32_PLUS_MAGIC, optional, LE)?;
+        headers.pwrite_with(size_of(true), optional + 4, LE)?;
+        headers.pwrite_with(size_of(false), optional + 8, LE)?;
+        headers.pwrite_with(EFI_PAGE_SIZE as u32, optional + 16, LE)?;
+        headers.pwrite_with(EFI_PAGE_SIZE as u32, optional + 20, LE)?;
+        headers.pwrite_with(image_base, optional + 24, LE)?;
+        headers.pwrite_with(EFI_PAGE_SIZE as u32, optional + 32, LE)?;
+        headers.pwrite_with(EFI_PAGE_SIZE as u32, optional + 36, LE)?;
+        headers.pwrite_with(image_size as u32, optional + 56, LE)?;
+        headers.pwrite_with(EFI_PAGE_SIZE as u32, optional + 60, LE)?;
+        headers.pwrite_with(IMAGE_SUBSYSTEM_EFI_APPLICATION, optional + 68, LE)?;
+        headers.pwrite_with(IMAGE_NUMBEROF_DIRECTORY_ENTRIES, optional + 108, LE)?;
+
+        // Section table (the file layout matches the memory layout)
+        for (index, section) in sections.iter().enumerate() {
+            let offset = SECTION_TABLE_OFFSET + index * SECTION_HEADER_SIZE;
+            let size = (section.end - section.start) as u32;
+            headers[offset..offset + 8].copy_from_slice(section.name(index == 0));
+            headers.pwrite_with(size, offset + 8, LE)?;
+            headers.pwrite_with(section.start as u32, offset + 12, LE)?;
+            headers.pwrite_with(size, offset + 16, LE)?;
+            headers.pwrite_with(section.start as u32, offset + 20, LE)?;
+            headers.pwrite_with(section.characteristics(), offset + 36, LE)?;
+        }
+
+        Ok(headers)
+    }
+}
+
+// Write the entry stub (at RVA EFI_PAGE_SIZE) that branches to the entry point
+// at the given RVA.
+fn write_entry_stub(machine: u16, entry: u64, stub: &mut [u8]) -> Result<()> {
+    let offset = entry - EFI_PAGE_SIZE;
+
+    if machine == IMAGE_FILE_MACHINE_ARM64 {
+        // b entry
+        if offset >= 0x800_0000 {
+            return Err(Error::AddressOutOfRange { address: entry });
+        }
+        stub.pwrite_with(0x1400_0000 | (offset / 4) as u32, 0, LE)?;
+    } else {
+        // mov rdi, rcx; mov rsi, rdx; jmp entry
+        let jump = offset - 11;
+        if jump > i32::MAX as u64 {
+            return Err(Error::AddressOutOfRange { address: entry });
+        }
+        stub[..7].copy_from_slice(&[0x48, 0x89, 0xcf, 0x48, 0x89, 0xd6, 0xe9]);
+        stub.pwrite_with(jump as u32, 7, LE)?;
+    }
+
+    Ok(())
+}
+
+fn align_up(address: u64) -> u64 {
+    (address + EFI_PAGE_SIZE - 1) & !(EFI_PAGE_SIZE - 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::test::create_machine_arch;
+    use crate::layout::test::{create_layout, NO_SECTIONS};
+    use crate::{LayoutConfig, LayoutStrategy};
+    use goblin::container::{Container, Endian};
+    use goblin::elf::ProgramHeader;
+    use scroll::Pread;
+    use std::io::Cursor;
+
+    #[test]
+    fn layout_efi_has_pe32_plus_headers() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, header::EM_AARCH64, Endian::Little);
+
+        let image = sut.binary_image().unwrap();
+
+        let output = write_efi(&sut).unwrap();
+
+        let pe = output.pread_with::<u32>(0x3c, LE).unwrap() as usize;
+        assert_eq!(&output[..2], b"MZ");
+        assert_eq!(&output[pe..pe + 4], b"PE\0\0");
+        assert_eq!(output.pread_with::<u16>(pe + 4, LE).unwrap(), 0xaa64);
+        assert_eq!(output.pread_with::<u16>(pe + 24, LE).unwrap(), 0x020b);
+        assert_eq!(output.pread_with::<u16>(pe + 24 + 68, LE).unwrap(), 10);
+        assert_eq!(
+            output.pread_with::<u64>(pe + 24 + 24, LE).unwrap(),
+            image.load_address() - 0x2000
+        );
+    }
+
+    #[test]
+    fn layout_efi_size_is_size_of_image() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, header::EM_AARCH64, Endian::Little);
+
+        let output = write_efi(&sut).unwrap();
+
+        let size_of_image = output.pread_with::<u32>(OPTIONAL_OFFSET + 56, LE).unwrap();
+        assert_eq!(output.len(), size_of_image as usize);
+    }
+
+    #[test]
+    fn layout_efi_sections_match_segments() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, header::EM_AARCH64, Endian::Little);
+
+        let image = sut.binary_image().unwrap();
+
+        let sections = sut.efi_sections(image.load_address() - 0x2000);
+
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[1].name(false), b".text\0\0\0");
+        assert_eq!(sections[2].name(false), b".data\0\0\0");
+        assert_eq!(
+            sections[2].characteristics(),
+            IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE
+        );
+    }
+
+    #[test]
+    fn layout_efi_binary_output_follows_stub() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, header::EM_AARCH64, Endian::Little);
+        let mut expected = vec![0; sut.binary_image().unwrap().size()];
+        sut.binary_output(&mut expected).unwrap().write().unwrap();
+
+        let output = write_efi(&sut).unwrap();
+
+        assert_eq!(&output[0x2000..0x2000 + expected.len()], &expected[..]);
+    }
+
+    #[test]
+    fn arm64_entry_stub_branches_to_entry() {
+        let mut stub = [0; 16];
+
+        write_entry_stub(IMAGE_FILE_MACHINE_ARM64, 0x2004, &mut stub).unwrap();
+
+        assert_eq!(stub.pread_with::<u32>(0, LE).unwrap(), 0x1400_0401);
+    }
+
+    #[test]
+    fn x86_64_entry_stub_jumps_to_entry() {
+        let mut stub = [0; 16];
+
+        write_entry_stub(IMAGE_FILE_MACHINE_AMD64, 0x2004, &mut stub).unwrap();
+
+        assert_eq!(stub[6], 0xe9);
+        assert_eq!(stub.pread_with::<u32>(7, LE).unwrap(), 0x2004 - 0x100b);
+    }
+
+    #[test]
+    fn layout_efi_for_other_machine_is_error() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, header::EM_ARM, Endian::Little);
+
+        let result = write_efi(&sut);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::EfiMachine {
+                machine: header::EM_ARM
+            }))
+        );
+    }
+
+    #[test]
+    fn layout_efi_for_big_endian_is_error() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, header::EM_AARCH64, Endian::Big);
+
+        let result = write_efi(&sut);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::EfiBigEndian))
+        );
+    }
+
+    #[test]
+    fn layout_efi_without_room_for_headers_is_error() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout_at(&input, header::EM_AARCH64, Endian::Little, 0);
+
+        let result = write_efi(&sut);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::EfiNoRoom { .. }))
+        );
+    }
+
+    #[test]
+    fn layout_efi_with_too_many_sections_is_error() {
+        let phdr: Vec<_> = (1..=100)
+            .map(|page| ProgramHeader {
+                p_type: program_header::PT_LOAD,
+                p_flags: program_header::PF_R | program_header::PF_X,
+                p_vaddr: 0x10_0000 + page * 0x1000,
+                p_memsz: 0x10,
+                p_align: 0x1000,
+                ..ProgramHeader::new()
+            })
+            .collect();
+        let sut = Layout::new(
+            create_machine_arch(header::EM_AARCH64, Container::Big, Endian::Little),
+            phdr.iter(),
+            &[],
+            &NO_SECTIONS,
+            0x10_1000,
+            LayoutStrategy::SpecifiedStart(0x8000_0000),
+            &LayoutConfig::new(),
+        );
+
+        let result = write_efi(&sut);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::EfiTooManySections {
+                sections: 101
+            }))
+        );
+    }
+
+    fn write_efi(layout: &Layout) -> Result<Vec<u8>> {
+        let mut sink = Cursor::new(Vec::new());
+        layout.write_efi_to(&mut sink)?;
+
+        Ok(sink.into_inner())
+    }
+
+    fn make_layout(input: &[u8], machine: u16, endian: Endian) -> Layout<'_> {
+        make_layout_at(input, machine, endian, 0x8000_0000)
+    }
+
+    fn make_layout_at(input: &[u8], machine: u16, endian: Endian, start: u64) -> Layout<'_> {
+        let arch = create_machine_arch(machine, Container::Big, endian);
+
+        create_layout(arch, input, 0x1000, 0x1004, start)
+    }
+}
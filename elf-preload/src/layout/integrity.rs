@@ -0,0 +1,121 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::{Layout, LayoutStrategy};
+use crate::integrity::{digest, DESC_HEADER_SIZE, DESC_OFFSET, ENTRY_SIZE};
+use crate::integrity::{NOTE_NAME, NT_SEGMENT_DIGESTS};
+use crate::Result;
+use scroll::Pwrite;
+
+impl<'a> Layout<'a> {
+    /// Write the segment digests note (if this layout has one) into the output
+    /// for the first segment.
+    // #SPC-elfpreload.digests
+    pub(super) fn write_digests_note(&self, output: &mut [u8]) -> Result<()> {
+        let note = match LayoutStrategy::digests_note(&self.out_phdr) {
+            Some(note) => &mut output[note.file_range()],
+            None => return Ok(()),
+        };
+        let le = self.arch.ctx().le;
+        let segments = self.out_segments() - 1; // the first segment is not covered
+
+        note.pwrite_with(NOTE_NAME.len() as u32, 0, le)?;
+        note.pwrite_with((note.len() - DESC_OFFSET) as u32, 4, le)?;
+        note.pwrite_with(NT_SEGMENT_DIGESTS, 8, le)?;
+        note[12..DESC_OFFSET].copy_from_slice(NOTE_NAME);
+        note.pwrite_with(segments as u32, DESC_OFFSET, le)?;
+        note.pwrite_with(0u32, DESC_OFFSET + 4, le)?;
+
+        for segment in 1..=segments {
+            let in_range = self.in_phdr[LayoutStrategy::in_index(segment)].file_range();
+            let out_phdr = &self.out_phdr[LayoutStrategy::out_index(segment)];
+            let zeros = out_phdr.p_memsz as usize - in_range.len();
+            let (crc32, sha256) = digest(&self.input[in_range], zeros);
+
+            let offset = DESC_OFFSET + DESC_HEADER_SIZE + (segment - 1) * ENTRY_SIZE;
+            note.pwrite_with(out_phdr.p_paddr, offset, le)?;
+            note.pwrite_with(out_phdr.p_memsz, offset + 8, le)?;
+            note.pwrite_with(crc32, offset + 16, le)?;
+            note.pwrite_with(0u32, offset + 20, le)?;
+            note[offset + 24..offset + 24 + sha256.len()].copy_from_slice(&sha256);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::arch::test::create_arch;
+    use crate::layout::test::{create_loads, NO_SECTIONS};
+    use crate::{verify_digests, Error, Layout, LayoutConfig, LayoutStrategy, ValidationError};
+    use goblin::container::{Container, Endian};
+
+    #[test]
+    fn layout_with_digests_verifies() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input);
+        let mut output = vec![0; sut.required_size()];
+
+        sut.output(&mut output).unwrap().write().unwrap();
+        let result = verify_digests(&output);
+
+        assert_matches!(result, Ok(()));
+    }
+
+    #[test]
+    fn layout_with_digests_detects_corrupt_bss() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input);
+        let mut output = vec![0; sut.required_size()];
+
+        sut.output(&mut output).unwrap().write().unwrap();
+        let last = output.len() - 1;
+        output[last] = 0xc0;
+        let result = verify_digests(&output);
+
+        let paddr = sut.program_headers()[3].p_paddr;
+        assert_matches!(result, Err(Error::DigestMismatch { paddr: p }) if p == paddr);
+    }
+
+    #[test]
+    fn layout_without_digests_fails_to_verify() {
+        let input = [0xdb; 0x20];
+        let config = LayoutConfig::new();
+        let sut = Layout::new(
+            create_arch(Container::Big, Endian::Big),
+            create_loads(0x1000).iter(),
+            &input,
+            &NO_SECTIONS,
+            0x1000,
+            LayoutStrategy::SpecifiedStart(0x8000),
+            &config,
+        );
+        let mut output = vec![0; sut.required_size()];
+
+        sut.output(&mut output).unwrap().write().unwrap();
+        let result = verify_digests(&output);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::NoDigestsNote))
+        );
+    }
+
+    fn make_layout(input: &[u8]) -> Layout<'_> {
+        Layout::new(
+            create_arch(Container::Big, Endian::Big),
+            create_loads(0x1000).iter(),
+            input,
+            &NO_SECTIONS,
+            0x1000,
+            LayoutStrategy::SpecifiedStart(0x8000),
+            &LayoutConfig::new().digests(true),
+        )
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::{Layout, LayoutStrategy};
+use crate::{Error, Result};
+use std::io::Write;
+
+const RECORD_SIZE: usize = 16;
+const SREC_HEADER: &[u8] = b"elf-preload";
+
+impl<'a> Layout<'a> {
+    /// Write the output represented by this layout to `sink` as Intel HEX.
+    ///
+    /// Each loadable segment of the output is written as data records at its
+    /// physical address, using extended linear address records for the upper
+    /// 16 bits of the addresses. The data is the same as the contents of the
+    /// segments of the ELF output. If the entry point is within a loadable
+    /// segment, its physical address is written as a start linear address
+    /// record.
+    ///
+    /// # Errors
+    /// `write_ihex_to()` can return the following errors:
+    /// * `Error::AddressOutOfRange`: a segment (or the entry point) does not
+    ///   fit in 32 bit addresses
+    /// * `Error::Io`: writing to `sink` failed
+    pub fn write_ihex_to<W: Write>(&self, sink: &mut W) -> Result<()> {
+        let mut upper = None;
+
+        for (address, data) in self.records()? {
+            if upper != Some(address >> 16) {
+                upper = Some(address >> 16);
+                let ela = ((address >> 16) as u16).to_be_bytes();
+                write_ihex_record(sink, 0x04, 0, &ela)?;
+            }
+            write_ihex_record(sink, 0x00, address as u16, &data)?;
+        }
+        if let Some(entry) = self.entry_paddr()? {
+            write_ihex_record(sink, 0x05, 0, &entry.to_be_bytes())?;
+        }
+        write_ihex_record(sink, 0x01, 0, &[])?;
+
+        Ok(())
+    }
+
+    /// Write the output represented by this layout to `sink` as Motorola
+    /// S-records.
+    ///
+    /// Each loadable segment of the output is written as S3 records (with 32
+    /// bit addresses) at its physical address. The data is the same as the
+    /// contents of the segments of the ELF output. The S7 termination record
+    /// has the physical address of the entry point (or zero if the entry point
+    /// is not within a loadable segment).
+    ///
+    /// # Errors
+    /// `write_srec_to()` can return the following errors:
+    /// * `Error::AddressOutOfRange`: a segment (or the entry point) does not
+    ///   fit in 32 bit addresses
+    /// * `Error::Io`: writing to `sink` failed
+    pub fn write_srec_to<W: Write>(&self, sink: &mut W) -> Result<()> {
+        let records = self.records()?;
+
+        write_srec_record(sink, b'0', &[0, 0], SREC_HEADER)?;
+        for (address, data) in &records {
+            write_srec_record(sink, b'3', &address.to_be_bytes(), data)?;
+        }
+        match records.len() {
+            count if count <= 0xffff => {
+                write_srec_record(sink, b'5', &(count as u16).to_be_bytes(), &[])?
+            }
+            count if count <= 0xff_ffff => {
+                write_srec_record(sink, b'6', &(count as u32).to_be_bytes()[1..], &[])?
+            }
+            _ => {}
+        }
+        let entry = self.entry_paddr()?.unwrap_or(0);
+        write_srec_record(sink, b'7', &entry.to_be_bytes(), &[])?;
+
+        Ok(())
+    }
+
+    // The address and data of each record, with no record crossing a 64 KiB
+    // boundary.
+    fn records(&self) -> Result<Vec<(u32, Vec<u8>)>> {
+        let mut records = Vec::new();
+
+        for segment in 0..self.out_segments() {
+            let mut contents = vec![0; self.segment_size(segment)];
+            self.write_segment(segment, &mut contents)?;
+            let paddr = self.out_phdr[LayoutStrategy::out_index(segment)].p_paddr;
+            let mut address = check_address(paddr, contents.len())?;
+
+            let mut remaining = &contents[..];
+            while !remaining.is_empty() {
+                let to_boundary = 0x1_0000 - (address as usize & 0xffff);
+                let len = remaining.len().min(RECORD_SIZE).min(to_boundary);
+                records.push((address, remaining[..len].to_vec()));
+                remaining = &remaining[len..];
+                address = address.wrapping_add(len as u32);
+            }
+        }
+
+        Ok(records)
+    }
+
+    fn entry_paddr(&self) -> Result<Option<u32>> {
+        let paddr = self
+            .out_phdr
+            .iter()
+            .skip(LayoutStrategy::out_index(1))
+            .take(self.out_segments() - 1)
+            .find(|ph| self.entry >= ph.p_vaddr && self.entry - ph.p_vaddr < ph.p_memsz)
+            .map(|ph| ph.p_paddr + (self.entry - ph.p_vaddr));
+
+        paddr.map(|paddr| check_address(paddr, 1)).transpose()
+    }
+}
+
+fn check_address(address: u64, len: usize) -> Result<u32> {
+    let last = address.saturating_add(len.saturating_sub(1) as u64);
+
+    if last > u64::from(u32::MAX) {
+        Err(Error::AddressOutOfRange { address: last })
+    } else {
+        Ok(address as u32)
+    }
+}
+
+fn write_ihex_record<W: Write>(sink: &mut W, kind: u8, address: u16, data: &[u8]) -> Result<()> {
+    let address = address.to_be_bytes();
+    let mut record = vec![data.len() as u8, address[0], address[1], kind];
+    record.extend_from_slice(data);
+    let checksum = record
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg();
+    record.push(checksum);
+
+    write!(sink, ":")?;
+    write_hex(sink, &record)?;
+    writeln!(sink)?;
+
+    Ok(())
+}
+
+fn write_srec_record<W: Write>(sink: &mut W, kind: u8, address: &[u8], data: &[u8]) -> Result<()> {
+    let mut record = vec![(address.len() + data.len() + 1) as u8];
+    record.extend_from_slice(address);
+    record.extend_from_slice(data);
+    let checksum = !record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    record.push(checksum);
+
+    sink.write_all(&[b'S', kind])?;
+    write_hex(sink, &record)?;
+    writeln!(sink)?;
+
+    Ok(())
+}
+
+fn write_hex<W: Write>(sink: &mut W, bytes: &[u8]) -> Result<()> {
+    for byte in bytes {
+        write!(sink, "{:02X}", byte)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::test::create_arch;
+    use crate::layout::test::create_layout;
+    use goblin::container::{Container, Endian};
+
+    #[test]
+    fn ihex_record_has_known_checksum() {
+        let mut sink = Vec::new();
+
+        write_ihex_record(&mut sink, 0x00, 0x0030, &[0x02, 0x33, 0x7a]).unwrap();
+
+        assert_eq!(sink, b":0300300002337A1E\n");
+    }
+
+    #[test]
+    fn srec_record_has_known_checksum() {
+        let mut sink = Vec::new();
+
+        write_srec_record(&mut sink, b'1', &[0x00, 0x38], b"Hello").unwrap();
+
+        assert_eq!(sink, b"S108003848656C6C6FCB\n");
+    }
+
+    #[test]
+    fn layout_records_do_not_cross_64k_boundary() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, 0xfff0);
+
+        let records = sut.records().unwrap();
+
+        assert!(records
+            .iter()
+            .all(|(address, data)| address >> 16 == (address + data.len() as u32 - 1) >> 16));
+    }
+
+    #[test]
+    fn layout_write_ihex_to_writes_extended_linear_address() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, 0x1234_0000);
+        let mut sink = Vec::new();
+
+        sut.write_ihex_to(&mut sink).unwrap();
+
+        let ihex = String::from_utf8(sink).unwrap();
+        assert_eq!(ihex.lines().next(), Some(":020000041234B4"));
+        assert_eq!(ihex.lines().last(), Some(":00000001FF"));
+    }
+
+    #[test]
+    fn layout_write_srec_to_above_4g_is_error() {
+        let input = [0xdb; 0x20];
+        let sut = make_layout(&input, 0xffff_ff00);
+
+        let result = sut.write_srec_to(&mut Vec::new());
+
+        assert_matches!(result, Err(Error::AddressOutOfRange { address }) if address > 0xffff_ffff);
+    }
+
+    fn make_layout(input: &[u8], start: u64) -> Layout<'_> {
+        let arch = create_arch(Container::Little, Endian::Little);
+
+        create_layout(arch, input, 0x10, 0x1000, start)
+    }
+}
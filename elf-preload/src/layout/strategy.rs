@@ -6,7 +6,9 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms
 
-use crate::PAGE_SIZE;
+use super::LayoutConfig;
+use crate::Arch;
+use alloc::vec::Vec;
 use goblin::container::Ctx;
 use goblin::elf::{program_header, Header, ProgramHeader};
 
@@ -22,16 +24,88 @@ pub enum LayoutStrategy {
 
     /// The starting physical address is set to the specified physical address and
     /// later the subsequent physical addresses are set to exactly follow the end
-    /// of the previous segment.
+    /// of the previous segment. The specified physical address is first aligned
+    /// up to the output alignment (see [`LayoutConfig::align`]).
     SpecifiedStart(u64),
+
+    /// The starting physical address is the highest address such that the
+    /// output ends at or below the exclusive `end` physical address. The
+    /// subsequent physical addresses are set as for `SpecifiedStart`.
+    ///
+    /// The starting physical address is aligned to the largest of `align`, the
+    /// output alignment (see [`LayoutConfig::align`]), and the `p_align` of
+    /// each of the segments so that every segment is aligned in physical memory
+    /// as it is in virtual memory.
+    EndAt {
+        /// The exclusive end physical address of the output.
+        end: u64,
+        /// The minimum alignment of the starting physical address.
+        align: u64,
+    },
+
+    /// The physical address of each segment is its virtual address. The
+    /// segments are otherwise laid out as for `FromInput`.
+    ///
+    /// This and the following strategies are for inputs whose physical
+    /// addresses are missing (e.g. zero) or not useful.
+    Identity,
+
+    /// The physical address of each segment is its virtual address less the
+    /// given offset. The segments are otherwise laid out as for `FromInput`.
+    VirtOffset(u64),
+
+    /// The physical address of each segment is its virtual address with the
+    /// bits in `mask` cleared (e.g. a `mask` of 0xffff_ff80_0000_0000 for a
+    /// kernel that is linked at 0xffff_ff80_0000_0000 plus its physical
+    /// address). The segments are otherwise laid out as for `FromInput`.
+    HighHalf {
+        /// The bits to clear from each virtual address.
+        mask: u64,
+    },
 }
 
 impl LayoutStrategy {
+    /// Whether this strategy derives the physical addresses of the segments
+    /// from their virtual addresses.
+    pub(crate) fn derives_paddr(&self) -> bool {
+        use LayoutStrategy::{HighHalf, Identity, VirtOffset};
+
+        matches!(self, Identity | VirtOffset(_) | HighHalf { .. })
+    }
+
+    /// The physical address that this strategy derives from the given virtual
+    /// address. This is `None` if the strategy doesn't derive physical
+    /// addresses or the virtual address is below the offset for `VirtOffset`.
+    pub(crate) fn derive_paddr(&self, vaddr: u64) -> Option<u64> {
+        use LayoutStrategy::{HighHalf, Identity, VirtOffset};
+
+        match self {
+            Identity => Some(vaddr),
+            VirtOffset(offset) => vaddr.checked_sub(*offset),
+            HighHalf { mask } => Some(vaddr & !mask),
+            _ => None,
+        }
+    }
+
     /// Count of the loadable segments in out_phdr.
     ///
     /// out_phdr should be the return value of [LayoutStrategy::layout].
     pub(super) fn out_segments(out_phdr: &[ProgramHeader]) -> usize {
-        out_phdr.len() - 1 // ignore the initial PT_PHDR
+        out_phdr
+            .iter()
+            .filter(|ph| ph.p_type == program_header::PT_LOAD)
+            .count()
+    }
+
+    /// Return the PT_NOTE for the segment digests in out_phdr (if there is one).
+    ///
+    /// This is the only PT_NOTE within the first PT_LOAD segment.
+    #[cfg(feature = "integrity")]
+    pub(super) fn digests_note(out_phdr: &[ProgramHeader]) -> Option<&ProgramHeader> {
+        let header_size = out_phdr[Self::out_index(0)].p_filesz;
+        out_phdr
+            .iter()
+            .find(|ph| ph.p_type == program_header::PT_NOTE && ph.p_offset < header_size)
     }
 
     /// Return the index into an out_phdr for a given loadable segment
@@ -44,35 +118,124 @@ impl LayoutStrategy {
         segment - 1 // there is no PT_LOAD for the headers in the input
     }
 
+    /// Omit the trailing bss of the last loadable segments in out_phdr from the
+    /// output file.
+    ///
+    /// Working back from the last segment, the file size of each segment is cut
+    /// back to the file size of the corresponding segment in in_phdr until (and
+    /// including) the first segment that has contents in the input. The offsets
+    /// are unchanged.
+    // #SPC-elfpreload.elidebss
+    pub(super) fn elide_trailing_bss(out_phdr: &mut [ProgramHeader], in_phdr: &[ProgramHeader]) {
+        for segment in (1..Self::out_segments(out_phdr)).rev() {
+            let filesz = in_phdr[Self::in_index(segment)].p_filesz;
+            out_phdr[Self::out_index(segment)].p_filesz = filesz;
+            if filesz > 0 {
+                break;
+            }
+        }
+    }
+
+    /// The memory size of the first PT_LOAD program header of an output that is
+    /// laid out with `FromInput` (or with a strategy that derives the physical
+    /// addresses), whose first loadable segment is `first`.
+    ///
+    /// This is the room that the output needs below the physical address of
+    /// `first`: the ELF header, the program headers, any digests note, and the
+    /// padding that aligns `first` in the output file.
+    pub(crate) fn header_load_size(
+        first: &ProgramHeader,
+        segments: usize,
+        preserved: usize,
+        arch: Arch,
+        config: &LayoutConfig,
+    ) -> u64 {
+        let (_, header_size) = headers_size(segments, preserved, arch.ctx(), config);
+
+        header_size + align_adjustment(header_size, first.p_vaddr, first.p_align)
+    }
+
     /// Tranforms the input program headers into the output program hearders
     /// in accorance with the given LayoutStrategy.
     ///
     /// The input program headers should be sorted by p_paddr and then by
-    /// p_vaddr.
+    /// p_vaddr. For the strategies that derive the physical addresses from the
+    /// virtual addresses, the p_paddr of the input program headers should
+    /// already be the derived physical addresses. The preserved program
+    /// headers are added after the PT_LOAD program headers with their offsets
+    /// and physical addresses rewritten to match the PT_LOAD segment that
+    /// contains them. If config calls for a note then room for the note is
+    /// reserved in the first PT_LOAD segment (after the program headers) and a
+    /// PT_NOTE for it is added last. If config separates permissions then
+    /// `SpecifiedStart` pads the output so that each segment whose p_flags
+    /// differ from those of the previous segment starts after the page that
+    /// holds the end of the previous one.
     // #SPC-elfpreload.ptphdr
     // #SPC-elfpreload.ptload
     // #SPC-elfpreload.nobss
     // #SPC-elfpreload.paddr
     // #SPC-elfpreload.plenum
-    pub(super) fn layout<'a, I>(&self, input: I, ctx: Ctx) -> Vec<ProgramHeader>
+    // #SPC-elfpreload.digests
+    // #SPC-elfpreload.permissions
+    pub(super) fn layout<'a, I>(
+        &self,
+        input: I,
+        preserved: &[ProgramHeader],
+        arch: Arch,
+        config: &LayoutConfig,
+    ) -> Vec<ProgramHeader>
     where
         I: ExactSizeIterator<Item = &'a ProgramHeader>,
     {
-        use LayoutStrategy::{FromInput, SpecifiedStart};
-        let count = input.len() + 2;
+        use LayoutStrategy::{EndAt, FromInput, HighHalf, Identity, SpecifiedStart, VirtOffset};
+        let ctx = arch.ctx();
+        let page_size = config.resolve_page_size(arch);
+        let note_size = config.note_size(input.len());
+        let (count, header_size) = headers_size(input.len(), preserved.len(), ctx, config);
         let mut phdrs = Vec::with_capacity(count);
 
         match self {
+            EndAt { end, align } => {
+                let input: Vec<_> = input.collect();
+                let size = SpecifiedStart(0)
+                    .layout(input.iter().cloned(), preserved, arch, config)
+                    .iter()
+                    .map(|ph| ph.p_paddr + ph.p_memsz)
+                    .max()
+                    .unwrap_or(0);
+                let align = input
+                    .iter()
+                    .map(|ph| ph.p_align)
+                    .fold((*align).max(config.resolve_align(arch)), u64::max);
+
+                return SpecifiedStart(align_down(end.saturating_sub(size), 0, align)).layout(
+                    input.into_iter(),
+                    preserved,
+                    arch,
+                    config,
+                );
+            }
+
             SpecifiedStart(start) => {
-                phdrs.push(create_phdr_header(*start, count, ctx));
-                phdrs.push(create_first_load_header(*start, count, ctx));
+                phdrs.push(create_phdr_header(*start, count, ctx, page_size));
+                phdrs.push(create_first_load_header(
+                    *start,
+                    header_size,
+                    config.resolve_align(arch),
+                ));
 
                 let mut min_vaddr = u64::max_value();
-                let mut offset = first_load_header_size(count, ctx);
+                let mut offset = header_size;
                 let mut paddr = start + offset;
                 for phdr in input {
                     paddr += align_adjustment(offset, phdr.p_vaddr, phdr.p_align);
                     offset += align_adjustment(offset, phdr.p_vaddr, phdr.p_align);
+                    if config.separates_permissions() {
+                        let padding =
+                            permissions_padding(phdrs.last(), phdr, offset, paddr, page_size);
+                        paddr += padding;
+                        offset += padding;
+                    }
                     extend_load_header_size(phdrs.last_mut(), offset);
                     phdrs.push(create_subsequent_load_header(offset, paddr, phdr));
                     offset += phdr.p_memsz;
@@ -81,16 +244,20 @@ impl LayoutStrategy {
                 }
 
                 adjust_phdr_header(&mut phdrs[0], min_vaddr, count, ctx, None);
-                adjust_first_load_header(&mut phdrs[1], min_vaddr, count, ctx, None);
+                adjust_first_load_header(&mut phdrs[1], min_vaddr, header_size, None);
             }
 
-            FromInput => {
-                phdrs.push(create_phdr_header(0, count, ctx));
-                phdrs.push(create_first_load_header(0, count, ctx));
+            FromInput | Identity | VirtOffset(_) | HighHalf { .. } => {
+                phdrs.push(create_phdr_header(0, count, ctx, page_size));
+                phdrs.push(create_first_load_header(
+                    0,
+                    header_size,
+                    config.resolve_align(arch),
+                ));
 
                 let mut min_vaddr = u64::max_value();
                 let mut min_paddr = u64::max_value();
-                let mut offset = first_load_header_size(count, ctx);
+                let mut offset = header_size;
                 for phdr in input {
                     offset += align_adjustment(offset, phdr.p_vaddr, phdr.p_align);
                     extend_load_header_size(phdrs.last_mut(), offset);
@@ -102,15 +269,25 @@ impl LayoutStrategy {
 
                 let paddr_adjust = min_paddr - phdrs[1].p_memsz;
                 adjust_phdr_header(&mut phdrs[0], min_vaddr, count, ctx, Some(paddr_adjust));
-                adjust_first_load_header(&mut phdrs[1], min_vaddr, count, ctx, Some(paddr_adjust));
+                adjust_first_load_header(&mut phdrs[1], min_vaddr, header_size, Some(paddr_adjust));
             }
         }
 
+        for phdr in preserved {
+            let preserved = create_preserved_header(&phdrs[2..], phdr);
+            phdrs.push(preserved);
+        }
+
+        if let Some(size) = note_size {
+            let note = create_note_header(&phdrs[1], first_load_header_size(count, ctx), size);
+            phdrs.push(note);
+        }
+
         phdrs
     }
 }
 
-fn create_phdr_header(start_paddr: u64, count: usize, ctx: Ctx) -> ProgramHeader {
+fn create_phdr_header(start_paddr: u64, count: usize, ctx: Ctx, page_size: u64) -> ProgramHeader {
     let size = program_header_size(count, ctx);
     let header_size = Header::size(&ctx) as u64;
 
@@ -120,7 +297,7 @@ fn create_phdr_header(start_paddr: u64, count: usize, ctx: Ctx) -> ProgramHeader
         p_paddr: start_paddr + header_size,
         p_filesz: size,
         p_memsz: size,
-        p_align: PAGE_SIZE as u64,
+        p_align: page_size,
         ..ProgramHeader::new()
     };
 
@@ -152,15 +329,13 @@ fn adjust_phdr_header(
     }
 }
 
-fn create_first_load_header(start_paddr: u64, count: usize, ctx: Ctx) -> ProgramHeader {
-    let size = first_load_header_size(count, ctx);
-
+fn create_first_load_header(start_paddr: u64, size: u64, align: u64) -> ProgramHeader {
     let mut load = ProgramHeader {
         p_offset: 0,
         p_paddr: start_paddr,
         p_filesz: size,
         p_memsz: size,
-        p_align: PAGE_SIZE as u64,
+        p_align: align,
         ..ProgramHeader::new()
     };
 
@@ -172,15 +347,10 @@ fn create_first_load_header(start_paddr: u64, count: usize, ctx: Ctx) -> Program
 fn adjust_first_load_header(
     load: &mut ProgramHeader,
     lowest_vaddr: u64,
-    count: usize,
-    ctx: Ctx,
+    size: u64,
     paddr_adjust: Option<u64>,
 ) {
-    let vaddr = align_down(
-        lowest_vaddr - first_load_header_size(count, ctx),
-        load.p_offset,
-        load.p_align,
-    );
+    let vaddr = align_down(lowest_vaddr - size, load.p_offset, load.p_align);
 
     debug_assert!(load.p_type == program_header::PT_LOAD);
     debug_assert!(load.p_offset % load.p_align == vaddr % load.p_align);
@@ -192,6 +362,70 @@ fn adjust_first_load_header(
     }
 }
 
+fn create_note_header(first_load: &ProgramHeader, offset: u64, size: u64) -> ProgramHeader {
+    debug_assert!(first_load.p_type == program_header::PT_LOAD);
+    debug_assert!(offset + size <= first_load.p_filesz);
+
+    ProgramHeader {
+        p_type: program_header::PT_NOTE,
+        p_offset: offset,
+        p_paddr: first_load.p_paddr + offset,
+        p_vaddr: first_load.p_vaddr + offset,
+        p_filesz: size,
+        p_memsz: size,
+        p_flags: program_header::PF_R,
+        p_align: 4,
+    }
+}
+
+// #SPC-elfpreload.preserve
+/// Whether the contents of the given (preserved) program header are within the
+/// given loadable segment in virtual memory.
+///
+/// Only the p_filesz of a PT_TLS counts since its .tbss takes no address space
+/// in the segment (it is the template for each thread's own copy).
+pub(crate) fn within_load(ph: &ProgramHeader, load: &ProgramHeader) -> bool {
+    let size = match ph.p_type {
+        program_header::PT_TLS => ph.p_filesz,
+        _ => ph.p_memsz,
+    };
+
+    match (
+        ph.p_vaddr.checked_add(size),
+        load.p_vaddr.checked_add(load.p_memsz),
+    ) {
+        (Some(end), Some(load_end)) => ph.p_vaddr >= load.p_vaddr && end <= load_end,
+        _ => false,
+    }
+}
+
+// loads may also hold the earlier preserved program headers.
+fn create_preserved_header(loads: &[ProgramHeader], input: &ProgramHeader) -> ProgramHeader {
+    let load = loads
+        .iter()
+        .filter(|load| load.p_type == program_header::PT_LOAD)
+        .find(|load| within_load(input, load));
+
+    match load {
+        Some(load) if input.p_memsz > 0 => {
+            let delta = input.p_vaddr - load.p_vaddr;
+            ProgramHeader {
+                p_offset: load.p_offset + delta,
+                p_paddr: load.p_paddr + delta,
+                ..input.clone()
+            }
+        }
+
+        // A program header without contents (e.g. PT_GNU_STACK).
+        _ => ProgramHeader {
+            p_offset: 0,
+            p_paddr: 0,
+            p_filesz: 0,
+            ..input.clone()
+        },
+    }
+}
+
 fn create_subsequent_load_header(offset: u64, paddr: u64, input: &ProgramHeader) -> ProgramHeader {
     assert!(offset % input.p_align == input.p_vaddr % input.p_align);
 
@@ -207,6 +441,37 @@ fn create_subsequent_load_header(offset: u64, paddr: u64, input: &ProgramHeader)
     }
 }
 
+// The padding needed after the aligned next (at offset and paddr) so that it
+// doesn't share a page with previous if their permissions differ. The padded
+// next is still aligned.
+// #SPC-elfpreload.permissions
+fn permissions_padding(
+    previous: Option<&ProgramHeader>,
+    next: &ProgramHeader,
+    offset: u64,
+    paddr: u64,
+    page_size: u64,
+) -> u64 {
+    match previous {
+        Some(previous)
+            if previous.p_flags != next.p_flags
+                && shares_page(previous.p_paddr, previous.p_memsz, paddr, page_size) =>
+        {
+            let padding = page_size - paddr % page_size;
+            padding + align_adjustment(offset + padding, next.p_vaddr, next.p_align)
+        }
+        _ => 0,
+    }
+}
+
+/// Whether `address` is on the page that holds the last byte of the `size`
+/// bytes at `previous`.
+pub(super) fn shares_page(previous: u64, size: u64, address: u64, page_size: u64) -> bool {
+    let last = previous.saturating_add(size).saturating_sub(1);
+
+    last / page_size == address / page_size
+}
+
 fn extend_load_header_size(load: Option<&mut ProgramHeader>, next_offset: u64) {
     load.map(|ph| {
         ph.p_filesz = next_offset - ph.p_offset;
@@ -222,6 +487,23 @@ fn align_down(input: u64, reference: u64, align: u64) -> u64 {
     input - align_adjustment(input, reference, align)
 }
 
+// The number of output program headers and the size of the headers (including
+// any digests note) at the start of the first PT_LOAD.
+fn headers_size(
+    segments: usize,
+    preserved: usize,
+    ctx: Ctx,
+    config: &LayoutConfig,
+) -> (usize, u64) {
+    let note_size = config.note_size(segments);
+    let count = segments + 2 + preserved + note_size.map_or(0, |_| 1);
+
+    (
+        count,
+        first_load_header_size(count, ctx) + note_size.unwrap_or(0),
+    )
+}
+
 fn first_load_header_size(count: usize, ctx: Ctx) -> u64 {
     Header::size(&ctx) as u64 + program_header_size(count, ctx)
 }
@@ -233,6 +515,8 @@ fn program_header_size(count: usize, ctx: Ctx) -> u64 {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::arch::test::create_arch;
+    use crate::PAGE_SIZE;
     use itertools::Itertools;
 
     #[test]
@@ -240,7 +524,7 @@ mod test {
         let phdr = vec![make_phdr(25, 100)];
 
         let sut = LayoutStrategy::SpecifiedStart(100);
-        let out = sut.layout(phdr.iter(), new_ctx());
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
 
         // out[1] is the first PT_LOAD segment
         assert!(out[1].p_filesz >= PAGE_SIZE as u64);
@@ -251,7 +535,7 @@ mod test {
         let phdr = vec![make_phdr(1000, 100)];
 
         let sut = LayoutStrategy::SpecifiedStart(100);
-        let out = sut.layout(phdr.iter(), new_ctx());
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
 
         // out[1] is the first PT_LOAD segment
         assert!(out[1].p_filesz < PAGE_SIZE as u64);
@@ -262,7 +546,7 @@ mod test {
         let phdr = vec![make_phdr(1000, 100), make_phdr(1200, 50)];
 
         let sut = LayoutStrategy::SpecifiedStart(100);
-        let out = sut.layout(phdr.iter(), new_ctx());
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
 
         assert_eq!(out.len(), 4);
         assert_eq!(out[0].p_type, program_header::PT_PHDR);
@@ -276,7 +560,7 @@ mod test {
         let phdr = vec![make_phdr(1000, 100), make_phdr(1200, 50)];
 
         let sut = LayoutStrategy::SpecifiedStart(100);
-        let out = sut.layout(phdr.iter(), new_ctx());
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
 
         for ph in out {
             assert_eq!(ph.p_memsz, ph.p_filesz);
@@ -288,7 +572,7 @@ mod test {
         let phdr = vec![make_phdr(1000, 100), make_phdr(1200, 50)];
 
         let sut = LayoutStrategy::SpecifiedStart(100);
-        let out = sut.layout(phdr.iter(), new_ctx());
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
 
         for (l, r) in out.iter().tuple_windows() {
             assert!(l.p_type != program_header::PT_LOAD || l.p_vaddr <= r.p_vaddr);
@@ -300,7 +584,7 @@ mod test {
         let phdr = vec![make_phdr(1000, 100), make_phdr(1200, 50)];
 
         let sut = LayoutStrategy::SpecifiedStart(100);
-        let out = sut.layout(phdr.iter(), new_ctx());
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
 
         for (l, r) in out.iter().tuple_windows() {
             if l.p_type == program_header::PT_PHDR {
@@ -321,11 +605,54 @@ mod test {
         let start = 100;
 
         let sut = LayoutStrategy::SpecifiedStart(start);
-        let out = sut.layout(phdr.iter(), new_ctx());
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
 
         assert_eq!(out[1].p_paddr, start);
     }
 
+    #[test]
+    fn end_at_layout_ends_at_or_below_end() {
+        let phdr = [make_phdr(1000, 100), make_phdr(1200, 50)];
+        let end = 0x10_0000;
+
+        let sut = LayoutStrategy::EndAt { end, align: 0 };
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
+
+        let last = out.last().unwrap();
+        assert!(last.p_paddr + last.p_memsz <= end);
+        assert!(last.p_paddr + last.p_memsz > end - PAGE_SIZE as u64);
+    }
+
+    #[test]
+    fn end_at_layout_aligns_start_to_largest_alignment() {
+        let mut phdr = [make_phdr(1000, 100), make_phdr(1200, 50)];
+        phdr[1].p_align = 0x10000;
+
+        let sut = LayoutStrategy::EndAt {
+            end: 0x10_8000,
+            align: 0x2000,
+        };
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
+
+        assert_eq!(out[1].p_paddr, 0x10_0000);
+    }
+
+    #[test]
+    fn end_at_layout_gives_plenum() {
+        let phdr = [make_phdr(1000, 100), make_phdr(1200, 50)];
+
+        let sut = LayoutStrategy::EndAt {
+            end: 0x10_0000,
+            align: 0,
+        };
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
+
+        for (l, r) in out.iter().skip(1).tuple_windows() {
+            assert_eq!(l.p_paddr + l.p_filesz, r.p_paddr);
+            assert_eq!(l.p_offset + l.p_filesz, r.p_offset);
+        }
+    }
+
     #[test]
     fn from_input_layout_gives_phdr_and_load_segments() {
         let phdr = vec![
@@ -334,7 +661,7 @@ mod test {
         ];
 
         let sut = LayoutStrategy::FromInput;
-        let out = sut.layout(phdr.iter(), new_ctx());
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
 
         assert_eq!(out.len(), 4);
         assert_eq!(out[0].p_type, program_header::PT_PHDR);
@@ -351,7 +678,7 @@ mod test {
         ];
 
         let sut = LayoutStrategy::FromInput;
-        let out = sut.layout(phdr.iter(), new_ctx());
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
 
         for ph in out {
             assert_eq!(ph.p_memsz, ph.p_filesz);
@@ -366,7 +693,7 @@ mod test {
         ];
 
         let sut = LayoutStrategy::FromInput;
-        let out = sut.layout(phdr.iter(), new_ctx());
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
 
         for (l, r) in out.iter().tuple_windows() {
             assert!(l.p_type != program_header::PT_LOAD || l.p_vaddr <= r.p_vaddr);
@@ -381,7 +708,7 @@ mod test {
         ];
 
         let sut = LayoutStrategy::FromInput;
-        let out = sut.layout(phdr.iter(), new_ctx());
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
 
         for (l, r) in out.iter().tuple_windows() {
             if l.p_type == program_header::PT_PHDR {
@@ -407,16 +734,257 @@ mod test {
         ];
 
         let sut = LayoutStrategy::FromInput;
-        let out = sut.layout(phdr.iter(), new_ctx());
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
 
         assert_eq!(out[2].p_paddr, paddr1);
         assert_eq!(out[3].p_paddr, paddr2);
     }
 
-    fn new_ctx() -> Ctx {
+    #[cfg(feature = "integrity")]
+    #[test]
+    fn specified_start_layout_with_digests_gives_trailing_note_segment() {
+        let phdr = [make_phdr(1000, 100), make_phdr(1200, 50)];
+        let config = LayoutConfig::new().digests(true);
+
+        let sut = LayoutStrategy::SpecifiedStart(100);
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &config);
+
+        assert_eq!(out.len(), 5);
+        assert_eq!(out[4].p_type, program_header::PT_NOTE);
+        assert_eq!(out[4].p_filesz, config.note_size(2).unwrap());
+        assert_eq!(LayoutStrategy::out_segments(&out), 3);
+    }
+
+    #[cfg(feature = "integrity")]
+    #[test]
+    fn specified_start_layout_with_digests_places_note_within_first_load_segment() {
+        let phdr = [make_phdr(1000, 100), make_phdr(1200, 50)];
+        let config = LayoutConfig::new().digests(true);
+
+        let sut = LayoutStrategy::SpecifiedStart(100);
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &config);
+
+        // out[1] is the first PT_LOAD segment
+        assert!(out[4].p_offset >= out[0].p_offset + out[0].p_filesz);
+        assert!(out[4].p_offset + out[4].p_filesz <= out[1].p_filesz);
+        assert_eq!(out[4].p_paddr - out[1].p_paddr, out[4].p_offset);
+    }
+
+    #[test]
+    fn specified_start_layout_with_page_size_aligns_phdr_to_page_size() {
+        let phdr = [make_phdr(1000, 100), make_phdr(1200, 50)];
+        let config = LayoutConfig::new().page_size(0x4000);
+
+        let sut = LayoutStrategy::SpecifiedStart(100);
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &config);
+
+        assert_eq!(out[0].p_align, 0x4000);
+        assert_eq!(out[0].p_vaddr % 0x4000, out[0].p_offset % 0x4000);
+        assert_eq!(out[1].p_align, 0x4000);
+    }
+
+    #[test]
+    fn end_at_layout_with_align_aligns_start_and_first_load_segment() {
+        let phdr = [make_phdr(1000, 100), make_phdr(1200, 50)];
+        let config = LayoutConfig::new().align(0x20_0000);
+
+        let sut = LayoutStrategy::EndAt {
+            end: 0x50_8000,
+            align: 0,
+        };
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &config);
+
+        assert_eq!(out[1].p_paddr, 0x40_0000);
+        assert_eq!(out[1].p_align, 0x20_0000);
+        assert_eq!(out[1].p_vaddr % 0x20_0000, 0);
+    }
+
+    #[test]
+    fn specified_start_layout_rewrites_preserved_header_within_load() {
+        let phdr = [make_phdr(1000, 100), make_phdr(1200, 50)];
+        let preserved = [ProgramHeader {
+            p_type: program_header::PT_TLS,
+            p_vaddr: phdr[1].p_vaddr + 0x10,
+            p_filesz: 0x8,
+            p_memsz: 0x20,
+            ..ProgramHeader::new()
+        }];
+
+        let sut = LayoutStrategy::SpecifiedStart(100);
+        let out = sut.layout(phdr.iter(), &preserved, new_arch(), &LayoutConfig::new());
+
+        assert_eq!(out.len(), 5);
+        assert_eq!(out[4].p_type, program_header::PT_TLS);
+        assert_eq!(out[4].p_offset, out[3].p_offset + 0x10);
+        assert_eq!(out[4].p_paddr, out[3].p_paddr + 0x10);
+        assert_eq!(out[4].p_vaddr, preserved[0].p_vaddr);
+        assert_eq!(out[4].p_filesz, preserved[0].p_filesz);
+        assert_eq!(out[4].p_memsz, preserved[0].p_memsz);
+    }
+
+    #[test]
+    fn specified_start_layout_rewrites_preserved_tls_with_tbss_past_load() {
+        let phdr = [make_phdr(1000, 100), make_phdr(1200, 50)];
+        let preserved = [ProgramHeader {
+            p_type: program_header::PT_TLS,
+            p_vaddr: phdr[1].p_vaddr + 0x10,
+            p_filesz: 0x8,
+            p_memsz: 0x100,
+            ..ProgramHeader::new()
+        }];
+
+        let sut = LayoutStrategy::SpecifiedStart(100);
+        let out = sut.layout(phdr.iter(), &preserved, new_arch(), &LayoutConfig::new());
+
+        assert_eq!(out[4].p_offset, out[3].p_offset + 0x10);
+        assert_eq!(out[4].p_paddr, out[3].p_paddr + 0x10);
+        assert_eq!(out[4].p_memsz, preserved[0].p_memsz);
+    }
+
+    #[test]
+    fn specified_start_layout_places_preserved_header_only_within_load() {
+        let phdr = [make_phdr(1000, 100)];
+        let preserved = [
+            ProgramHeader {
+                p_type: program_header::PT_TLS,
+                p_vaddr: phdr[0].p_vaddr,
+                p_filesz: 0x10,
+                p_memsz: 0x1000,
+                ..ProgramHeader::new()
+            },
+            ProgramHeader {
+                p_type: program_header::PT_NOTE,
+                p_vaddr: phdr[0].p_vaddr + 0x800,
+                p_filesz: 0x10,
+                p_memsz: 0x10,
+                ..ProgramHeader::new()
+            },
+        ];
+
+        let sut = LayoutStrategy::SpecifiedStart(100);
+        let out = sut.layout(phdr.iter(), &preserved, new_arch(), &LayoutConfig::new());
+
+        assert_eq!(out[3].p_paddr, out[2].p_paddr);
+        assert_eq!(out[4].p_type, program_header::PT_NOTE);
+        assert_eq!(out[4].p_paddr, 0);
+        assert_eq!(out[4].p_offset, 0);
+    }
+
+    #[test]
+    fn specified_start_layout_keeps_preserved_header_without_contents() {
+        let phdr = [make_phdr(1000, 100)];
+        let preserved = [ProgramHeader {
+            p_type: program_header::PT_GNU_STACK,
+            p_offset: 0,
+            p_vaddr: 0,
+            p_filesz: 0,
+            p_memsz: 0,
+            p_flags: program_header::PF_R | program_header::PF_W,
+            p_align: 0x10,
+            p_paddr: 0,
+        }];
+
+        let sut = LayoutStrategy::SpecifiedStart(100);
+        let out = sut.layout(phdr.iter(), &preserved, new_arch(), &LayoutConfig::new());
+
+        assert_eq!(out[3], preserved[0]);
+        assert_eq!(LayoutStrategy::out_segments(&out), 2);
+    }
+
+    #[test]
+    fn elide_trailing_bss_stops_at_segment_with_contents() {
+        let phdr = [
+            ProgramHeader {
+                p_filesz: 25,
+                ..make_phdr(0, 100)
+            },
+            ProgramHeader {
+                p_filesz: 50,
+                ..make_phdr(100, 100)
+            },
+            make_phdr(200, 100),
+        ];
+        let mut out = LayoutStrategy::SpecifiedStart(0x8000).layout(
+            phdr.iter(),
+            &[],
+            new_arch(),
+            &LayoutConfig::new(),
+        );
+
+        LayoutStrategy::elide_trailing_bss(&mut out, &phdr);
+
+        let filesz: Vec<_> = out[2..].iter().map(|ph| ph.p_filesz).collect();
+        assert_eq!(filesz, [100, 50, 0]);
+        assert!(out[2..].iter().all(|ph| ph.p_memsz == 100));
+    }
+
+    #[test]
+    fn specified_start_layout_separating_permissions_pads_to_new_page() {
+        let phdr = [
+            ProgramHeader {
+                p_flags: program_header::PF_R | program_header::PF_X,
+                ..make_phdr(0, 100)
+            },
+            ProgramHeader {
+                p_flags: program_header::PF_R | program_header::PF_W,
+                p_align: 0x10,
+                ..make_phdr(PAGE_SIZE as u64, 50)
+            },
+        ];
+        let config = LayoutConfig::new().separate_permissions(true);
+
+        let sut = LayoutStrategy::SpecifiedStart(0x8000);
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &config);
+
+        assert_eq!(out[3].p_paddr % PAGE_SIZE as u64, 0);
+        assert_eq!(out[3].p_offset % PAGE_SIZE as u64, 0);
+        assert_eq!(out[2].p_offset + out[2].p_filesz, out[3].p_offset);
+    }
+
+    #[test]
+    fn specified_start_layout_separating_permissions_does_not_pad_same_permissions() {
+        let phdr = [
+            make_phdr(0, 100),
+            ProgramHeader {
+                p_align: 0x10,
+                ..make_phdr(PAGE_SIZE as u64, 50)
+            },
+        ];
+        let config = LayoutConfig::new().separate_permissions(true);
+
+        let sut = LayoutStrategy::SpecifiedStart(0x8000);
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &config);
+        let expected = sut.layout(phdr.iter(), &[], new_arch(), &LayoutConfig::new());
+
+        assert_ne!(out[3].p_paddr % PAGE_SIZE as u64, 0);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn specified_start_layout_separating_permissions_pads_aligned_segment_to_new_page() {
+        let phdr = [
+            ProgramHeader {
+                p_flags: program_header::PF_R | program_header::PF_X,
+                ..make_phdr(0, 0xe00)
+            },
+            ProgramHeader {
+                p_flags: program_header::PF_R | program_header::PF_W,
+                ..make_phdr(0xf00, 0x100)
+            },
+        ];
+        let config = LayoutConfig::new().separate_permissions(true);
+
+        let sut = LayoutStrategy::SpecifiedStart(0x8000);
+        let out = sut.layout(phdr.iter(), &[], new_arch(), &config);
+
+        assert_eq!(out[3].p_paddr % PAGE_SIZE as u64, 0xf00);
+        assert_eq!(out[3].p_paddr - out[2].p_paddr, 0x1f00);
+    }
+
+    fn new_arch() -> Arch {
         use goblin::container::{Container, Endian};
 
-        Ctx::new(Container::Big, Endian::Big)
+        create_arch(Container::Big, Endian::Big)
     }
 
     fn make_phdr(rel_offset: u64, memsz: u64) -> ProgramHeader {
@@ -0,0 +1,198 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use super::{Layout, LayoutStrategy};
+use crate::Result;
+use goblin::elf::{Header, ProgramHeader};
+use std::io::{Seek, SeekFrom, Write};
+
+const ZEROS_SIZE: usize = 4096;
+
+impl<'a> Layout<'a> {
+    /// Write the output represented by this layout to `sink` starting at the
+    /// current position of `sink`.
+    ///
+    /// Unlike [`output`][Layout::output] this does not need a buffer of
+    /// [`required_size`][Layout::required_size] bytes. The headers and the
+    /// segments are written directly to `sink` and the bss of each segment is
+    /// written from a small, fixed size buffer of zeros.
+    ///
+    /// # Errors
+    /// `write_to()` can return the following errors:
+    /// * `Error::Io`: writing to or seeking `sink` failed
+    pub fn write_to<W: Write + Seek>(&self, sink: &mut W) -> Result<()> {
+        let start = sink.stream_position()?;
+
+        for segment in 0..self.out_segments() {
+            let offset = self.out_phdr[LayoutStrategy::out_index(segment)].p_offset;
+            sink.seek(SeekFrom::Start(start + offset))?;
+            self.stream_segment(segment, self.segment_size(segment), sink)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the flat binary output represented by this layout to `sink`
+    /// starting at the current position of `sink`.
+    ///
+    /// This is the streaming equivalent of [`binary_output`][Layout::binary_output].
+    ///
+    /// # Errors
+    /// `write_binary_to()` can return the following errors:
+    /// * `Error::InvalidElf`: see [`binary_image`][Layout::binary_image]
+    /// * `Error::Io`: writing to or seeking `sink` failed
+    pub fn write_binary_to<W: Write + Seek>(&self, sink: &mut W) -> Result<()> {
+        let image = self.binary_image()?;
+        let start = sink.stream_position()?;
+
+        for segment in 1..self.out_segments() {
+            let paddr = self.out_phdr[LayoutStrategy::out_index(segment)].p_paddr;
+            sink.seek(SeekFrom::Start(start + paddr - image.load_address()))?;
+            self.stream_segment(segment, self.binary_segment_size(segment), sink)?;
+        }
+
+        Ok(())
+    }
+
+    // Write the contents of segment followed by zeros up to size.
+    fn stream_segment<W: Write>(&self, segment: usize, size: usize, sink: &mut W) -> Result<()> {
+        let written = if segment == 0 {
+            let ctx = self.arch.ctx();
+            let headers_size = Header::size(&ctx) + self.out_phdr.len() * ProgramHeader::size(&ctx);
+            #[cfg(feature = "integrity")]
+            let headers_size = LayoutStrategy::digests_note(&self.out_phdr)
+                .map_or(headers_size, |note| headers_size.max(note.file_range().end));
+            let mut headers = vec![0; headers_size];
+            self.write_segment(0, &mut headers)?;
+            sink.write_all(&headers)?;
+            headers.len()
+        } else {
+            let in_range = self.in_phdr[LayoutStrategy::in_index(segment)].file_range();
+            sink.write_all(&self.input[in_range.clone()])?;
+            in_range.len()
+        };
+
+        write_zeros(sink, size - written)
+    }
+}
+
+fn write_zeros<W: Write>(sink: &mut W, mut count: usize) -> Result<()> {
+    let zeros = [0; ZEROS_SIZE];
+
+    while count > 0 {
+        let len = count.min(zeros.len());
+        sink.write_all(&zeros[..len])?;
+        count -= len;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::test::create_arch;
+    use crate::layout::test::create_layout;
+    use crate::Error;
+    use goblin::container::{Container, Endian};
+    use std::io::{self, Cursor};
+
+    #[test]
+    fn layout_write_to_matches_output() {
+        let input = vec![0xdb; 0x20];
+        let sut = make_layout(&input);
+        let mut expected = vec![0; sut.required_size()];
+        sut.output(&mut expected).unwrap().write().unwrap();
+
+        let mut sink = Cursor::new(Vec::new());
+        sut.write_to(&mut sink)
+            .expect("write_to failed unexpectedly");
+
+        assert_eq!(sink.into_inner(), expected);
+    }
+
+    #[test]
+    fn layout_write_to_writes_bss_as_zeros() {
+        let input = vec![0xdb; 0x20];
+        let sut = make_layout(&input);
+
+        let mut sink = Cursor::new(vec![0xc0; sut.required_size()]);
+        sut.write_to(&mut sink)
+            .expect("write_to failed unexpectedly");
+
+        let output = sink.into_inner();
+        let bss = sut.out_phdr[3].p_offset as usize + 0x10;
+        for out_char in &output[bss..bss + 0x2000] {
+            assert_eq!(*out_char, 0);
+        }
+    }
+
+    #[test]
+    fn layout_write_to_writes_from_current_position() {
+        let input = vec![0xdb; 0x20];
+        let sut = make_layout(&input);
+        let mut expected = vec![0; sut.required_size()];
+        sut.output(&mut expected).unwrap().write().unwrap();
+
+        let mut sink = Cursor::new(Vec::new());
+        sink.write_all(&[0xc0; 0x10]).unwrap();
+        sut.write_to(&mut sink)
+            .expect("write_to failed unexpectedly");
+
+        assert_eq!(&sink.into_inner()[0x10..], &expected[..]);
+    }
+
+    #[test]
+    fn layout_write_binary_to_matches_binary_output() {
+        let input = vec![0xdb; 0x20];
+        let sut = make_layout(&input);
+        let image = sut.binary_image().unwrap();
+        let mut expected = vec![0xc0; image.size()];
+        sut.binary_output(&mut expected).unwrap().write().unwrap();
+
+        let mut sink = Cursor::new(Vec::new());
+        sut.write_binary_to(&mut sink)
+            .expect("write_binary_to failed unexpectedly");
+
+        assert_eq!(sink.into_inner(), expected);
+    }
+
+    #[test]
+    fn layout_write_to_with_failing_sink_is_error() {
+        let input = vec![0xdb; 0x20];
+        let sut = make_layout(&input);
+
+        let result = sut.write_to(&mut FailingSink);
+
+        assert_matches!(result, Err(Error::Io(_)));
+    }
+
+    struct FailingSink;
+
+    impl Write for FailingSink {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("sink failed"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FailingSink {
+        fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+            Ok(0)
+        }
+    }
+
+    fn make_layout(input: &[u8]) -> Layout<'_> {
+        let arch = create_arch(Container::Little, Endian::Little);
+
+        create_layout(arch, input, 0x1000, 0x1000, 0x8000)
+    }
+}
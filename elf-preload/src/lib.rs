@@ -8,7 +8,7 @@
 // #SPC-elfpreload
 
 //! Library to convert ELF files into a form that is suitable for loading with
-//! [`std::ptr::copy_nonoverlapping`].
+//! [`core::ptr::copy_nonoverlapping`].
 //!
 //! In general an arbitrary ELF file cannot be loaded by copying its bytes to
 //! its expected load address. This library transforms ELF files that complies with
@@ -26,28 +26,115 @@
 //! The rational for the split is that parsing and processing `Input` and writing
 //! the output with a collection of `OutputWriter` are potentially parallelizable.
 //! `Layout`, on the otherhand, is inherently serialized.
+//!
+//! When the output is only going to a file (or another [`std::io::Write`] and
+//! [`std::io::Seek`] sink) [`Layout::write_to`] streams it there without
+//! needing a buffer for the whole output.
+//!
+//! A `Layout` can also be written as a flat binary (a headerless memory image)
+//! for loaders that cannot accept an ELF file. [`BinaryImage`] describes where
+//! such an image is to be loaded.
+//!
+//! For flashing and JTAG tools, a `Layout` can also be written as Intel HEX or
+//! Motorola S-records (see [`Layout::write_ihex_to`] and
+//! [`Layout::write_srec_to`]). For U-Boot's `booti` and `bootz` the flat binary
+//! can be wrapped in a Linux boot image header (see [`BootImage`]), and for
+//! UEFI firmware a `Layout` can be written as a PE32+ EFI application (see
+//! [`Layout::write_efi_to`]).
+//!
+//! Since the output has neither section headers nor symbols, a `Layout` can
+//! also produce a companion debug ELF file (see [`Layout::debug_output`]) that
+//! has the symbols and DWARF sections of the input at the addresses of the
+//! output.
+//!
+//! With the `deflate` or `lz4` cargo features, a `Layout` can also be written
+//! as a compressed container (see [`Layout::compressed_output`]) that a
+//! bootloader can place in memory with the `core` only [`unpack`] function.
+//!
+//! The trailing bss of the output can be omitted to make it smaller (see
+//! [`LayoutConfig::elide_bss`]). The loader must then zero it with
+//! [`zero_bss`] after copying the output.
+//!
+//! For loaders that map the output with page granularity, segments with
+//! different permissions can be kept out of each other's pages (see
+//! [`LayoutConfig::separate_permissions`]).
+//!
+//! With the `integrity` cargo feature, the output can embed a digest of each of
+//! its segments (see [`LayoutConfig::digests`]) which [`verify_digests`] can
+//! later check against the image as it was loaded.
+//!
+//! An input whose segments are too sparse to be laid out as a single output
+//! can instead be laid out as one output for each dense run of its segments
+//! (see [`Input::layout_runs`]).
+//!
+//! Several images that are to be preloaded together can be placed relative to
+//! one another with a [`Planner`].
+//!
+//! On the other side, a bootloader can read an image that was preloaded with
+//! this library in place (without copying or allocating) with [`Preloaded`].
+//!
+//! [`verify_output`] checks that an output (from this or any other version of
+//! the library) satisfies each of the constraints on the output and reports
+//! those that it does not.
+//!
+//! # Features
+//! The `std` feature (enabled by default) adds the methods that write to a
+//! [`std::io::Write`] sink and implements [`std::error::Error`] for
+//! [`Error`]. Without it the library uses only `core` and `alloc`, so that
+//! `Input`, `Layout`, and `OutputWriter` can be used on target (e.g. by a
+//! bootloader).
+//!
+//! The `test-support` feature adds [`ElfBuilder`] for creating synthetic ELF
+//! executables to use in tests of `Layout` (in this or a downstream crate).
 
 // Note: The idea for the potentially parallelizable Input and OutputWriter with
 // an inherently serialized Layout in the middle is from Ian Lance Taylor's 20
 // part blog post on linkers, and specifically from https://www.airs.com/blog/archives/47
 
+#![cfg_attr(not(feature = "std"), no_std)]
 #![deny(missing_docs)]
 #![cfg_attr(not(test), deny(unsafe_code))]
 
+extern crate alloc;
+
 #[cfg(test)]
 #[macro_use]
 extern crate assert_matches;
 
 mod arch;
+mod bss;
+#[cfg(any(test, feature = "test-support"))]
+mod builder;
+#[cfg(any(feature = "deflate", feature = "lz4"))]
+mod compress;
 mod error;
 mod input;
+#[cfg(feature = "integrity")]
+mod integrity;
 mod layout;
 mod output;
+mod planner;
+mod preloaded;
+mod reloc;
+mod verify;
 
 pub use arch::Arch;
-pub use error::{BadElfError, Error, Result};
+pub use bss::zero_bss;
+#[cfg(feature = "test-support")]
+pub use builder::ElfBuilder;
+#[cfg(any(feature = "deflate", feature = "lz4"))]
+pub use compress::{unpack, Compression, UnpackError};
+pub use error::{BadElfError, Error, Result, ValidationError};
 pub use input::Input;
-pub use layout::{Layout, LayoutStrategy};
+#[cfg(feature = "integrity")]
+pub use integrity::verify_digests;
+#[cfg(feature = "std")]
+pub use layout::BootImage;
+pub use layout::{BinaryImage, Layout, LayoutConfig, LayoutStrategy};
 pub use output::OutputWriter;
+pub use planner::{Placement, Planner};
+pub use preloaded::{Preloaded, Segment, Segments};
+pub use verify::{verify_output, Constraint, Report, Violation};
 
+// The page size for architectures without a larger default page size.
 const PAGE_SIZE: usize = 4096;
@@ -0,0 +1,392 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+//! Command line interface to the elf-preload library.
+//!
+//! The `elf-preload` binary reads an input ELF file, lays it out using one of
+//! the [`LayoutStrategy`]'s, writes the output ELF file (or flat binary, Linux
+//! boot image, EFI application, Intel HEX, or S-records), and prints the
+//! program headers of the output to standard out.
+
+#[cfg(any(feature = "deflate", feature = "lz4"))]
+use elf_preload::Compression;
+use elf_preload::{BootImage, Error, Input, Layout, LayoutConfig, LayoutStrategy};
+use goblin::elf::program_header::{self, ProgramHeader};
+use std::convert::TryFrom;
+use std::error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::num::ParseIntError;
+use std::path::PathBuf;
+use std::process;
+use structopt::StructOpt;
+
+const EXIT_IO: i32 = 1;
+const EXIT_BAD_ELF: i32 = 2;
+const EXIT_INVALID_ELF: i32 = 3;
+const EXIT_OTHER: i32 = 4;
+
+/// Convert an ELF file into a form that can be loaded by copying the whole
+/// file to its physical load address.
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "elf-preload",
+    after_help = "EXIT CODES:\n    \
+                  0    success\n    \
+                  1    the input or output file could not be read or written\n    \
+                  2    the input is not a proper ELF file\n    \
+                  3    the input ELF file does not satisfy a required constraint\n    \
+                  4    any other error"
+)]
+struct Opt {
+    /// The input ELF file.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The file to which to write the output.
+    #[structopt(parse(from_os_str))]
+    output: PathBuf,
+
+    /// Keep the physical addresses of the segments in the input file.
+    #[structopt(
+        long = "from-input",
+        raw(conflicts_with_all = r#"&["start", "identity", "virt_offset", "high_half"]"#),
+        raw(required_unless_one = r#"&["start", "identity", "virt_offset", "high_half"]"#)
+    )]
+    from_input: bool,
+
+    /// Start the output at the given physical address (decimal or 0x prefixed hex).
+    #[structopt(
+        long = "start",
+        raw(conflicts_with_all = r#"&["identity", "virt_offset", "high_half"]"#),
+        parse(try_from_str = "parse_address")
+    )]
+    start: Option<u64>,
+
+    /// Use the virtual address of each segment as its physical address.
+    #[structopt(
+        long = "identity",
+        raw(conflicts_with_all = r#"&["virt_offset", "high_half"]"#)
+    )]
+    identity: bool,
+
+    /// Use the virtual address less the given offset (decimal or 0x prefixed
+    /// hex) as the physical address of each segment.
+    #[structopt(
+        long = "virt-offset",
+        conflicts_with = "high_half",
+        parse(try_from_str = "parse_address")
+    )]
+    virt_offset: Option<u64>,
+
+    /// Use the virtual address with the bits of the given mask (decimal or 0x
+    /// prefixed hex) cleared as the physical address of each segment.
+    #[structopt(long = "high-half", parse(try_from_str = "parse_address"))]
+    high_half: Option<u64>,
+
+    /// Relocate a position independent executable (static-pie) to the given base
+    /// address (decimal or 0x prefixed hex).
+    #[structopt(long = "base", parse(try_from_str = "parse_address"))]
+    base: Option<u64>,
+
+    /// Use the given page size instead of the default page size for the
+    /// architecture of the input (decimal or 0x prefixed hex).
+    #[structopt(long = "page-size", parse(try_from_str = "parse_address"))]
+    page_size: Option<u64>,
+
+    /// Align the first segment of the output to the given alignment (decimal
+    /// or 0x prefixed hex), e.g. 0x200000 for block mappings.
+    #[structopt(long = "align", parse(try_from_str = "parse_address"))]
+    align: Option<u64>,
+
+    /// Carry the program headers of the given type ("note", "tls",
+    /// "gnu-stack", or a number) from the input into the output.
+    #[structopt(
+        long = "preserve",
+        number_of_values = 1,
+        parse(try_from_str = "parse_program_header_type")
+    )]
+    preserve: Vec<u32>,
+
+    /// Keep the physical addresses of the segments in the input file and write
+    /// one output ELF file (OUTPUT.0, OUTPUT.1, ...) for each dense run of
+    /// segments instead of failing on a sparse input.
+    #[structopt(
+        long = "runs",
+        requires = "from_input",
+        raw(conflicts_with_all = r#"&["binary", "debug_output"]"#)
+    )]
+    runs: bool,
+
+    /// Omit the trailing bss of the last segments from the output ELF file. The
+    /// loader must zero it after copying the output.
+    #[structopt(long = "elide-bss", conflicts_with = "binary")]
+    elide_bss: bool,
+
+    /// Keep segments with different permissions out of each other's pages,
+    /// padding the output if needed (e.g. for seL4, which maps the rootserver
+    /// with page granularity).
+    #[structopt(long = "separate-permissions")]
+    separate_permissions: bool,
+
+    /// Write a flat binary (a headerless memory image) instead of an ELF file.
+    #[structopt(long = "binary")]
+    binary: bool,
+
+    /// Write a flat binary wrapped in a Linux boot image header ("arm64" for
+    /// booti or "arm32" for bootz) instead of an ELF file.
+    #[structopt(
+        long = "boot-image",
+        raw(conflicts_with_all = r#"&["binary", "runs", "ihex", "srec", "elide_bss"]"#),
+        parse(try_from_str = "parse_boot_image")
+    )]
+    boot_image: Option<BootImage>,
+
+    /// Write a PE32+ EFI application (for an aarch64 or x86_64 input) instead of
+    /// an ELF file.
+    #[structopt(
+        long = "efi",
+        raw(
+            conflicts_with_all = r#"&["binary", "runs", "ihex", "srec", "boot_image", "elide_bss"]"#
+        )
+    )]
+    efi: bool,
+
+    /// Write Intel HEX instead of an ELF file.
+    #[structopt(
+        long = "ihex",
+        raw(conflicts_with_all = r#"&["binary", "srec", "runs"]"#)
+    )]
+    ihex: bool,
+
+    /// Write Motorola S-records instead of an ELF file.
+    #[structopt(long = "srec", raw(conflicts_with_all = r#"&["binary", "runs"]"#))]
+    srec: bool,
+
+    /// Write a compressed container (with "deflate" or "lz4" compression)
+    /// instead of an ELF file.
+    #[cfg(any(feature = "deflate", feature = "lz4"))]
+    #[structopt(
+        long = "compress",
+        raw(conflicts_with_all = r#"&["binary", "runs", "ihex", "srec", "boot_image", "efi"]"#),
+        parse(try_from_str = "parse_compression")
+    )]
+    compress: Option<Compression>,
+
+    /// Embed a CRC32 and a SHA-256 digest of each segment in a note in the
+    /// output.
+    #[cfg(feature = "integrity")]
+    #[structopt(
+        long = "digests",
+        raw(conflicts_with_all = r#"&["binary", "boot_image", "efi"]"#)
+    )]
+    digests: bool,
+
+    /// Also write a debug ELF file with the symbols and DWARF sections of the
+    /// input and the segment addresses of the output.
+    #[structopt(long = "debug-output", parse(from_os_str))]
+    debug_output: Option<PathBuf>,
+}
+
+impl Opt {
+    fn strategy(&self) -> LayoutStrategy {
+        match (self.identity, self.start, self.virt_offset, self.high_half) {
+            (false, Some(start), _, _) => LayoutStrategy::SpecifiedStart(start),
+            (false, _, Some(offset), _) => LayoutStrategy::VirtOffset(offset),
+            (false, _, _, Some(mask)) => LayoutStrategy::HighHalf { mask },
+            (true, _, _, _) if !self.from_input => LayoutStrategy::Identity,
+            _ => LayoutStrategy::FromInput,
+        }
+    }
+
+    fn config(&self) -> LayoutConfig {
+        let mut config = LayoutConfig::new();
+        if let Some(page_size) = self.page_size {
+            config = config.page_size(page_size);
+        }
+        if let Some(align) = self.align {
+            config = config.align(align);
+        }
+        for p_type in &self.preserve {
+            config = config.preserve(*p_type);
+        }
+        config = config.elide_bss(self.elide_bss);
+        config = config.separate_permissions(self.separate_permissions);
+        #[cfg(feature = "integrity")]
+        let config = config.digests(self.digests);
+
+        config
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    if let Err(err) = run(&opt) {
+        eprintln!("elf-preload: {}", err);
+        let mut cause = err.source();
+        while let Some(inner) = cause {
+            eprintln!("    caused by: {}", inner);
+            cause = inner.source();
+        }
+        process::exit(exit_code(err.as_ref()));
+    }
+}
+
+fn run(opt: &Opt) -> Result<(), Box<dyn error::Error>> {
+    let bytes = fs::read(&opt.input)?;
+    let input = match opt.base {
+        Some(base) => Input::with_base(&bytes, base)?,
+        None => Input::new(&bytes)?,
+    };
+    if opt.runs {
+        return write_runs(opt, &input);
+    }
+    let layout = input.layout_with(opt.strategy(), &opt.config())?;
+
+    #[cfg(any(feature = "deflate", feature = "lz4"))]
+    {
+        if let Some(method) = opt.compress {
+            fs::write(&opt.output, layout.compressed_output(method)?)?;
+            print_segments(layout.program_headers());
+            return write_debug_output(opt, &layout);
+        }
+    }
+
+    let mut output = BufWriter::new(File::create(&opt.output)?);
+    if opt.binary {
+        let image = layout.binary_image()?;
+        layout.write_binary_to(&mut output)?;
+        output.flush()?;
+
+        print_segments(layout.program_headers());
+        println!();
+        println!("Load address: {:#x}", image.load_address());
+        println!("Entry offset: {:#x}", image.entry_offset());
+    } else if let Some(kind) = opt.boot_image {
+        layout.write_boot_image_to(kind, &mut output)?;
+        output.flush()?;
+
+        print_segments(layout.program_headers());
+    } else if opt.efi {
+        layout.write_efi_to(&mut output)?;
+        output.flush()?;
+
+        print_segments(layout.program_headers());
+    } else {
+        if opt.ihex {
+            layout.write_ihex_to(&mut output)?;
+        } else if opt.srec {
+            layout.write_srec_to(&mut output)?;
+        } else {
+            layout.write_to(&mut output)?;
+        }
+        output.flush()?;
+
+        print_segments(layout.program_headers());
+    }
+
+    write_debug_output(opt, &layout)
+}
+
+fn write_runs(opt: &Opt, input: &Input) -> Result<(), Box<dyn error::Error>> {
+    for (run, layout) in input.layout_runs(&opt.config())?.iter().enumerate() {
+        let mut path = opt.output.clone().into_os_string();
+        path.push(format!(".{}", run));
+        let mut output = BufWriter::new(File::create(&path)?);
+        layout.write_to(&mut output)?;
+        output.flush()?;
+
+        let range = layout.paddr_range();
+        if run > 0 {
+            println!();
+        }
+        println!("Run {}: {:#x}..{:#x}", run, range.start, range.end);
+        print_segments(layout.program_headers());
+    }
+
+    Ok(())
+}
+
+fn write_debug_output(opt: &Opt, layout: &Layout) -> Result<(), Box<dyn error::Error>> {
+    if let Some(ref path) = opt.debug_output {
+        fs::write(path, layout.debug_output()?)?;
+    }
+
+    Ok(())
+}
+
+fn exit_code(err: &(dyn error::Error + 'static)) -> i32 {
+    match err.downcast_ref::<Error>() {
+        Some(Error::BadElf(_)) => EXIT_BAD_ELF,
+        Some(Error::InvalidElf(_)) => EXIT_INVALID_ELF,
+        Some(Error::Io(_)) => EXIT_IO,
+        Some(_) => EXIT_OTHER,
+        None if err.downcast_ref::<std::io::Error>().is_some() => EXIT_IO,
+        None => EXIT_OTHER,
+    }
+}
+
+fn parse_address(src: &str) -> Result<u64, ParseIntError> {
+    if src.starts_with("0x") || src.starts_with("0X") {
+        u64::from_str_radix(&src[2..], 16)
+    } else {
+        src.parse()
+    }
+}
+
+fn parse_program_header_type(src: &str) -> Result<u32, String> {
+    match src {
+        "note" => Ok(program_header::PT_NOTE),
+        "tls" => Ok(program_header::PT_TLS),
+        "gnu-stack" => Ok(program_header::PT_GNU_STACK),
+        _ => parse_address(src)
+            .ok()
+            .and_then(|p_type| u32::try_from(p_type).ok())
+            .ok_or_else(|| format!("unsupported program header type: {}", src)),
+    }
+}
+
+fn parse_boot_image(src: &str) -> Result<BootImage, String> {
+    match src {
+        "arm64" => Ok(BootImage::Arm64),
+        "arm32" => Ok(BootImage::Arm32),
+        _ => Err(format!("unsupported boot image header: {}", src)),
+    }
+}
+
+#[cfg(any(feature = "deflate", feature = "lz4"))]
+fn parse_compression(src: &str) -> Result<Compression, String> {
+    match src {
+        #[cfg(feature = "deflate")]
+        "deflate" => Ok(Compression::Deflate),
+        #[cfg(feature = "lz4")]
+        "lz4" => Ok(Compression::Lz4),
+        _ => Err(format!("unsupported compression method: {}", src)),
+    }
+}
+
+fn print_segments(phdrs: &[ProgramHeader]) {
+    println!(
+        "{:<8} {:>10} {:>18} {:>18} {:>10} Flg",
+        "Type", "Offset", "PhysAddr", "VirtAddr", "FileSiz"
+    );
+    for phdr in phdrs {
+        println!(
+            "{:<8} {:#010x} {:#018x} {:#018x} {:#010x} {}{}{}",
+            program_header::pt_to_str(phdr.p_type).trim_start_matches("PT_"),
+            phdr.p_offset,
+            phdr.p_paddr,
+            phdr.p_vaddr,
+            phdr.p_filesz,
+            if phdr.is_read() { "R" } else { " " },
+            if phdr.is_write() { "W" } else { " " },
+            if phdr.is_executable() { "E" } else { " " },
+        );
+    }
+}
@@ -7,18 +7,27 @@
 // except according to those terms
 
 use crate::{Layout, Result};
-use std::ops::Range;
+use core::ops::Range;
 
 /// A potentially parallelizable writer for the output file. Created by the
-/// [`output`][Layout::output] method.
-pub struct OutputWriter<'a, 'b>(ElfWriter<'a, 'b, Layout<'a>>);
+/// [`output`][Layout::output] or [`binary_output`][Layout::binary_output]
+/// methods.
+pub struct OutputWriter<'a, 'b>(ElfWriter<'b, Format<'a>>);
 
 impl<'a, 'b> OutputWriter<'a, 'b> {
     pub(crate) fn new(layout: &'a Layout<'a>, output: &'b mut [u8]) -> OutputWriter<'a, 'b> {
         OutputWriter(ElfWriter {
             output,
             segments: 0..layout.out_segments(),
-            writer: layout,
+            writer: Format::Elf(layout),
+        })
+    }
+
+    pub(crate) fn new_binary(layout: &'a Layout<'a>, output: &'b mut [u8]) -> OutputWriter<'a, 'b> {
+        OutputWriter(ElfWriter {
+            output,
+            segments: 1..layout.out_segments(), // the headers are not part of a binary
+            writer: Format::Binary(layout),
         })
     }
 
@@ -37,7 +46,8 @@ impl<'a, 'b> OutputWriter<'a, 'b> {
 
     /// Write the portion of the output represented by this `OutputWriter` to the
     /// corresponding sub-slice of the output bytes passed to the
-    /// [`output`][Layout::output] method.
+    /// [`output`][Layout::output] or [`binary_output`][Layout::binary_output]
+    /// method.
     pub fn write(&mut self) -> Result<()> {
         self.0.write()
     }
@@ -48,23 +58,46 @@ trait SegmentWriter {
     fn write_segment<'b>(&self, segment: usize, output: &'b mut [u8]) -> Result<()>;
 }
 
-impl<'a> SegmentWriter for Layout<'a> {
+impl<S: SegmentWriter> SegmentWriter for &S {
     fn segment_size(&self, segment: usize) -> usize {
-        self.segment_size(segment)
+        (*self).segment_size(segment)
+    }
+
+    fn write_segment(&self, segment: usize, output: &mut [u8]) -> Result<()> {
+        (*self).write_segment(segment, output)
     }
+}
 
-    fn write_segment<'b>(&self, segment: usize, output: &'b mut [u8]) -> Result<()> {
-        self.write_segment(segment, output)
+/// The format of the output for an `OutputWriter`.
+#[derive(Clone, Copy)]
+enum Format<'a> {
+    Elf(&'a Layout<'a>),
+    Binary(&'a Layout<'a>),
+}
+
+impl<'a> SegmentWriter for Format<'a> {
+    fn segment_size(&self, segment: usize) -> usize {
+        match self {
+            Format::Elf(layout) => layout.segment_size(segment),
+            Format::Binary(layout) => layout.binary_segment_size(segment),
+        }
+    }
+
+    fn write_segment(&self, segment: usize, output: &mut [u8]) -> Result<()> {
+        match self {
+            Format::Elf(layout) => layout.write_segment(segment, output),
+            Format::Binary(layout) => layout.write_binary_segment(segment, output),
+        }
     }
 }
 
-struct ElfWriter<'a, 'b, S> {
+struct ElfWriter<'b, S> {
     segments: Range<usize>,
-    writer: &'a S,
+    writer: S,
     output: &'b mut [u8],
 }
 
-impl<'a, 'b, S: SegmentWriter> ElfWriter<'a, 'b, S> {
+impl<'b, S: SegmentWriter + Copy> ElfWriter<'b, S> {
     fn split(self) -> (Self, Option<Self>) {
         if self.segments.len() <= 1 {
             (self, None)
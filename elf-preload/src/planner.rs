@@ -0,0 +1,262 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use crate::{Error, Input, Layout, LayoutConfig, LayoutStrategy, Result, ValidationError};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// The rule for placing an image in physical memory relative to the other
+/// images in a [`Planner`].
+///
+/// Images are referred to by the index returned from [`Planner::add`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    /// Keep the physical addresses of the segments in the input file (i.e.
+    /// [`LayoutStrategy::FromInput`]).
+    AnchorAtInput,
+
+    /// Start the image at the first address aligned to `align` that follows
+    /// the end of `image`. The start is never aligned to less than the output
+    /// alignment of the image (see [`LayoutConfig::align`]).
+    After {
+        /// The image that this image follows.
+        image: usize,
+        /// The alignment of the start of this image.
+        align: u64,
+    },
+
+    /// Start the image at the highest address aligned to `align` such that this
+    /// image ends at or before the start of `image` (i.e.
+    /// [`LayoutStrategy::EndAt`]).
+    EndBefore {
+        /// The image that this image precedes.
+        image: usize,
+        /// The alignment of the start of this image.
+        align: u64,
+    },
+}
+
+/// A planner for the physical layout of several images that are to be
+/// preloaded together (e.g. a bootloader, a microkernel and a rootserver).
+///
+/// Each image is an [`Input`] with a [`Placement`] rule and a
+/// [`LayoutConfig`] for its output file. The planner works out
+/// the [`Layout`] of each image and verifies that the images neither overlap
+/// one another nor extend outside of the RAM regions given to the planner.
+#[derive(Debug)]
+pub struct Planner<'a> {
+    ram: Vec<Range<u64>>,
+    images: Vec<(&'a Input<'a>, Placement, LayoutConfig)>,
+}
+
+impl<'a> Planner<'a> {
+    /// Create a new `Planner` for images that must fit within the given RAM
+    /// regions (as ranges of physical addresses).
+    pub fn new(ram: Vec<Range<u64>>) -> Self {
+        Planner {
+            ram,
+            images: Vec::new(),
+        }
+    }
+
+    /// Add an image to be placed according to `placement` and return its index.
+    ///
+    /// The image is laid out with the default options (see
+    /// [`layout`][Input::layout]).
+    pub fn add(&mut self, input: &'a Input<'a>, placement: Placement) -> usize {
+        self.add_with(input, placement, LayoutConfig::default())
+    }
+
+    /// Add an image to be placed according to `placement` and laid out with
+    /// the given options (see [`layout_with`][Input::layout_with]) and return
+    /// its index.
+    pub fn add_with(
+        &mut self,
+        input: &'a Input<'a>,
+        placement: Placement,
+        config: LayoutConfig,
+    ) -> usize {
+        self.images.push((input, placement, config));
+        self.images.len() - 1
+    }
+
+    /// Work out the layout of each of the images in the order in which they
+    /// were added.
+    ///
+    /// # Errors
+    /// `plan()` can return the following errors:
+    /// * `Error::InvalidElf`: see [`layout_with`][Input::layout_with]
+    /// * `Error::InvalidElf`: a placement refers to an unknown image or the
+    ///   placements refer to each other in a cycle, an image does not fit before
+    ///   the image it must precede (see [`layout`][Input::layout]), an image
+    ///   cannot be aligned after the image it must follow, two images overlap,
+    ///   or an image is not entirely within one of the RAM regions
+    pub fn plan(&self) -> Result<Vec<Layout<'a>>> {
+        let mut layouts: Vec<Option<Layout<'a>>> = self.images.iter().map(|_| None).collect();
+
+        let mut remaining = self.images.len();
+        while remaining > 0 {
+            let mut placed = 0;
+            for (index, (input, placement, config)) in self.images.iter().enumerate() {
+                if layouts[index].is_some() {
+                    continue;
+                }
+                if let Some(layout) = self.place(index, input, *placement, config, &layouts)? {
+                    layouts[index] = Some(layout);
+                    placed += 1;
+                }
+            }
+
+            if placed == 0 {
+                return Err(ValidationError::PlacementCycle.into());
+            }
+            remaining -= placed;
+        }
+
+        let layouts: Vec<_> = layouts.into_iter().flatten().collect();
+        self.verify_in_ram(&layouts)?;
+        verify_no_overlap(&layouts)?;
+
+        Ok(layouts)
+    }
+
+    // Returns None if the image this one is placed relative to is not yet placed.
+    fn place(
+        &self,
+        index: usize,
+        input: &'a Input<'a>,
+        placement: Placement,
+        config: &LayoutConfig,
+        layouts: &[Option<Layout<'a>>],
+    ) -> Result<Option<Layout<'a>>> {
+        use Placement::*;
+
+        let relative_to = |image: usize| {
+            if image == index || image >= layouts.len() {
+                Err(Error::from(ValidationError::InvalidImageReference {
+                    index,
+                    image,
+                }))
+            } else {
+                Ok(layouts[image].as_ref().map(Layout::paddr_range))
+            }
+        };
+
+        match placement {
+            AnchorAtInput => input
+                .layout_with(LayoutStrategy::FromInput, config)
+                .map(Some),
+
+            After { image, align } => match relative_to(image)? {
+                Some(range) => {
+                    let start =
+                        align_up(range.end, align).ok_or(ValidationError::ImageNotAlignable {
+                            index,
+                            image,
+                            align,
+                        })?;
+                    input
+                        .layout_with(LayoutStrategy::SpecifiedStart(start), config)
+                        .map(Some)
+                }
+                None => Ok(None),
+            },
+
+            EndBefore { image, align } => match relative_to(image)? {
+                Some(range) => input
+                    .layout_with(
+                        LayoutStrategy::EndAt {
+                            end: range.start,
+                            align,
+                        },
+                        config,
+                    )
+                    .map(Some),
+                None => Ok(None),
+            },
+        }
+    }
+
+    fn verify_in_ram(&self, layouts: &[Layout<'a>]) -> Result<()> {
+        for (index, layout) in layouts.iter().enumerate() {
+            let range = layout.paddr_range();
+            let in_ram = self
+                .ram
+                .iter()
+                .any(|ram| range.start >= ram.start && range.end <= ram.end);
+
+            if !in_ram {
+                return Err(ValidationError::ImageOutsideRam {
+                    index,
+                    start: range.start,
+                    end: range.end,
+                }
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn verify_no_overlap(layouts: &[Layout]) -> Result<()> {
+    let mut ranges: Vec<_> = layouts
+        .iter()
+        .map(Layout::paddr_range)
+        .enumerate()
+        .collect();
+    ranges.sort_by_key(|(_, range)| range.start);
+
+    for pair in ranges.windows(2) {
+        let (index1, ref range1) = pair[0];
+        let (index2, ref range2) = pair[1];
+        if range2.start < range1.end {
+            return Err(ValidationError::OverlappingImages {
+                first: index1.min(index2),
+                second: index1.max(index2),
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+// Returns None if the aligned address is past the end of the address space.
+fn align_up(addr: u64, align: u64) -> Option<u64> {
+    addr.checked_add(align.max(1) - 1)
+        .map(|addr| align_down(addr, align))
+}
+
+fn align_down(addr: u64, align: u64) -> u64 {
+    addr - addr % align.max(1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn align_up_rounds_up_to_alignment() {
+        assert_eq!(align_up(0x1001, 0x1000), Some(0x2000));
+        assert_eq!(align_up(0x1000, 0x1000), Some(0x1000));
+        assert_eq!(align_up(0x1001, 0), Some(0x1001));
+    }
+
+    #[test]
+    fn align_up_past_end_of_address_space_is_none() {
+        assert_eq!(align_up(u64::MAX - 0x10, 0x1000), None);
+    }
+
+    #[test]
+    fn align_down_rounds_down_to_alignment() {
+        assert_eq!(align_down(0x1fff, 0x1000), 0x1000);
+        assert_eq!(align_down(0x1000, 0x1000), 0x1000);
+        assert_eq!(align_down(0x1fff, 0), 0x1fff);
+    }
+}
@@ -0,0 +1,474 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use crate::{Error, Result, ValidationError};
+use alloc::format;
+use core::ops::Range;
+use goblin::container::Ctx;
+use goblin::elf::header::{self, header32, header64};
+use goblin::elf::{program_header, Header, ProgramHeader};
+use goblin::error::Error as GoblinError;
+use scroll::Pread;
+
+/// A view of an image produced by elf-preload (e.g. one that a bootloader has
+/// found in memory) that reads its program headers in place.
+///
+/// A `Preloaded` neither copies nor allocates. It checks that the image
+/// satisfies the constraints on the output of elf-preload when it is created:
+/// the first program header is the PT_PHDR, it is followed only by PT_LOAD
+/// program headers (and then any preserved program headers and the segment
+/// digests note), the PT_LOAD segments cover the whole file from the ELF
+/// header onwards, and their file size and memory size are equal (unless the
+/// trailing bss was elided).
+#[derive(Debug, Clone, Copy)]
+pub struct Preloaded<'a> {
+    image: &'a [u8],
+    ctx: Ctx,
+    entry: u64,
+    phoff: usize,
+    loads_end: usize,
+}
+
+/// A loadable segment of a [`Preloaded`] image. Created by the
+/// [`segments`][Preloaded::segments] method.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment<'a> {
+    paddr: u64,
+    vaddr: u64,
+    mem_size: u64,
+    flags: u32,
+    contents: &'a [u8],
+}
+
+/// An iterator over the loadable segments of a [`Preloaded`] image. Created by
+/// the [`segments`][Preloaded::segments] method.
+#[derive(Debug, Clone)]
+pub struct Segments<'a> {
+    preloaded: Preloaded<'a>,
+    index: Range<usize>,
+}
+
+impl<'a> Preloaded<'a> {
+    /// Create a view of the image in `image`, which starts with the ELF header.
+    ///
+    /// `image` must extend to the end of the file contents of the last loadable
+    /// segment (it may be the whole output file or the memory into which the
+    /// output was loaded).
+    ///
+    /// # Errors
+    /// `new()` can return the following errors:
+    /// * `Error::BadElf`: `image` does not start with an ELF header and program
+    ///   headers
+    /// * `Error::InvalidElf`: the image does not satisfy the constraints on the
+    ///   output of elf-preload
+    /// * `Error::OutputTooSmall`: `image` does not extend to the end of the
+    ///   file contents of the last loadable segment
+    pub fn new(image: &'a [u8]) -> Result<Self> {
+        let header: Header = image.pread(0)?;
+        let ctx = Ctx::new(header.container()?, header.endianness()?);
+        let phoff = header.e_phoff as usize;
+        let phnum = header.e_phnum as usize;
+        let preloaded = Preloaded {
+            image,
+            ctx,
+            entry: header.e_entry,
+            phoff,
+            loads_end: 1,
+        };
+
+        let loads_end = preloaded.verify_program_headers(phnum)?;
+        let headers_end = phoff + phnum * ProgramHeader::size(&ctx);
+        preloaded.verify_loads(1..loads_end, headers_end)?;
+
+        Ok(Preloaded {
+            loads_end,
+            ..preloaded
+        })
+    }
+
+    /// Create a view of the image that starts (with the ELF header) at `image`.
+    ///
+    /// The length of the image is taken from its program headers.
+    ///
+    /// # Safety
+    /// `image` must point to an image that is valid for reads from the start of
+    /// the ELF header to the end of the file contents of the last loadable
+    /// segment, and the image must not be modified for the lifetime `'a`. If it
+    /// is not such an image it must still be valid for reads of the 16 byte ELF
+    /// identification and, if that identifies an ELF32 or an ELF64 file, of the
+    /// 52 or 64 byte ELF header and of what that header describes as the
+    /// program headers.
+    ///
+    /// # Errors
+    /// `from_ptr()` can return the same errors as [`new`][Preloaded::new].
+    #[allow(unsafe_code)]
+    pub unsafe fn from_ptr(image: *const u8) -> Result<Self> {
+        let ident = core::slice::from_raw_parts(image, header::SIZEOF_IDENT);
+        let header_size = match ident[header::EI_CLASS] {
+            header::ELFCLASS32 => header32::SIZEOF_EHDR,
+            header::ELFCLASS64 => header64::SIZEOF_EHDR,
+            class => {
+                return Err(GoblinError::Malformed(format!("invalid ELF class {:x}", class)).into())
+            }
+        };
+        let header: Header = core::slice::from_raw_parts(image, header_size).pread(0)?;
+        let ctx = Ctx::new(header.container()?, header.endianness()?);
+        let phoff = header.e_phoff as usize;
+        let headers_end = phoff + header.e_phnum as usize * ProgramHeader::size(&ctx);
+        let headers = core::slice::from_raw_parts(image, headers_end);
+
+        let mut len = headers_end;
+        for index in 0..header.e_phnum as usize {
+            let ph: ProgramHeader =
+                headers.pread_with(phoff + index * ProgramHeader::size(&ctx), ctx)?;
+            if ph.p_type == program_header::PT_LOAD {
+                len = len.max(ph.p_offset.saturating_add(ph.p_filesz) as usize);
+            }
+        }
+
+        Self::new(core::slice::from_raw_parts(image, len))
+    }
+
+    /// The entry point (a virtual address) of the image.
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// The range of physical addresses into which the image is to be loaded,
+    /// including the bss of each segment.
+    pub fn paddr_range(&self) -> Range<u64> {
+        // the end of each segment was verified to fit when the Preloaded was
+        // created
+        let start = self.segments().map(|s| s.paddr()).min().unwrap_or(0);
+        let end = self
+            .segments()
+            .map(|s| s.paddr() + s.mem_size())
+            .max()
+            .unwrap_or(0);
+
+        start..end
+    }
+
+    /// An iterator over the loadable segments of the image, starting with the
+    /// one that holds the ELF header and program headers.
+    pub fn segments(&self) -> Segments<'a> {
+        Segments {
+            preloaded: *self,
+            index: 1..self.loads_end,
+        }
+    }
+
+    fn program_header(&self, index: usize) -> Result<ProgramHeader> {
+        let offset = self.phoff + index * ProgramHeader::size(&self.ctx);
+
+        Ok(self.image.pread_with(offset, self.ctx)?)
+    }
+
+    // The PT_PHDR is first, then the PT_LOAD's, then the others. Returns the
+    // index after the last PT_LOAD.
+    // #SPC-elfpreload.ptphdr
+    // #SPC-elfpreload.ptload
+    fn verify_program_headers(&self, phnum: usize) -> Result<usize> {
+        if phnum == 0 || self.program_header(0)?.p_type != program_header::PT_PHDR {
+            return Err(ValidationError::NoProgramHeaderSegment.into());
+        }
+
+        let mut end = 1;
+        while end < phnum && self.program_header(end)?.p_type == program_header::PT_LOAD {
+            end += 1;
+        }
+        if end == 1 {
+            return Err(ValidationError::NoLoadableSegments.into());
+        }
+        for index in end..phnum {
+            let p_type = self.program_header(index)?.p_type;
+            if p_type == program_header::PT_LOAD || p_type == program_header::PT_PHDR {
+                return Err(ValidationError::UnexpectedProgramHeader { index, p_type }.into());
+            }
+        }
+
+        Ok(end)
+    }
+
+    // #SPC-elfpreload.nobss
+    // #SPC-elfpreload.plenum
+    fn verify_loads(&self, loads: Range<usize>, headers_end: usize) -> Result<()> {
+        let mut next_offset = 0;
+        let mut next_paddr = 0;
+        let mut bss = None;
+
+        for index in loads {
+            let ph = self.program_header(index)?;
+            if ph.p_offset != next_offset
+                || ph.p_paddr < next_paddr
+                || (index == 1 && ph.p_filesz < headers_end as u64)
+            {
+                return Err(ValidationError::NotPlenum { index }.into());
+            }
+            if ph.p_filesz > ph.p_memsz {
+                return Err(ValidationError::FileSizeExceedsMemorySize {
+                    index,
+                    filesz: ph.p_filesz,
+                    memsz: ph.p_memsz,
+                }
+                .into());
+            }
+            match bss {
+                Some(index) if ph.p_filesz > 0 => {
+                    return Err(ValidationError::InteriorBss { index }.into())
+                }
+                None if ph.p_filesz < ph.p_memsz => bss = Some(index),
+                _ => {}
+            }
+            let (offset_end, paddr_end) = match (
+                ph.p_offset.checked_add(ph.p_memsz),
+                ph.p_paddr.checked_add(ph.p_memsz),
+            ) {
+                (Some(offset_end), Some(paddr_end)) => (offset_end, paddr_end),
+                _ => return Err(ValidationError::NotPlenum { index }.into()),
+            };
+            if (self.image.len() as u64) < ph.p_offset + ph.p_filesz {
+                return Err(Error::OutputTooSmall);
+            }
+
+            next_offset = offset_end;
+            next_paddr = paddr_end;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Segment<'a> {
+    /// The physical address at which to load the segment.
+    pub fn paddr(&self) -> u64 {
+        self.paddr
+    }
+
+    /// The virtual address of the segment.
+    pub fn vaddr(&self) -> u64 {
+        self.vaddr
+    }
+
+    /// The size of the segment in memory. The part of the segment after its
+    /// contents (if any) is bss to be zeroed.
+    pub fn mem_size(&self) -> u64 {
+        self.mem_size
+    }
+
+    /// The contents of the segment within the image.
+    pub fn contents(&self) -> &'a [u8] {
+        self.contents
+    }
+
+    /// Whether the segment is readable.
+    pub fn is_read(&self) -> bool {
+        self.flags & program_header::PF_R != 0
+    }
+
+    /// Whether the segment is writable.
+    pub fn is_write(&self) -> bool {
+        self.flags & program_header::PF_W != 0
+    }
+
+    /// Whether the segment is executable.
+    pub fn is_executable(&self) -> bool {
+        self.flags & program_header::PF_X != 0
+    }
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = Segment<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.index.next()?;
+        // the program headers were verified when the Preloaded was created
+        let ph = self.preloaded.program_header(index).ok()?;
+        let start = ph.p_offset as usize;
+
+        Some(Segment {
+            paddr: ph.p_paddr,
+            vaddr: ph.p_vaddr,
+            mem_size: ph.p_memsz,
+            flags: ph.p_flags,
+            contents: &self.preloaded.image[start..start + ph.p_filesz as usize],
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.index.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use goblin::container::{Container, Endian};
+    use goblin::elf::header;
+    use scroll::Pwrite;
+
+    #[test]
+    fn preloaded_reads_segments_in_place() {
+        let image = create_image(&[load(0, 0x100, 0x100), load(0x100, 0x20, 0x20)]);
+
+        let sut = Preloaded::new(&image).unwrap();
+
+        let segments: Vec<_> = sut.segments().collect();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].paddr(), 0x8100);
+        assert_eq!(segments[1].contents(), &image[0x100..0x120]);
+        assert!(segments[1].is_executable());
+        assert_eq!(sut.paddr_range(), 0x8000..0x8120);
+        assert_eq!(sut.entry(), 0x8100);
+    }
+
+    #[test]
+    fn preloaded_from_ptr_finds_image_length() {
+        let image = create_image(&[load(0, 0x100, 0x100), load(0x100, 0x20, 0x80)]);
+
+        #[allow(unsafe_code)]
+        let sut = unsafe { Preloaded::from_ptr(image.as_ptr()) }.unwrap();
+
+        let last = sut.segments().last().unwrap();
+        assert_eq!(last.contents().len(), 0x20);
+        assert_eq!(last.mem_size(), 0x80);
+    }
+
+    #[test]
+    fn preloaded_with_gap_in_file_is_error() {
+        let image = create_image(&[load(0, 0x100, 0x100), load(0x110, 0x20, 0x20)]);
+
+        let result = Preloaded::new(&image);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::NotPlenum { index: 2 }))
+        );
+    }
+
+    #[test]
+    fn preloaded_with_segment_past_end_of_address_space_is_error() {
+        let ctx = Ctx::new(Container::Big, Endian::Little);
+        let image = create_image_with(
+            ctx,
+            &[
+                load(0, 0x100, 0x100),
+                ProgramHeader {
+                    p_paddr: u64::MAX - 0x10,
+                    ..load(0x100, 0x20, 0x20)
+                },
+            ],
+        );
+
+        let result = Preloaded::new(&image);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::NotPlenum { index: 2 }))
+        );
+    }
+
+    #[test]
+    fn preloaded_from_ptr_with_bad_class_is_error() {
+        let mut ident = [0; header::SIZEOF_IDENT];
+        ident[..header::SELFMAG].copy_from_slice(header::ELFMAG);
+        ident[header::EI_CLASS] = 3;
+
+        #[allow(unsafe_code)]
+        let result = unsafe { Preloaded::from_ptr(ident.as_ptr()) };
+
+        assert_matches!(result, Err(Error::BadElf(_)));
+    }
+
+    #[test]
+    fn preloaded_with_interior_bss_is_error() {
+        let image = create_image(&[load(0, 0x100, 0x180), load(0x180, 0x20, 0x20)]);
+
+        let result = Preloaded::new(&image);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::InteriorBss { index: 1 }))
+        );
+    }
+
+    #[test]
+    fn preloaded_without_phdr_first_is_error() {
+        let mut image = create_image(&[load(0, 0x100, 0x100), load(0x100, 0x20, 0x20)]);
+        let ctx = Ctx::new(Container::Little, Endian::Little);
+        image
+            .pwrite_with(program_header::PT_NOTE, 52, ctx.le)
+            .unwrap();
+
+        let result = Preloaded::new(&image);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::NoProgramHeaderSegment))
+        );
+    }
+
+    #[test]
+    fn preloaded_with_short_image_is_error() {
+        let image = create_image(&[load(0, 0x100, 0x100), load(0x100, 0x20, 0x20)]);
+
+        let result = Preloaded::new(&image[..0x110]);
+
+        assert_matches!(result, Err(Error::OutputTooSmall));
+    }
+
+    fn load(offset: u64, filesz: u64, memsz: u64) -> ProgramHeader {
+        ProgramHeader {
+            p_type: program_header::PT_LOAD,
+            p_flags: program_header::PF_R | program_header::PF_X,
+            p_offset: offset,
+            p_vaddr: 0x8000 + offset,
+            p_paddr: 0x8000 + offset,
+            p_filesz: filesz,
+            p_memsz: memsz,
+            p_align: 4,
+        }
+    }
+
+    fn create_image(loads: &[ProgramHeader]) -> Vec<u8> {
+        create_image_with(Ctx::new(Container::Little, Endian::Little), loads)
+    }
+
+    fn create_image_with(ctx: Ctx, loads: &[ProgramHeader]) -> Vec<u8> {
+        let phnum = loads.len() + 1;
+        let len = loads
+            .iter()
+            .map(|ph| ph.p_offset + ph.p_filesz)
+            .max()
+            .unwrap();
+        let mut image = vec![0xdb; len as usize];
+
+        let mut header = Header::new(ctx);
+        header.e_type = header::ET_EXEC;
+        header.e_entry = 0x8100;
+        header.e_phoff = Header::size(&ctx) as u64;
+        header.e_phnum = phnum as u16;
+        image.pwrite(header, 0).unwrap();
+
+        let phdr = ProgramHeader {
+            p_type: program_header::PT_PHDR,
+            p_offset: header.e_phoff,
+            p_vaddr: 0x8000 + header.e_phoff,
+            p_paddr: 0x8000 + header.e_phoff,
+            p_filesz: (phnum * ProgramHeader::size(&ctx)) as u64,
+            p_memsz: (phnum * ProgramHeader::size(&ctx)) as u64,
+            ..ProgramHeader::new()
+        };
+        let mut offset = header.e_phoff as usize;
+        for ph in core::iter::once(&phdr).chain(loads) {
+            image.gwrite_with(ph.clone(), &mut offset, ctx).unwrap();
+        }
+
+        image
+    }
+}
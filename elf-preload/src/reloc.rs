@@ -0,0 +1,265 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use crate::{Arch, Result, ValidationError};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use goblin::container::Container;
+use goblin::elf::{header, program_header, reloc, Elf, ProgramHeader, Reloc};
+use scroll::{Pread, Pwrite};
+
+/// Apply the `R_*_RELATIVE` relocations of a position independent executable
+/// as if it were loaded at the `base` virtual address.
+///
+/// The relocated bytes are a copy of `input`. Both RELA and REL relocations are
+/// supported for x86_64, aarch64 and arm ELF files.
+pub(crate) fn relocate(elf: &Elf, arch: Arch, input: &[u8], base: u64) -> Result<Vec<u8>> {
+    let relative = relative_type(arch.machine())?;
+    let ctx = arch.ctx();
+    let mut output = input.to_vec();
+
+    let relocs = elf
+        .dynrelas
+        .iter()
+        .chain(elf.dynrels.iter())
+        .chain(elf.pltrelocs.iter());
+    for reloc in relocs {
+        if reloc.r_type != relative {
+            return Err(ValidationError::UnsupportedRelocation {
+                machine: arch.machine(),
+                r_type: reloc.r_type,
+            }
+            .into());
+        }
+
+        let offset = file_offset(&elf.program_headers, reloc.r_offset)?;
+        match ctx.container {
+            Container::Big => {
+                let value =
+                    relocated_value(&reloc, base, || output.pread_with::<u64>(offset, ctx.le))?;
+                output.pwrite_with(value, offset, ctx.le)?;
+            }
+            Container::Little => {
+                let value = relocated_value(&reloc, base, || {
+                    output.pread_with::<u32>(offset, ctx.le).map(u64::from)
+                })?;
+                let value =
+                    u32::try_from(value).map_err(|_| ValidationError::BaseOutOfRange { base })?;
+                output.pwrite_with(value, offset, ctx.le)?;
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+fn relative_type(machine: u16) -> Result<u32> {
+    match machine {
+        header::EM_X86_64 => Ok(reloc::R_X86_64_RELATIVE),
+        header::EM_AARCH64 => Ok(reloc::R_AARCH64_RELATIVE),
+        header::EM_ARM => Ok(reloc::R_ARM_RELATIVE),
+        _ => Err(ValidationError::UnsupportedMachine { machine }.into()),
+    }
+}
+
+// The implicit addend for REL relocations is read from the relocated location.
+fn relocated_value<F>(reloc: &Reloc, base: u64, implicit_addend: F) -> Result<u64>
+where
+    F: FnOnce() -> core::result::Result<u64, scroll::Error>,
+{
+    match reloc.r_addend {
+        Some(addend) => Ok(base.wrapping_add(addend as u64)),
+        None => Ok(base.wrapping_add(implicit_addend()?)),
+    }
+}
+
+fn file_offset(phdr: &[ProgramHeader], vaddr: u64) -> Result<usize> {
+    phdr.iter()
+        .filter(|ph| ph.p_type == program_header::PT_LOAD)
+        .find(|ph| vaddr >= ph.p_vaddr && vaddr - ph.p_vaddr < ph.p_filesz)
+        .map(|ph| (ph.p_offset + (vaddr - ph.p_vaddr)) as usize)
+        .ok_or_else(|| ValidationError::RelocationOutsideFile { r_offset: vaddr }.into())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::Error;
+    use goblin::container::{Ctx, Endian};
+    use goblin::elf::header::Header;
+    use goblin::elf::r#dyn::{self as dynamic, Dyn};
+    use scroll::ctx::SizeWith;
+
+    const RELOCATED_VADDR: u64 = 0x1c0;
+    const ADDEND: u64 = 0x40;
+
+    #[test]
+    fn relocate_applies_rela_relocations() {
+        let input = create_pie(Container::Big, header::EM_X86_64, true);
+        let elf = Elf::parse(&input).expect("Invalid ELF file");
+        let arch = Arch::new(&elf.header).expect("Invalid Arch");
+
+        let output = relocate(&elf, arch, &input, 0x10000).expect("relocate failed unexpectedly");
+
+        let value: u64 = output
+            .pread_with(RELOCATED_VADDR as usize, scroll::LE)
+            .unwrap();
+        assert_eq!(value, 0x10000 + ADDEND);
+    }
+
+    #[test]
+    fn relocate_applies_rel_relocations() {
+        let input = create_pie(Container::Little, header::EM_ARM, false);
+        let elf = Elf::parse(&input).expect("Invalid ELF file");
+        let arch = Arch::new(&elf.header).expect("Invalid Arch");
+
+        let output = relocate(&elf, arch, &input, 0x10000).expect("relocate failed unexpectedly");
+
+        let value: u32 = output
+            .pread_with(RELOCATED_VADDR as usize, scroll::LE)
+            .unwrap();
+        assert_eq!(value as u64, 0x10000 + ADDEND);
+    }
+
+    #[test]
+    fn relocate_with_unsupported_machine_is_error() {
+        let input = create_pie(Container::Big, header::EM_PPC64, true);
+        let elf = Elf::parse(&input).expect("Invalid ELF file");
+        let arch = Arch::new(&elf.header).expect("Invalid Arch");
+
+        let result = relocate(&elf, arch, &input, 0x10000);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::UnsupportedMachine {
+                machine: header::EM_PPC64
+            }))
+        );
+    }
+
+    #[test]
+    fn relocate_with_non_relative_relocation_is_error() {
+        let input =
+            create_pie_with_reloc(Container::Big, header::EM_X86_64, true, reloc::R_X86_64_64);
+        let elf = Elf::parse(&input).expect("Invalid ELF file");
+        let arch = Arch::new(&elf.header).expect("Invalid Arch");
+
+        let result = relocate(&elf, arch, &input, 0x10000);
+
+        assert_matches!(
+            result,
+            Err(Error::InvalidElf(ValidationError::UnsupportedRelocation {
+                r_type: reloc::R_X86_64_64,
+                ..
+            }))
+        );
+    }
+
+    /// Create a little endian, position independent ELF file with a single
+    /// `R_*_RELATIVE` relocation of the word at `RELOCATED_VADDR`.
+    pub(crate) fn create_pie(container: Container, machine: u16, is_rela: bool) -> Vec<u8> {
+        let r_type = relative_type(machine).unwrap_or(0);
+        create_pie_with_reloc(container, machine, is_rela, r_type)
+    }
+
+    fn create_pie_with_reloc(
+        container: Container,
+        machine: u16,
+        is_rela: bool,
+        r_type: u32,
+    ) -> Vec<u8> {
+        let ctx = Ctx::new(container, Endian::Little);
+        let dyn_offset = 0x100;
+        let reloc_offset = 0x180;
+        let reloc_size = Reloc::size(is_rela, ctx) as u64;
+        let (tag, tag_size, tag_ent) = if is_rela {
+            (dynamic::DT_RELA, dynamic::DT_RELASZ, dynamic::DT_RELAENT)
+        } else {
+            (dynamic::DT_REL, dynamic::DT_RELSZ, dynamic::DT_RELENT)
+        };
+        let dyns = [
+            (tag, reloc_offset),
+            (tag_size, reloc_size),
+            (tag_ent, reloc_size),
+            (dynamic::DT_NULL, 0),
+        ];
+        let mut input = vec![0; 0x200];
+
+        let mut offset = 0;
+        input
+            .gwrite(
+                Header {
+                    e_type: header::ET_DYN,
+                    e_machine: machine,
+                    e_phoff: Header::size(&ctx) as u64,
+                    e_phnum: 2,
+                    ..Header::new(ctx)
+                },
+                &mut offset,
+            )
+            .unwrap();
+        input
+            .gwrite_with(
+                ProgramHeader {
+                    p_type: program_header::PT_LOAD,
+                    p_flags: program_header::PF_R | program_header::PF_W | program_header::PF_X,
+                    p_filesz: 0x200,
+                    p_memsz: 0x200,
+                    p_align: 0x1000,
+                    ..ProgramHeader::new()
+                },
+                &mut offset,
+                ctx,
+            )
+            .unwrap();
+        input
+            .gwrite_with(
+                ProgramHeader {
+                    p_type: program_header::PT_DYNAMIC,
+                    p_offset: dyn_offset,
+                    p_vaddr: dyn_offset,
+                    p_paddr: dyn_offset,
+                    p_filesz: (dyns.len() * Dyn::size_with(&ctx)) as u64,
+                    p_memsz: (dyns.len() * Dyn::size_with(&ctx)) as u64,
+                    p_align: 8,
+                    ..ProgramHeader::new()
+                },
+                &mut offset,
+                ctx,
+            )
+            .unwrap();
+
+        let mut offset = dyn_offset as usize;
+        for &(d_tag, d_val) in &dyns {
+            input
+                .gwrite_with(Dyn { d_tag, d_val }, &mut offset, ctx)
+                .unwrap();
+        }
+
+        let reloc = Reloc {
+            r_offset: RELOCATED_VADDR,
+            r_addend: if is_rela { Some(ADDEND as i64) } else { None },
+            r_sym: 0,
+            r_type,
+        };
+        input
+            .pwrite_with(reloc, reloc_offset as usize, (is_rela, ctx))
+            .unwrap();
+        if !is_rela {
+            match container {
+                Container::Big => input.pwrite_with(ADDEND, RELOCATED_VADDR as usize, ctx.le),
+                Container::Little => {
+                    input.pwrite_with(ADDEND as u32, RELOCATED_VADDR as usize, ctx.le)
+                }
+            }
+            .unwrap();
+        }
+
+        input
+    }
+}
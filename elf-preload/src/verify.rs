@@ -0,0 +1,577 @@
+// Copyright 2019 Steven Bosnick
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use crate::Result;
+use core::fmt::{self, Display, Formatter};
+use goblin::container::Ctx;
+use goblin::elf::{program_header, Header, ProgramHeader};
+use scroll::Pread;
+
+/// A constraint that the output of elf-preload satisfies (see the
+/// SPC-elfpreload design document).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constraint {
+    /// The output has program headers but no section headers.
+    ProgramHeader,
+
+    /// The first program header is a PT_PHDR for the program header table.
+    PtPhdr,
+
+    /// The PT_PHDR is followed by the PT_LOAD program headers and then only by
+    /// the preserved program headers and the segment digests note.
+    PtLoad,
+
+    /// The file size and memory size of each PT_LOAD are equal (unless the
+    /// trailing bss was elided).
+    NoBss,
+
+    /// The PT_LOAD segments are in order of their non-overlapping physical
+    /// addresses and the other program headers have the physical address of
+    /// their place in a PT_LOAD segment.
+    Paddr,
+
+    /// All of the file (from the ELF header onwards) is in the PT_LOAD segments.
+    Plenum,
+}
+
+/// A failure of an output to satisfy a [`Constraint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Violation {
+    constraint: Constraint,
+    index: Option<usize>,
+    reason: &'static str,
+}
+
+/// The result of checking an output against each of the [`Constraint`]'s.
+/// Created by [`verify_output`].
+///
+/// The report has the first violation (if any) of each constraint.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    violations: [Option<Violation>; CONSTRAINTS.len()],
+}
+
+const CONSTRAINTS: [Constraint; 6] = [
+    Constraint::ProgramHeader,
+    Constraint::PtPhdr,
+    Constraint::PtLoad,
+    Constraint::NoBss,
+    Constraint::Paddr,
+    Constraint::Plenum,
+];
+
+/// Check an output of elf-preload (produced by any version of it) against each
+/// of the constraints in SPC-elfpreload.
+///
+/// The returned [`Report`] says which of the constraints (if any) the output
+/// fails to satisfy. Unlike [`Preloaded`][crate::Preloaded], which stops at the
+/// first failure, every constraint is checked.
+///
+/// # Errors
+/// `verify_output()` can return the following errors:
+/// * `Error::BadElf`: `output` does not start with an ELF header and program
+///   headers
+pub fn verify_output(output: &[u8]) -> Result<Report> {
+    let header: Header = output.pread(0)?;
+    let ctx = Ctx::new(header.container()?, header.endianness()?);
+    let phdrs = ProgramHeaders {
+        output,
+        ctx,
+        phoff: header.e_phoff as usize,
+        phnum: header.e_phnum as usize,
+    };
+    for index in 0..phdrs.phnum {
+        phdrs.parse(index)?;
+    }
+
+    let mut report = Report::default();
+    let loads = 1..phdrs.loads_end();
+    verify_program_header(&mut report, &header);
+    verify_ptphdr(&mut report, &header, &phdrs);
+    verify_ptload(&mut report, &phdrs);
+    verify_nobss(&mut report, &phdrs, loads.clone());
+    verify_paddr(&mut report, &phdrs, loads.clone());
+    verify_plenum(&mut report, &phdrs, loads);
+
+    Ok(report)
+}
+
+impl Constraint {
+    /// The tag of the constraint in SPC-elfpreload (e.g. "plenum").
+    pub fn tag(self) -> &'static str {
+        match self {
+            Constraint::ProgramHeader => "programheader",
+            Constraint::PtPhdr => "ptphdr",
+            Constraint::PtLoad => "ptload",
+            Constraint::NoBss => "nobss",
+            Constraint::Paddr => "paddr",
+            Constraint::Plenum => "plenum",
+        }
+    }
+}
+
+impl Display for Constraint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "SPC-elfpreload.{}", self.tag())
+    }
+}
+
+impl Violation {
+    /// The constraint that the output fails to satisfy.
+    pub fn constraint(&self) -> Constraint {
+        self.constraint
+    }
+
+    /// The index of the program header (in the program header table of the
+    /// output) that fails to satisfy the constraint, if the failure is
+    /// particular to one program header.
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    /// A description of the failure.
+    pub fn reason(&self) -> &'static str {
+        self.reason
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(
+                f,
+                "{}: program header {}: {}",
+                self.constraint, index, self.reason
+            ),
+            None => write!(f, "{}: {}", self.constraint, self.reason),
+        }
+    }
+}
+
+impl Report {
+    /// Whether the output satisfies all of the constraints.
+    pub fn is_conforming(&self) -> bool {
+        self.violations().next().is_none()
+    }
+
+    /// The first violation of the given constraint, if any.
+    pub fn violation(&self, constraint: Constraint) -> Option<Violation> {
+        self.violations[constraint as usize]
+    }
+
+    /// An iterator over the first violation of each constraint that the output
+    /// fails to satisfy.
+    pub fn violations(&self) -> impl Iterator<Item = Violation> + '_ {
+        self.violations.iter().filter_map(|violation| *violation)
+    }
+
+    fn fail(&mut self, constraint: Constraint, index: Option<usize>, reason: &'static str) {
+        let slot = &mut self.violations[constraint as usize];
+        if slot.is_none() {
+            *slot = Some(Violation {
+                constraint,
+                index,
+                reason,
+            });
+        }
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_conforming() {
+            return write!(f, "the output satisfies all of the constraints");
+        }
+
+        for (count, violation) in self.violations().enumerate() {
+            if count > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", violation)?;
+        }
+
+        Ok(())
+    }
+}
+
+// The program header table of the output, read in place.
+struct ProgramHeaders<'a> {
+    output: &'a [u8],
+    ctx: Ctx,
+    phoff: usize,
+    phnum: usize,
+}
+
+impl<'a> ProgramHeaders<'a> {
+    fn parse(&self, index: usize) -> Result<ProgramHeader> {
+        let offset = index
+            .checked_mul(ProgramHeader::size(&self.ctx))
+            .and_then(|offset| offset.checked_add(self.phoff))
+            .ok_or(scroll::Error::BadOffset(self.phoff))?;
+
+        Ok(self.output.pread_with(offset, self.ctx)?)
+    }
+
+    // Each of the program headers was parsed successfully in verify_output().
+    fn get(&self, index: usize) -> ProgramHeader {
+        self.parse(index).unwrap_or_else(|_| ProgramHeader::new())
+    }
+
+    // A table that ends past the address space is not held by any PT_LOAD.
+    fn table_end(&self) -> u64 {
+        self.phnum
+            .checked_mul(ProgramHeader::size(&self.ctx))
+            .and_then(|size| size.checked_add(self.phoff))
+            .map_or(u64::MAX, |end| end as u64)
+    }
+
+    // The index after the PT_LOAD's that immediately follow the first program
+    // header.
+    fn loads_end(&self) -> usize {
+        (1..self.phnum)
+            .find(|index| self.get(*index).p_type != program_header::PT_LOAD)
+            .unwrap_or_else(|| self.phnum.max(1))
+    }
+}
+
+fn verify_program_header(report: &mut Report, header: &Header) {
+    if header.e_phnum == 0 {
+        report.fail(
+            Constraint::ProgramHeader,
+            None,
+            "the output has no program headers",
+        );
+    }
+    if header.e_shnum != 0 || header.e_shoff != 0 {
+        report.fail(
+            Constraint::ProgramHeader,
+            None,
+            "the output has section headers",
+        );
+    }
+}
+
+fn verify_ptphdr(report: &mut Report, header: &Header, phdrs: &ProgramHeaders) {
+    if phdrs.phnum == 0 {
+        report.fail(Constraint::PtPhdr, None, "the output has no PT_PHDR");
+        return;
+    }
+
+    let phdr = phdrs.get(0);
+    if phdr.p_type != program_header::PT_PHDR {
+        report.fail(
+            Constraint::PtPhdr,
+            Some(0),
+            "the first program header is not a PT_PHDR",
+        );
+    } else if phdr.p_offset != header.e_phoff
+        || phdr.p_offset.saturating_add(phdr.p_filesz) < phdrs.table_end()
+    {
+        report.fail(
+            Constraint::PtPhdr,
+            Some(0),
+            "the PT_PHDR does not describe the program header table",
+        );
+    }
+}
+
+fn verify_ptload(report: &mut Report, phdrs: &ProgramHeaders) {
+    let loads_end = phdrs.loads_end();
+    if loads_end == 1 {
+        report.fail(
+            Constraint::PtLoad,
+            None,
+            "the PT_PHDR is not followed by a PT_LOAD",
+        );
+    }
+
+    for index in loads_end..phdrs.phnum {
+        match phdrs.get(index).p_type {
+            program_header::PT_LOAD => report.fail(
+                Constraint::PtLoad,
+                Some(index),
+                "a PT_LOAD follows a program header of another type",
+            ),
+            program_header::PT_PHDR | program_header::PT_DYNAMIC | program_header::PT_INTERP => {
+                report.fail(
+                    Constraint::PtLoad,
+                    Some(index),
+                    "the output has a program header of a type that is not preserved",
+                )
+            }
+            _ => {}
+        }
+    }
+}
+
+fn verify_nobss(report: &mut Report, phdrs: &ProgramHeaders, loads: core::ops::Range<usize>) {
+    let mut bss = None;
+
+    for index in loads {
+        let ph = phdrs.get(index);
+        if ph.p_filesz > ph.p_memsz {
+            report.fail(
+                Constraint::NoBss,
+                Some(index),
+                "the filesz of a PT_LOAD is larger than its memsz",
+            );
+        }
+        match bss {
+            Some(bss) if ph.p_filesz > 0 => report.fail(
+                Constraint::NoBss,
+                Some(bss),
+                "a PT_LOAD with bss is followed by a PT_LOAD with contents",
+            ),
+            None if ph.p_filesz < ph.p_memsz => bss = Some(index),
+            _ => {}
+        }
+    }
+}
+
+fn verify_paddr(report: &mut Report, phdrs: &ProgramHeaders, loads: core::ops::Range<usize>) {
+    let mut next_paddr = 0;
+    for index in loads.clone() {
+        let ph = phdrs.get(index);
+        if ph.p_paddr < next_paddr {
+            report.fail(
+                Constraint::Paddr,
+                Some(index),
+                "a PT_LOAD overlaps or precedes the previous PT_LOAD in paddr",
+            );
+        }
+        next_paddr = ph.p_paddr.saturating_add(ph.p_memsz);
+    }
+
+    for index in others(phdrs, &loads) {
+        let ph = phdrs.get(index);
+        let matches = loads.clone().map(|index| phdrs.get(index)).any(|load| {
+            within_file(&ph, &load)
+                && load.p_paddr.checked_add(ph.p_offset - load.p_offset) == Some(ph.p_paddr)
+        });
+        if !matches {
+            report.fail(
+                Constraint::Paddr,
+                Some(index),
+                "the paddr of a program header does not match its place in a PT_LOAD",
+            );
+        }
+    }
+}
+
+fn verify_plenum(report: &mut Report, phdrs: &ProgramHeaders, loads: core::ops::Range<usize>) {
+    let mut next_offset = 0;
+    for index in loads.clone() {
+        let ph = phdrs.get(index);
+        if index == 1 && (ph.p_offset != 0 || ph.p_filesz < phdrs.table_end()) {
+            report.fail(
+                Constraint::Plenum,
+                Some(index),
+                "the first PT_LOAD does not hold the ELF header and program headers",
+            );
+        } else if ph.p_offset != next_offset {
+            report.fail(
+                Constraint::Plenum,
+                Some(index),
+                "a PT_LOAD does not immediately follow the previous PT_LOAD in the file",
+            );
+        }
+        next_offset = ph.p_offset.saturating_add(ph.p_memsz);
+    }
+
+    let file_end = loads
+        .clone()
+        .map(|index| phdrs.get(index))
+        .map(|ph| ph.p_offset.saturating_add(ph.p_filesz))
+        .max()
+        .unwrap_or(0);
+    if file_end != phdrs.output.len() as u64 {
+        report.fail(
+            Constraint::Plenum,
+            None,
+            "the file does not end where its PT_LOAD segments end",
+        );
+    }
+
+    for index in others(phdrs, &loads) {
+        let ph = phdrs.get(index);
+        if !loads.clone().any(|load| within_file(&ph, &phdrs.get(load))) {
+            report.fail(
+                Constraint::Plenum,
+                Some(index),
+                "a program header is not within a PT_LOAD",
+            );
+        }
+    }
+}
+
+// The indices of the program headers with contents other than the PT_LOAD's.
+fn others<'a>(
+    phdrs: &'a ProgramHeaders,
+    loads: &core::ops::Range<usize>,
+) -> impl Iterator<Item = usize> + 'a {
+    let loads = loads.clone();
+
+    (0..phdrs.phnum)
+        .filter(move |index| !loads.contains(index))
+        .filter(move |index| phdrs.get(*index).p_memsz > 0)
+}
+
+fn within_file(ph: &ProgramHeader, load: &ProgramHeader) -> bool {
+    ph.p_offset >= load.p_offset
+        && ph.p_offset.saturating_add(ph.p_filesz) <= load.p_offset.saturating_add(load.p_filesz)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use goblin::container::{Container, Endian};
+    use goblin::elf::header;
+    use scroll::Pwrite;
+
+    #[test]
+    fn verify_output_for_conforming_output_is_conforming() {
+        let output = create_output(&[load(0, 0x100, 0x100), load(0x100, 0x20, 0x20)]);
+
+        let report = verify_output(&output).unwrap();
+
+        assert!(report.is_conforming(), "{}", report);
+    }
+
+    #[test]
+    fn verify_output_for_interior_bss_fails_nobss() {
+        let output = create_output(&[load(0, 0x100, 0x180), load(0x180, 0x20, 0x20)]);
+
+        let report = verify_output(&output).unwrap();
+
+        assert_matches!(
+            report.violation(Constraint::NoBss),
+            Some(Violation { index: Some(1), .. })
+        );
+    }
+
+    #[test]
+    fn verify_output_for_gap_in_file_fails_only_plenum() {
+        let output = create_output(&[load(0, 0x100, 0x100), load(0x110, 0x20, 0x20)]);
+
+        let report = verify_output(&output).unwrap();
+
+        let violations: Vec<_> = report.violations().map(|v| v.constraint()).collect();
+        assert_eq!(violations, [Constraint::Plenum]);
+    }
+
+    #[test]
+    fn verify_output_for_overlapping_paddr_fails_paddr() {
+        let mut second = load(0x100, 0x20, 0x20);
+        second.p_paddr = 0x80f0;
+        let output = create_output(&[load(0, 0x100, 0x100), second]);
+
+        let report = verify_output(&output).unwrap();
+
+        assert_matches!(
+            report.violation(Constraint::Paddr),
+            Some(Violation { index: Some(2), .. })
+        );
+    }
+
+    #[test]
+    fn verify_output_for_load_after_note_fails_ptload() {
+        let mut note = load(0x40, 0x10, 0x10);
+        note.p_type = program_header::PT_NOTE;
+        let output = create_output(&[load(0, 0x100, 0x100), note, load(0x100, 0x20, 0x20)]);
+
+        let report = verify_output(&output).unwrap();
+
+        assert_matches!(
+            report.violation(Constraint::PtLoad),
+            Some(Violation { index: Some(3), .. })
+        );
+    }
+
+    #[test]
+    fn verify_output_for_paddr_past_end_of_address_space_fails_paddr() {
+        let ctx = Ctx::new(Container::Big, Endian::Little);
+        let mut first = load(0, 0x100, 0x100);
+        first.p_paddr = u64::MAX - 0x10;
+        let mut note = load(0xe0, 0x10, 0x10);
+        note.p_type = program_header::PT_NOTE;
+        note.p_paddr = 0x80e0;
+        let output = create_output_with(ctx, &[first, note]);
+
+        let report = verify_output(&output).unwrap();
+
+        assert_matches!(
+            report.violation(Constraint::Paddr),
+            Some(Violation { index: Some(0), .. })
+        );
+    }
+
+    #[test]
+    fn violation_display_names_constraint() {
+        let output = create_output(&[load(0, 0x100, 0x180), load(0x180, 0x20, 0x20)]);
+
+        let report = verify_output(&output).unwrap();
+
+        assert_eq!(
+            report.to_string(),
+            "SPC-elfpreload.nobss: program header 1: \
+             a PT_LOAD with bss is followed by a PT_LOAD with contents"
+        );
+    }
+
+    fn load(offset: u64, filesz: u64, memsz: u64) -> ProgramHeader {
+        ProgramHeader {
+            p_type: program_header::PT_LOAD,
+            p_flags: program_header::PF_R | program_header::PF_X,
+            p_offset: offset,
+            p_vaddr: 0x8000 + offset,
+            p_paddr: 0x8000 + offset,
+            p_filesz: filesz,
+            p_memsz: memsz,
+            p_align: 4,
+        }
+    }
+
+    fn create_output(phdrs: &[ProgramHeader]) -> Vec<u8> {
+        create_output_with(Ctx::new(Container::Little, Endian::Little), phdrs)
+    }
+
+    fn create_output_with(ctx: Ctx, phdrs: &[ProgramHeader]) -> Vec<u8> {
+        let phnum = phdrs.len() + 1;
+        let len = phdrs
+            .iter()
+            .map(|ph| ph.p_offset + ph.p_filesz)
+            .max()
+            .unwrap();
+        let mut output = vec![0xdb; len as usize];
+
+        let mut header = Header::new(ctx);
+        header.e_type = header::ET_EXEC;
+        header.e_entry = 0x8100;
+        header.e_phoff = Header::size(&ctx) as u64;
+        header.e_phnum = phnum as u16;
+        header.e_shoff = 0;
+        header.e_shnum = 0;
+        output.pwrite(header, 0).unwrap();
+
+        let phdr = ProgramHeader {
+            p_type: program_header::PT_PHDR,
+            p_offset: header.e_phoff,
+            p_vaddr: 0x8000 + header.e_phoff,
+            p_paddr: 0x8000 + header.e_phoff,
+            p_filesz: (phnum * ProgramHeader::size(&ctx)) as u64,
+            p_memsz: (phnum * ProgramHeader::size(&ctx)) as u64,
+            ..ProgramHeader::new()
+        };
+        let mut offset = header.e_phoff as usize;
+        for ph in core::iter::once(&phdr).chain(phdrs) {
+            output.gwrite_with(ph.clone(), &mut offset, ctx).unwrap();
+        }
+
+        output
+    }
+}
@@ -0,0 +1,10 @@
+static int value = 42;
+static int other = 7;
+int *pointers[] = { &value, &other, &value };
+
+void _start(void)
+{
+    for (;;) {
+        *pointers[0] += *pointers[1];
+    }
+}
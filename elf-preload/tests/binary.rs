@@ -0,0 +1,58 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use elf_preload::{Input, LayoutStrategy};
+use goblin::elf::{program_header, Elf};
+
+static SMOKETEST_ELF: &[u8] = include_bytes!("../test_data/smoketest");
+static KERNEL_ELF: &[u8] = include_bytes!("../test_data/kernel.elf");
+
+#[test]
+fn elf_preload_binary_matches_elf_for_specified_start() {
+    binary_matches_elf_test(SMOKETEST_ELF, LayoutStrategy::SpecifiedStart(5000));
+}
+
+#[test]
+fn elf_preload_binary_matches_elf_for_from_input() {
+    binary_matches_elf_test(KERNEL_ELF, LayoutStrategy::FromInput);
+}
+
+fn binary_matches_elf_test(input: &[u8], strategy: LayoutStrategy) {
+    let input = Input::new(input).expect("Unable to read input file");
+    let layout = input
+        .layout(strategy)
+        .expect("Unable to layout output file");
+
+    let mut elf_output = vec![0xd0; layout.required_size()];
+    let mut writer = layout
+        .output(&mut elf_output)
+        .expect("Unable to create writer");
+    writer.write().expect("Unable to write output file");
+
+    let image = layout.binary_image().expect("Unable to place binary");
+    let mut bin_output = vec![0xd0; image.size()];
+    let mut writer = layout
+        .binary_output(&mut bin_output)
+        .expect("Unable to create binary writer");
+    writer.write().expect("Unable to write binary output");
+
+    let elf = Elf::parse(&elf_output).expect("Output file invalid");
+    let mut end = 0;
+    for phdr in elf
+        .program_headers
+        .iter()
+        .filter(|p| p.p_type == program_header::PT_LOAD)
+        .skip(1)
+    {
+        let start = (phdr.p_paddr - image.load_address()) as usize;
+        let size = phdr.p_filesz as usize;
+        let elf_bytes = &elf_output[phdr.p_offset as usize..][..size];
+        assert_eq!(&bin_output[start..start + size], elf_bytes);
+        end = start + size;
+    }
+    assert_eq!(bin_output.len(), end);
+}
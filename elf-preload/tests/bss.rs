@@ -0,0 +1,103 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use assert_matches::assert_matches;
+use elf_preload::{zero_bss, Error, Input, LayoutConfig, LayoutStrategy};
+use goblin::elf::{program_header, Elf};
+
+static KERNEL_ELF: &[u8] = include_bytes!("../test_data/kernel.elf");
+
+#[test]
+fn elf_preload_with_elided_bss_has_short_last_segment() {
+    let output = preload(&LayoutConfig::new().elide_bss(true));
+
+    let elf = Elf::parse(&output).expect("Unable to parse output file");
+    let last_load = elf
+        .program_headers
+        .iter()
+        .rfind(|ph| ph.p_type == program_header::PT_LOAD)
+        .expect("No PT_LOAD program header");
+    assert!(last_load.p_filesz < last_load.p_memsz);
+    assert_eq!(output.len() as u64, last_load.p_offset + last_load.p_filesz);
+}
+
+#[test]
+fn elf_preload_with_elided_bss_matches_full_output_after_zero_bss() {
+    let expected = preload(&LayoutConfig::new());
+    let output = preload(&LayoutConfig::new().elide_bss(true));
+
+    let mut image = vec![0xc0; expected.len()];
+    image[..output.len()].copy_from_slice(&output);
+    zero_bss(&mut image).expect("zero_bss failed unexpectedly");
+
+    // only the program headers differ
+    let headers = Elf::parse(&output)
+        .expect("Unable to parse output file")
+        .program_headers[1]
+        .p_filesz as usize;
+    assert_eq!(&image[headers..], &expected[headers..]);
+}
+
+#[test]
+fn elf_preload_zero_bss_on_output_alone_is_error() {
+    let mut output = preload(&LayoutConfig::new().elide_bss(true));
+
+    let result = zero_bss(&mut output);
+
+    assert_matches!(result, Err(Error::OutputTooSmall));
+}
+
+#[cfg(feature = "test-support")]
+#[test]
+fn elf_preload_zero_bss_with_bss_past_end_of_address_space_is_error() {
+    use elf_preload::{ElfBuilder, ValidationError};
+    use goblin::container::{Container, Ctx, Endian};
+    use goblin::elf::ProgramHeader;
+    use scroll::Pwrite;
+
+    let input = ElfBuilder::new(Container::Big, Endian::Little)
+        .load(0x8000, 0x8000, &[0xdb; 0x100], 0x200)
+        .build();
+    let mut output = preload_input(&input, &LayoutConfig::new().elide_bss(true));
+    let elf = Elf::parse(&output).expect("Unable to parse output file");
+    let ctx = Ctx::new(Container::Big, Endian::Little);
+    let load = ProgramHeader {
+        p_memsz: u64::MAX,
+        ..elf.program_headers[2].clone()
+    };
+    let offset = elf.header.e_phoff as usize + 2 * ProgramHeader::size(&ctx);
+    output.pwrite_with(load, offset, ctx).unwrap();
+
+    let result = zero_bss(&mut output);
+
+    assert_matches!(
+        result,
+        Err(Error::InvalidElf(ValidationError::SegmentOutOfRange {
+            index: 2,
+            memsz: u64::MAX
+        }))
+    );
+}
+
+fn preload(config: &LayoutConfig) -> Vec<u8> {
+    preload_input(KERNEL_ELF, config)
+}
+
+fn preload_input(input: &[u8], config: &LayoutConfig) -> Vec<u8> {
+    let input = Input::new(input).expect("Unable to parse input file");
+    let layout = input
+        .layout_with(LayoutStrategy::FromInput, config)
+        .expect("Unable to layout input");
+    let mut output = vec![0; layout.required_size()];
+    layout
+        .output(&mut output)
+        .expect("Output too small")
+        .write()
+        .expect("Unable to write output");
+
+    output
+}
@@ -0,0 +1,83 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+#![cfg(feature = "test-support")]
+
+use assert_matches::assert_matches;
+use elf_preload::{verify_output, ElfBuilder, Error, Input, LayoutStrategy, ValidationError};
+use goblin::container::{Container, Endian};
+use goblin::elf::{program_header, ProgramHeader};
+
+#[test]
+fn elf_preload_for_built_input_conforms_for_each_class_and_endianness() {
+    for &container in &[Container::Little, Container::Big] {
+        for &endian in &[Endian::Little, Endian::Big] {
+            let input = ElfBuilder::new(container, endian)
+                .load(0x8000, 0x8000, &[0xdb; 0x100], 0x100)
+                .load(0x9000, 0x9000, &[0xdb; 0x40], 0x800)
+                .note(0x8010, "elf-preload", 1, &[0xdb; 8])
+                .build();
+
+            let output = run_preload(&input, LayoutStrategy::SpecifiedStart(0x1000_0000));
+            let report = verify_output(&output).expect("Output file invalid");
+
+            assert!(report.is_conforming(), "{}", report);
+        }
+    }
+}
+
+#[test]
+fn elf_preload_for_built_input_with_interpreter_is_error() {
+    let input = ElfBuilder::new(Container::Big, Endian::Little)
+        .interpreter(0x8000, "/lib/ld-linux-aarch64.so.1")
+        .load(0x8000, 0x8000, &[0xdb; 0x100], 0x100)
+        .build();
+
+    let result = Input::new(&input);
+
+    assert_matches!(result, Err(Error::InvalidElf(ValidationError::Interpreter)));
+}
+
+#[test]
+fn elf_preload_for_built_input_keeps_segment_permissions() {
+    let input = ElfBuilder::new(Container::Little, Endian::Little)
+        .load(0x8000, 0x8000, &[0xdb; 0x100], 0x100)
+        .segment(
+            ProgramHeader {
+                p_type: program_header::PT_LOAD,
+                p_flags: program_header::PF_R | program_header::PF_W,
+                p_vaddr: 0x9000,
+                p_paddr: 0x9000,
+                p_memsz: 0x200,
+                p_align: 0x1000,
+                ..ProgramHeader::new()
+            },
+            &[0xdb; 0x20],
+        )
+        .build();
+
+    let input = Input::new(&input).expect("Unable to read input file");
+    let layout = input
+        .layout(LayoutStrategy::FromInput)
+        .expect("Unable to layout output file");
+
+    let data = &layout.program_headers()[3];
+    assert_eq!(data.p_flags, program_header::PF_R | program_header::PF_W);
+    assert_eq!(data.p_paddr, 0x9000);
+}
+
+fn run_preload(input: &[u8], strategy: LayoutStrategy) -> Vec<u8> {
+    let input = Input::new(input).expect("Unable to read input file");
+    let layout = input
+        .layout(strategy)
+        .expect("Unable to layout output file");
+    let mut output = vec![0; layout.required_size()];
+    let mut writer = layout.output(&mut output).expect("Unable to create writer");
+    writer.write().expect("Unable to write output file");
+
+    output
+}
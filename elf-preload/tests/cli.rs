@@ -0,0 +1,197 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+#![cfg(feature = "cli")]
+
+use goblin::elf::{program_header, Elf};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+static SMOKETEST_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_data/smoketest");
+static KERNEL_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_data/kernel.elf");
+
+#[test]
+fn elf_preload_cli_for_from_input_writes_valid_elf() {
+    let out_dir = tempfile::tempdir().expect("Couldn't create temp dir.");
+    let out_file = out_dir.path().join("output.elf");
+
+    let result = run_cli(Path::new(KERNEL_PATH), &out_file, &["--from-input"]);
+
+    assert!(
+        result.status.success(),
+        "elf-preload did not exit sucessfully"
+    );
+    let output = fs::read(&out_file).expect("Unable to read output file");
+    Elf::parse(&output).expect("Output file is an invalid Elf file!");
+}
+
+#[test]
+fn elf_preload_cli_for_specified_start_uses_start() {
+    let out_dir = tempfile::tempdir().expect("Couldn't create temp dir.");
+    let out_file = out_dir.path().join("output.elf");
+
+    let result = run_cli(Path::new(SMOKETEST_PATH), &out_file, &["--start", "0x5000"]);
+
+    assert!(
+        result.status.success(),
+        "elf-preload did not exit sucessfully"
+    );
+    let output = fs::read(&out_file).expect("Unable to read output file");
+    let elf = Elf::parse(&output).expect("Output file is an invalid Elf file!");
+    let first_load = elf
+        .program_headers
+        .iter()
+        .find(|ph| ph.p_type == program_header::PT_LOAD)
+        .expect("No PT_LOAD program header");
+    assert_eq!(first_load.p_paddr, 0x5000);
+}
+
+#[test]
+fn elf_preload_cli_for_identity_uses_vaddr_as_paddr() {
+    let out_dir = tempfile::tempdir().expect("Couldn't create temp dir.");
+    let out_file = out_dir.path().join("output.elf");
+
+    let result = run_cli(Path::new(SMOKETEST_PATH), &out_file, &["--identity"]);
+
+    assert!(
+        result.status.success(),
+        "elf-preload did not exit sucessfully"
+    );
+    let output = fs::read(&out_file).expect("Unable to read output file");
+    let elf = Elf::parse(&output).expect("Output file is an invalid Elf file!");
+    assert!(elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == program_header::PT_LOAD)
+        .skip(1)
+        .all(|ph| ph.p_paddr == ph.p_vaddr));
+}
+
+#[test]
+fn elf_preload_cli_for_ihex_writes_intel_hex() {
+    let out_dir = tempfile::tempdir().expect("Couldn't create temp dir.");
+    let out_file = out_dir.path().join("output.hex");
+
+    let result = run_cli(
+        Path::new(KERNEL_PATH),
+        &out_file,
+        &["--from-input", "--ihex"],
+    );
+
+    assert!(
+        result.status.success(),
+        "elf-preload did not exit sucessfully"
+    );
+    let output = fs::read_to_string(&out_file).expect("Unable to read output file");
+    assert!(output.lines().all(|line| line.starts_with(':')));
+    assert_eq!(output.lines().last(), Some(":00000001FF"));
+}
+
+#[test]
+fn elf_preload_cli_for_arm32_boot_image_writes_zimage_header() {
+    let out_dir = tempfile::tempdir().expect("Couldn't create temp dir.");
+    let out_file = out_dir.path().join("zImage");
+
+    let result = run_cli(
+        Path::new(KERNEL_PATH),
+        &out_file,
+        &["--from-input", "--boot-image", "arm32"],
+    );
+
+    assert!(
+        result.status.success(),
+        "elf-preload did not exit sucessfully"
+    );
+    let output = fs::read(&out_file).expect("Unable to read output file");
+    assert_eq!(&output[0x24..0x28], &[0x18, 0x28, 0x6f, 0x01]);
+}
+
+#[test]
+fn elf_preload_cli_for_separate_permissions_starts_segments_on_new_pages() {
+    let out_dir = tempfile::tempdir().expect("Couldn't create temp dir.");
+    let out_file = out_dir.path().join("output.elf");
+
+    let result = run_cli(
+        Path::new(SMOKETEST_PATH),
+        &out_file,
+        &["--start", "0x10000", "--separate-permissions"],
+    );
+
+    assert!(
+        result.status.success(),
+        "elf-preload did not exit sucessfully"
+    );
+    let output = fs::read(&out_file).expect("Unable to read output file");
+    let elf = Elf::parse(&output).expect("Output file is an invalid Elf file!");
+    let loads: Vec<_> = elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == program_header::PT_LOAD)
+        .collect();
+    for pair in loads.windows(2) {
+        if pair[0].p_flags != pair[1].p_flags {
+            assert_eq!(pair[1].p_paddr % 0x1000, 0);
+            assert_eq!(pair[1].p_vaddr % 0x1000, 0);
+        }
+    }
+}
+
+#[test]
+fn elf_preload_cli_prints_segment_table() {
+    let out_dir = tempfile::tempdir().expect("Couldn't create temp dir.");
+    let out_file = out_dir.path().join("output.elf");
+
+    let result = run_cli(Path::new(KERNEL_PATH), &out_file, &["--from-input"]);
+
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(stdout.lines().any(|line| line.starts_with("PHDR")));
+    assert!(stdout.lines().any(|line| line.starts_with("LOAD")));
+}
+
+#[test]
+fn elf_preload_cli_for_runs_writes_numbered_outputs() {
+    let out_dir = tempfile::tempdir().expect("Couldn't create temp dir.");
+    let out_file = out_dir.path().join("output.elf");
+
+    let result = run_cli(
+        Path::new(KERNEL_PATH),
+        &out_file,
+        &["--from-input", "--runs"],
+    );
+
+    assert!(
+        result.status.success(),
+        "elf-preload did not exit sucessfully"
+    );
+    let output = fs::read(out_dir.path().join("output.elf.0")).expect("Unable to read output file");
+    Elf::parse(&output).expect("Output file is an invalid Elf file!");
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(stdout.lines().any(|line| line.starts_with("Run 0: ")));
+}
+
+#[test]
+fn elf_preload_cli_for_bad_elf_exits_with_bad_elf_code() {
+    let out_dir = tempfile::tempdir().expect("Couldn't create temp dir.");
+    let in_file = out_dir.path().join("input.elf");
+    let out_file = out_dir.path().join("output.elf");
+    fs::write(&in_file, [0xba, 0xdd, 0x00, 0xd8, 0xde, 0xad, 0xbe, 0xef])
+        .expect("Unable to write input file");
+
+    let result = run_cli(&in_file, &out_file, &["--from-input"]);
+
+    assert_eq!(result.status.code(), Some(2));
+}
+
+fn run_cli(input: &Path, output: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf-preload"))
+        .arg(input)
+        .arg(output)
+        .args(args)
+        .output()
+        .expect("Unable to run elf-preload")
+}
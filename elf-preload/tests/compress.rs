@@ -0,0 +1,47 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+#![cfg(any(feature = "deflate", feature = "lz4"))]
+
+use elf_preload::{unpack, Compression, Input, LayoutStrategy};
+
+static KERNEL_ELF: &[u8] = include_bytes!("../test_data/kernel.elf");
+
+#[cfg(feature = "deflate")]
+#[test]
+fn elf_preload_deflate_unpacks_to_elf_output() {
+    unpack_matches_elf_test(Compression::Deflate);
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn elf_preload_lz4_unpacks_to_elf_output() {
+    unpack_matches_elf_test(Compression::Lz4);
+}
+
+fn unpack_matches_elf_test(method: Compression) {
+    let input = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let layout = input
+        .layout(LayoutStrategy::FromInput)
+        .expect("Unable to layout output file");
+    let mut expected = vec![0; layout.required_size()];
+    let mut writer = layout
+        .output(&mut expected)
+        .expect("Unable to create writer");
+    writer.write().expect("Unable to write output file");
+
+    let container = layout
+        .compressed_output(method)
+        .expect("Unable to create compressed output");
+    let start = layout.paddr_range().start;
+    let mut memory = vec![0xd0; layout.paddr_range().end as usize - start as usize];
+    let entry = unpack(&container, &mut memory, start).expect("Unable to unpack");
+
+    assert!(container.len() < expected.len());
+    assert_eq!(entry, 0xe000_0000);
+    assert_eq!(&memory[..expected.len()], &expected[..]);
+}
@@ -0,0 +1,103 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use elf_preload::{Input, LayoutStrategy};
+use goblin::elf::{section_header, Elf};
+
+static SMOKETEST_ELF: &[u8] = include_bytes!("../test_data/smoketest");
+static STATIC_PIE_ELF: &[u8] = include_bytes!("../test_data/static-pie");
+
+const BASE: u64 = 0x4000_0000;
+
+#[test]
+fn elf_preload_debug_output_has_output_segments() {
+    let input = Input::new(SMOKETEST_ELF).expect("Unable to read input file");
+    let layout = input
+        .layout(LayoutStrategy::SpecifiedStart(5000))
+        .expect("Unable to layout output file");
+
+    let debug = layout.debug_output().expect("Unable to create debug file");
+
+    let elf = Elf::parse(&debug).expect("Debug file invalid");
+    assert_eq!(elf.program_headers.len(), layout.program_headers().len());
+    for (debug_phdr, out_phdr) in elf.program_headers.iter().zip(layout.program_headers()) {
+        assert_eq!(debug_phdr.p_paddr, out_phdr.p_paddr);
+        assert_eq!(debug_phdr.p_vaddr, out_phdr.p_vaddr);
+        assert_eq!(debug_phdr.p_filesz, 0);
+    }
+}
+
+#[test]
+fn elf_preload_debug_output_keeps_symbols_and_dwarf() {
+    let input_elf = Elf::parse(SMOKETEST_ELF).expect("Input file invalid");
+    let input = Input::new(SMOKETEST_ELF).expect("Unable to read input file");
+    let layout = input
+        .layout(LayoutStrategy::FromInput)
+        .expect("Unable to layout output file");
+
+    let debug = layout.debug_output().expect("Unable to create debug file");
+
+    let elf = Elf::parse(&debug).expect("Debug file invalid");
+    assert_eq!(elf.syms.len(), input_elf.syms.len());
+    for (sym, input_sym) in elf.syms.iter().zip(input_elf.syms.iter()) {
+        assert_eq!(sym.st_name, input_sym.st_name);
+        assert_eq!(sym.st_value, input_sym.st_value);
+    }
+    let debug_info = find_section(&elf, ".debug_info");
+    let input_debug_info = find_section(&input_elf, ".debug_info");
+    assert_eq!(
+        &debug[debug_info.file_range()],
+        &SMOKETEST_ELF[input_debug_info.file_range()]
+    );
+}
+
+#[test]
+fn elf_preload_debug_output_has_no_alloc_contents() {
+    let input = Input::new(SMOKETEST_ELF).expect("Unable to read input file");
+    let layout = input
+        .layout(LayoutStrategy::FromInput)
+        .expect("Unable to layout output file");
+
+    let debug = layout.debug_output().expect("Unable to create debug file");
+
+    let elf = Elf::parse(&debug).expect("Debug file invalid");
+    for shdr in elf.section_headers.iter().filter(|sh| sh.is_alloc()) {
+        assert_eq!(shdr.sh_type, section_header::SHT_NOBITS);
+    }
+}
+
+#[test]
+fn elf_preload_debug_output_with_base_offsets_symbols() {
+    let input_elf = Elf::parse(STATIC_PIE_ELF).expect("Input file invalid");
+    let input = Input::with_base(STATIC_PIE_ELF, BASE).expect("Unable to read input file");
+    let layout = input
+        .layout(LayoutStrategy::SpecifiedStart(BASE))
+        .expect("Unable to layout output file");
+
+    let debug = layout.debug_output().expect("Unable to create debug file");
+
+    let elf = Elf::parse(&debug).expect("Debug file invalid");
+    let start = find_symbol(&elf, "_start");
+    let input_start = find_symbol(&input_elf, "_start");
+    assert_eq!(start, input_start + BASE);
+    assert_eq!(start, elf.header.e_entry);
+}
+
+fn find_section<'a>(elf: &'a Elf, name: &str) -> &'a section_header::SectionHeader {
+    elf.section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get(sh.sh_name).and_then(|n| n.ok()) == Some(name))
+        .expect("Section not found")
+}
+
+fn find_symbol(elf: &Elf, name: &str) -> u64 {
+    elf.syms
+        .iter()
+        .find(|sym| elf.strtab.get(sym.st_name).and_then(|n| n.ok()) == Some(name))
+        .map(|sym| sym.st_value)
+        .expect("Symbol not found")
+}
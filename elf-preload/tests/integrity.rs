@@ -0,0 +1,99 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+#![cfg(feature = "integrity")]
+
+use assert_matches::assert_matches;
+use elf_preload::{verify_digests, zero_bss, Error, Input, LayoutConfig, LayoutStrategy};
+use goblin::elf::{program_header, Elf};
+
+static SMOKETEST_ELF: &[u8] = include_bytes!("../test_data/smoketest");
+static KERNEL_ELF: &[u8] = include_bytes!("../test_data/kernel.elf");
+
+#[test]
+fn elf_preload_with_digests_verifies_for_from_input() {
+    let output = digests_output(KERNEL_ELF, LayoutStrategy::FromInput);
+
+    let result = verify_digests(&output);
+
+    assert_matches!(result, Ok(()));
+}
+
+#[test]
+fn elf_preload_with_digests_verifies_for_specified_start() {
+    let output = digests_output(SMOKETEST_ELF, LayoutStrategy::SpecifiedStart(0x1000_0000));
+
+    let result = verify_digests(&output);
+
+    assert_matches!(result, Ok(()));
+}
+
+#[test]
+fn elf_preload_with_digests_has_note_in_first_load() {
+    let output = digests_output(KERNEL_ELF, LayoutStrategy::FromInput);
+
+    let elf = Elf::parse(&output).expect("Unable to parse output file");
+    let first_load = &elf.program_headers[1];
+    let note = elf
+        .program_headers
+        .iter()
+        .find(|ph| ph.p_type == program_header::PT_NOTE)
+        .expect("No PT_NOTE in output file");
+    assert!(note.p_offset + note.p_filesz <= first_load.p_offset + first_load.p_filesz);
+}
+
+#[test]
+fn elf_preload_with_digests_detects_corrupt_segment() {
+    let mut output = digests_output(KERNEL_ELF, LayoutStrategy::FromInput);
+    let elf = Elf::parse(&output).expect("Unable to parse output file");
+    let segment = elf.program_headers[2].clone();
+
+    output[segment.p_offset as usize] ^= 0xff;
+    let result = verify_digests(&output);
+
+    assert_matches!(result, Err(Error::DigestMismatch { paddr }) if paddr == segment.p_paddr);
+}
+
+#[test]
+fn elf_preload_with_digests_and_elided_bss_does_not_verify_output_alone() {
+    let config = LayoutConfig::new().digests(true).elide_bss(true);
+    let output = digests_output_with(KERNEL_ELF, LayoutStrategy::FromInput, config);
+
+    let result = verify_digests(&output);
+
+    assert_matches!(result, Err(Error::DigestMismatch { paddr: _ }));
+}
+
+#[test]
+fn elf_preload_with_digests_and_elided_bss_verifies_after_zero_bss() {
+    let config = LayoutConfig::new().digests(true).elide_bss(true);
+    let output = digests_output_with(KERNEL_ELF, LayoutStrategy::FromInput, config);
+    let expected_len = digests_output(KERNEL_ELF, LayoutStrategy::FromInput).len();
+
+    let mut image = vec![0xc0; expected_len];
+    image[..output.len()].copy_from_slice(&output);
+    zero_bss(&mut image).expect("zero_bss failed unexpectedly");
+    let result = verify_digests(&image);
+
+    assert_matches!(result, Ok(()));
+}
+
+fn digests_output(bytes: &[u8], strategy: LayoutStrategy) -> Vec<u8> {
+    digests_output_with(bytes, strategy, LayoutConfig::new().digests(true))
+}
+
+fn digests_output_with(bytes: &[u8], strategy: LayoutStrategy, config: LayoutConfig) -> Vec<u8> {
+    let input = Input::new(bytes).expect("Unable to read input file");
+    let layout = input
+        .layout_with(strategy, &config)
+        .expect("Unable to layout output file");
+    let mut output = vec![0; layout.required_size()];
+    let mut writer = layout.output(&mut output).expect("Unable to create writer");
+    writer.write().expect("Unable to write output file");
+
+    output
+}
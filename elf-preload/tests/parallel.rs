@@ -0,0 +1,55 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+#![cfg(feature = "rayon")]
+
+use assert_matches::assert_matches;
+use elf_preload::{Error, Input, LayoutStrategy};
+
+static SMOKETEST_ELF: &[u8] = include_bytes!("../test_data/smoketest");
+static KERNEL_ELF: &[u8] = include_bytes!("../test_data/kernel.elf");
+
+#[test]
+fn elf_preload_write_all_matches_write_for_smoketest() {
+    write_all_matches_write_test(SMOKETEST_ELF, LayoutStrategy::SpecifiedStart(5000));
+}
+
+#[test]
+fn elf_preload_write_all_matches_write_for_kernel() {
+    write_all_matches_write_test(KERNEL_ELF, LayoutStrategy::FromInput);
+}
+
+#[test]
+fn elf_preload_write_all_with_small_output_is_error() {
+    let input = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let layout = input
+        .layout(LayoutStrategy::FromInput)
+        .expect("Unable to layout output file");
+    let mut output = vec![0; layout.required_size() - 1];
+
+    let result = layout.write_all(&mut output);
+
+    assert_matches!(result, Err(Error::OutputTooSmall));
+}
+
+fn write_all_matches_write_test(input: &[u8], strategy: LayoutStrategy) {
+    let input = Input::new(input).expect("Unable to read input file");
+    let layout = input
+        .layout(strategy)
+        .expect("Unable to layout output file");
+
+    let mut serial = vec![0xd0; layout.required_size()];
+    let mut writer = layout.output(&mut serial).expect("Unable to create writer");
+    writer.write().expect("Unable to write output file");
+
+    let mut parallel = vec![0xd0; layout.required_size()];
+    layout
+        .write_all(&mut parallel)
+        .expect("Unable to write output file in parallel");
+
+    assert_eq!(parallel, serial);
+}
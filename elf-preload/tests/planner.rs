@@ -0,0 +1,203 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use assert_matches::assert_matches;
+use elf_preload::{
+    Error, Input, LayoutConfig, LayoutStrategy, Placement, Planner, ValidationError,
+};
+
+static SMOKETEST_ELF: &[u8] = include_bytes!("../test_data/smoketest");
+static KERNEL_ELF: &[u8] = include_bytes!("../test_data/kernel.elf");
+
+const RAM: std::ops::Range<u64> = 0x0800_0000..0x2000_0000;
+const SMALL_RAM: std::ops::Range<u64> = 0x0800_0000..0x1000_0000;
+
+#[test]
+fn planner_anchor_at_input_keeps_input_paddr() {
+    let kernel = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let mut sut = Planner::new(vec![RAM]);
+    sut.add(&kernel, Placement::AnchorAtInput);
+
+    let layouts = sut.plan().expect("plan failed unexpectedly");
+
+    let kernel_load = &layouts[0].program_headers()[2];
+    assert_eq!(kernel_load.p_paddr, 0x1000_0000);
+}
+
+#[test]
+fn planner_after_places_image_after_aligned_end() {
+    let kernel = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let rootserver = Input::new(SMOKETEST_ELF).expect("Unable to read input file");
+    let mut sut = Planner::new(vec![RAM]);
+    let kernel_index = sut.add(&kernel, Placement::AnchorAtInput);
+    sut.add(
+        &rootserver,
+        Placement::After {
+            image: kernel_index,
+            align: 0x10_0000,
+        },
+    );
+
+    let layouts = sut.plan().expect("plan failed unexpectedly");
+
+    let kernel_end = layouts[0].paddr_range().end;
+    let start = layouts[1].paddr_range().start;
+    assert!(start >= kernel_end);
+    assert!(start - kernel_end < 0x10_0000);
+    assert_eq!(start % 0x10_0000, 0);
+}
+
+#[test]
+fn planner_end_before_places_image_before_start() {
+    let kernel = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let bootloader = Input::new(SMOKETEST_ELF).expect("Unable to read input file");
+    let mut sut = Planner::new(vec![RAM]);
+    sut.add(
+        &bootloader,
+        Placement::EndBefore {
+            image: 1,
+            align: 0x1000,
+        },
+    );
+    sut.add(&kernel, Placement::AnchorAtInput);
+
+    let layouts = sut.plan().expect("plan failed unexpectedly");
+
+    let range = layouts[0].paddr_range();
+    let kernel_start = layouts[1].paddr_range().start;
+    // the smoketest segment has a p_align of 0x10000
+    assert!(range.end <= kernel_start);
+    assert!(kernel_start - range.end < 0x10000);
+    assert_eq!(range.start % 0x10000, 0);
+}
+
+#[test]
+fn planner_with_overlapping_images_is_error() {
+    let kernel = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let mut sut = Planner::new(vec![RAM]);
+    sut.add(&kernel, Placement::AnchorAtInput);
+    sut.add(&kernel, Placement::AnchorAtInput);
+
+    let result = sut.plan();
+
+    assert_matches!(
+        result,
+        Err(Error::InvalidElf(ValidationError::OverlappingImages {
+            first: 0,
+            second: 1
+        }))
+    );
+}
+
+#[test]
+fn planner_with_image_outside_ram_is_error() {
+    let kernel = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let mut sut = Planner::new(vec![SMALL_RAM]);
+    sut.add(&kernel, Placement::AnchorAtInput);
+
+    let result = sut.plan();
+
+    assert_matches!(
+        result,
+        Err(Error::InvalidElf(ValidationError::ImageOutsideRam {
+            index: 0,
+            ..
+        }))
+    );
+}
+
+#[test]
+fn planner_add_with_lays_out_image_with_config() {
+    let kernel = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let config = LayoutConfig::new().elide_bss(true);
+    let mut sut = Planner::new(vec![RAM]);
+    sut.add_with(&kernel, Placement::AnchorAtInput, config.clone());
+
+    let layouts = sut.plan().expect("plan failed unexpectedly");
+
+    let expected = kernel
+        .layout_with(LayoutStrategy::FromInput, &config)
+        .expect("Unable to layout output file");
+    assert_eq!(layouts[0].required_size(), expected.required_size());
+}
+
+#[test]
+fn planner_after_with_alignment_past_end_of_address_space_is_error() {
+    let kernel = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let rootserver = Input::new(SMOKETEST_ELF).expect("Unable to read input file");
+    let mut sut = Planner::new(vec![RAM]);
+    let kernel_index = sut.add(&kernel, Placement::AnchorAtInput);
+    sut.add(
+        &rootserver,
+        Placement::After {
+            image: kernel_index,
+            align: u64::MAX,
+        },
+    );
+
+    let result = sut.plan();
+
+    assert_matches!(
+        result,
+        Err(Error::InvalidElf(ValidationError::ImageNotAlignable {
+            index: 1,
+            image: 0,
+            align: u64::MAX
+        }))
+    );
+}
+
+#[test]
+fn planner_with_invalid_image_reference_is_error() {
+    let kernel = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let mut sut = Planner::new(vec![RAM]);
+    sut.add(
+        &kernel,
+        Placement::After {
+            image: 3,
+            align: 0x1000,
+        },
+    );
+
+    let result = sut.plan();
+
+    assert_matches!(
+        result,
+        Err(Error::InvalidElf(ValidationError::InvalidImageReference {
+            index: 0,
+            image: 3
+        }))
+    );
+}
+
+#[test]
+fn planner_with_placement_cycle_is_error() {
+    let kernel = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let rootserver = Input::new(SMOKETEST_ELF).expect("Unable to read input file");
+    let mut sut = Planner::new(vec![RAM]);
+    sut.add(
+        &kernel,
+        Placement::After {
+            image: 1,
+            align: 0x1000,
+        },
+    );
+    sut.add(
+        &rootserver,
+        Placement::After {
+            image: 0,
+            align: 0x1000,
+        },
+    );
+
+    let result = sut.plan();
+
+    assert_matches!(
+        result,
+        Err(Error::InvalidElf(ValidationError::PlacementCycle))
+    );
+}
@@ -0,0 +1,61 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use elf_preload::{Input, LayoutConfig, LayoutStrategy, Preloaded};
+use goblin::elf::program_header;
+
+static KERNEL_ELF: &[u8] = include_bytes!("../test_data/kernel.elf");
+
+#[test]
+fn elf_preload_preloaded_segments_match_layout() {
+    let input = Input::new(KERNEL_ELF).expect("Unable to parse input file");
+    let layout = input
+        .layout_with(LayoutStrategy::FromInput, &LayoutConfig::new())
+        .expect("Unable to layout input");
+    let mut output = vec![0; layout.required_size()];
+    layout
+        .output(&mut output)
+        .expect("Output too small")
+        .write()
+        .expect("Unable to write output");
+
+    let preloaded = Preloaded::new(&output).expect("Output is not a preloaded image");
+
+    let loads = layout
+        .program_headers()
+        .iter()
+        .filter(|ph| ph.p_type == program_header::PT_LOAD);
+    assert_eq!(preloaded.segments().count(), loads.clone().count());
+    for (segment, ph) in preloaded.segments().zip(loads) {
+        assert_eq!(segment.paddr(), ph.p_paddr);
+        assert_eq!(segment.contents(), &output[ph.file_range()]);
+    }
+    assert_eq!(preloaded.paddr_range(), layout.paddr_range());
+    assert_eq!(preloaded.entry(), 0xe000_0000);
+}
+
+#[test]
+fn elf_preload_preloaded_accepts_elided_bss() {
+    let input = Input::new(KERNEL_ELF).expect("Unable to parse input file");
+    let layout = input
+        .layout_with(
+            LayoutStrategy::SpecifiedStart(0x1000_0000),
+            &LayoutConfig::new().elide_bss(true),
+        )
+        .expect("Unable to layout input");
+    let mut output = vec![0; layout.required_size()];
+    layout
+        .output(&mut output)
+        .expect("Output too small")
+        .write()
+        .expect("Unable to write output");
+
+    let preloaded = Preloaded::new(&output).expect("Output is not a preloaded image");
+
+    let last = preloaded.segments().last().expect("No segments");
+    assert!((last.contents().len() as u64) < last.mem_size());
+}
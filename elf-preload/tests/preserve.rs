@@ -0,0 +1,80 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use elf_preload::{Input, LayoutConfig, LayoutStrategy};
+use goblin::elf::{program_header, Elf, ProgramHeader};
+
+static SMOKETEST_ELF: &[u8] = include_bytes!("../test_data/smoketest");
+
+#[test]
+fn elf_preload_preserves_note_contents() {
+    let config = LayoutConfig::new().preserve(program_header::PT_NOTE);
+    let output = preserve_output(&config);
+
+    let input_elf = Elf::parse(SMOKETEST_ELF).expect("Unable to parse input file");
+    let output_elf = Elf::parse(&output).expect("Unable to parse output file");
+    let input_note = find_header(&input_elf, program_header::PT_NOTE).expect("No input PT_NOTE");
+    let output_note = find_header(&output_elf, program_header::PT_NOTE).expect("No output PT_NOTE");
+    assert_eq!(
+        &output[output_note.file_range()],
+        &SMOKETEST_ELF[input_note.file_range()]
+    );
+    assert_eq!(output_note.p_vaddr, input_note.p_vaddr);
+}
+
+#[test]
+fn elf_preload_preserves_gnu_stack_permissions() {
+    let config = LayoutConfig::new().preserve(program_header::PT_GNU_STACK);
+    let output = preserve_output(&config);
+
+    let output_elf = Elf::parse(&output).expect("Unable to parse output file");
+    let stack = find_header(&output_elf, program_header::PT_GNU_STACK).expect("No PT_GNU_STACK");
+    assert_eq!(stack.p_flags, program_header::PF_R | program_header::PF_W);
+    assert_eq!(stack.p_filesz, 0);
+}
+
+#[test]
+fn elf_preload_with_preserved_headers_has_preserved_headers_within_loads() {
+    let config = LayoutConfig::new()
+        .preserve(program_header::PT_NOTE)
+        .preserve(program_header::PT_ARM_EXIDX);
+    let output = preserve_output(&config);
+
+    let output_elf = Elf::parse(&output).expect("Unable to parse output file");
+    let (loads, others): (Vec<_>, Vec<_>) = output_elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type != program_header::PT_PHDR)
+        .partition(|ph| ph.p_type == program_header::PT_LOAD);
+    assert_eq!(others.len(), 2);
+    for other in others {
+        assert!(loads.iter().any(|load| {
+            other.p_offset >= load.p_offset
+                && other.p_offset + other.p_filesz <= load.p_offset + load.p_filesz
+                && other.p_paddr - load.p_paddr == other.p_offset - load.p_offset
+        }));
+    }
+}
+
+fn preserve_output(config: &LayoutConfig) -> Vec<u8> {
+    let input = Input::new(SMOKETEST_ELF).expect("Unable to read input file");
+    let layout = input
+        .layout_with(LayoutStrategy::SpecifiedStart(0x1000_0000), config)
+        .expect("Unable to layout output file");
+    let mut output = vec![0; layout.required_size()];
+    let mut writer = layout.output(&mut output).expect("Unable to create writer");
+    writer.write().expect("Unable to write output file");
+
+    output
+}
+
+fn find_header(elf: &Elf, p_type: u32) -> Option<ProgramHeader> {
+    elf.program_headers
+        .iter()
+        .find(|ph| ph.p_type == p_type)
+        .cloned()
+}
@@ -0,0 +1,116 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use elf_preload::{Input, Layout, LayoutStrategy};
+use goblin::elf::{program_header, Elf};
+use std::collections::BTreeMap;
+
+static KERNEL_ELF: &[u8] = include_bytes!("../test_data/kernel.elf");
+static SMOKETEST_ELF: &[u8] = include_bytes!("../test_data/smoketest");
+
+#[test]
+fn elf_preload_ihex_gives_same_bytes_as_elf_output() {
+    let input = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let layout = input
+        .layout(LayoutStrategy::FromInput)
+        .expect("Unable to layout output file");
+    let mut ihex = Vec::new();
+
+    layout
+        .write_ihex_to(&mut ihex)
+        .expect("Unable to write Intel HEX");
+
+    assert_eq!(decode_ihex(&ihex), elf_memory(&layout));
+}
+
+#[test]
+fn elf_preload_srec_gives_same_bytes_as_elf_output() {
+    let input = Input::new(SMOKETEST_ELF).expect("Unable to read input file");
+    let layout = input
+        .layout(LayoutStrategy::SpecifiedStart(0x1000_0000))
+        .expect("Unable to layout output file");
+    let mut srec = Vec::new();
+
+    layout
+        .write_srec_to(&mut srec)
+        .expect("Unable to write S-records");
+
+    assert_eq!(decode_srec(&srec), elf_memory(&layout));
+}
+
+// The bytes of the ELF output at each of their physical addresses.
+fn elf_memory(layout: &Layout) -> BTreeMap<u64, u8> {
+    let mut output = vec![0; layout.required_size()];
+    layout
+        .output(&mut output)
+        .expect("Output too small")
+        .write()
+        .expect("Unable to write output");
+    let elf = Elf::parse(&output).expect("Unable to parse output file");
+
+    let mut memory = BTreeMap::new();
+    for ph in elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == program_header::PT_LOAD)
+    {
+        for (index, byte) in output[ph.file_range()].iter().enumerate() {
+            memory.insert(ph.p_paddr + index as u64, *byte);
+        }
+    }
+
+    memory
+}
+
+fn decode_ihex(ihex: &[u8]) -> BTreeMap<u64, u8> {
+    let mut memory = BTreeMap::new();
+    let mut upper = 0;
+
+    for line in String::from_utf8_lossy(ihex).lines() {
+        let record = decode_hex(&line[1..]);
+        assert_eq!(record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)), 0);
+        let address = u64::from(u16::from_be_bytes([record[1], record[2]]));
+        let data = &record[4..record.len() - 1];
+        match record[3] {
+            0x00 => {
+                for (index, byte) in data.iter().enumerate() {
+                    memory.insert(upper + address + index as u64, *byte);
+                }
+            }
+            0x04 => upper = u64::from(u16::from_be_bytes([data[0], data[1]])) << 16,
+            _ => {}
+        }
+    }
+
+    memory
+}
+
+fn decode_srec(srec: &[u8]) -> BTreeMap<u64, u8> {
+    let mut memory = BTreeMap::new();
+
+    for line in String::from_utf8_lossy(srec).lines() {
+        let record = decode_hex(&line[2..]);
+        assert_eq!(record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)), 0xff);
+        if &line[..2] == "S3" {
+            let address = u64::from(u32::from_be_bytes([
+                record[1], record[2], record[3], record[4],
+            ]));
+            for (index, byte) in record[5..record.len() - 1].iter().enumerate() {
+                memory.insert(address + index as u64, *byte);
+            }
+        }
+    }
+
+    memory
+}
+
+fn decode_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).expect("Bad hex digit"))
+        .collect()
+}
@@ -0,0 +1,70 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use elf_preload::{Input, LayoutStrategy};
+use goblin::elf::{header, program_header, Elf};
+use scroll::Pread;
+
+// test_data/static-pie is built from test_data/static-pie.c with:
+//   gcc -O2 -static-pie -nostdlib -fPIE -Wl,--build-id=none -o static-pie static-pie.c
+static STATIC_PIE_ELF: &[u8] = include_bytes!("../test_data/static-pie");
+
+const BASE: u64 = 0x4000_0000;
+
+#[test]
+fn elf_preload_with_base_writes_executable() {
+    let output = run_preload(STATIC_PIE_ELF, BASE);
+
+    let elf = Elf::parse(&output).expect("Output file invalid");
+
+    assert_eq!(elf.header.e_type, header::ET_EXEC);
+    assert!(elf.dynamic.is_none());
+}
+
+#[test]
+fn elf_preload_with_base_offsets_entry() {
+    let input = Elf::parse(STATIC_PIE_ELF).expect("Input file invalid");
+    let output = run_preload(STATIC_PIE_ELF, BASE);
+
+    let elf = Elf::parse(&output).expect("Output file invalid");
+
+    assert_eq!(elf.header.e_entry, input.header.e_entry + BASE);
+}
+
+#[test]
+fn elf_preload_with_base_applies_relocations() {
+    let input = Elf::parse(STATIC_PIE_ELF).expect("Input file invalid");
+    let output = run_preload(STATIC_PIE_ELF, BASE);
+
+    let elf = Elf::parse(&output).expect("Output file invalid");
+
+    assert!(input.dynrelas.len() > 0);
+    for reloc in input.dynrelas.iter() {
+        let vaddr = reloc.r_offset + BASE;
+        let phdr = elf
+            .program_headers
+            .iter()
+            .filter(|ph| ph.p_type == program_header::PT_LOAD)
+            .find(|ph| vaddr >= ph.p_vaddr && vaddr < ph.p_vaddr + ph.p_filesz)
+            .expect("Relocation is not in the output");
+        let offset = (phdr.p_offset + (vaddr - phdr.p_vaddr)) as usize;
+        let value: u64 = output.pread_with(offset, scroll::LE).unwrap();
+        assert_eq!(value, BASE + reloc.r_addend.unwrap() as u64);
+    }
+}
+
+fn run_preload(input: &[u8], base: u64) -> Vec<u8> {
+    let input = Input::with_base(input, base).expect("Unable to read input file");
+    let layout = input
+        .layout(LayoutStrategy::SpecifiedStart(base))
+        .expect("Unable to layout output file");
+    let mut output = vec![0xd0; layout.required_size()];
+    let mut writer = layout.output(&mut output).expect("Unable to create writer");
+    writer.write().expect("Unable to write output file");
+
+    output
+}
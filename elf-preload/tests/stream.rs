@@ -0,0 +1,70 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use elf_preload::{Input, LayoutStrategy};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+static SMOKETEST_ELF: &[u8] = include_bytes!("../test_data/smoketest");
+static KERNEL_ELF: &[u8] = include_bytes!("../test_data/kernel.elf");
+
+#[test]
+fn elf_preload_write_to_matches_output_for_specified_start() {
+    write_to_matches_output_test(SMOKETEST_ELF, LayoutStrategy::SpecifiedStart(5000));
+}
+
+#[test]
+fn elf_preload_write_to_matches_output_for_from_input() {
+    write_to_matches_output_test(KERNEL_ELF, LayoutStrategy::FromInput);
+}
+
+#[test]
+fn elf_preload_write_binary_to_matches_binary_output() {
+    let input = Input::new(KERNEL_ELF).expect("Unable to read input file");
+    let layout = input
+        .layout(LayoutStrategy::FromInput)
+        .expect("Unable to layout output file");
+    let image = layout.binary_image().expect("Unable to place binary");
+    let mut expected = vec![0xd0; image.size()];
+    let mut writer = layout
+        .binary_output(&mut expected)
+        .expect("Unable to create binary writer");
+    writer.write().expect("Unable to write binary output");
+
+    let mut file = tempfile::tempfile().expect("Couldn't create temp file.");
+    layout
+        .write_binary_to(&mut file)
+        .expect("Unable to stream binary output");
+
+    assert_eq!(read_back(&mut file), expected);
+}
+
+fn write_to_matches_output_test(input: &[u8], strategy: LayoutStrategy) {
+    let input = Input::new(input).expect("Unable to read input file");
+    let layout = input
+        .layout(strategy)
+        .expect("Unable to layout output file");
+    let mut expected = vec![0; layout.required_size()];
+    let mut writer = layout
+        .output(&mut expected)
+        .expect("Unable to create writer");
+    writer.write().expect("Unable to write output file");
+
+    let mut file = tempfile::tempfile().expect("Couldn't create temp file.");
+    layout
+        .write_to(&mut file)
+        .expect("Unable to stream output file");
+
+    assert_eq!(read_back(&mut file), expected);
+}
+
+fn read_back(file: &mut File) -> Vec<u8> {
+    let mut output = Vec::new();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.read_to_end(&mut output).unwrap();
+    output
+}
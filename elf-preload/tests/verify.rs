@@ -0,0 +1,93 @@
+// Copyright 2019 Steven Bosnick
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE-2.0 or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms
+
+use assert_matches::assert_matches;
+use elf_preload::{verify_output, Constraint, Input, LayoutConfig, LayoutStrategy};
+use goblin::elf::program_header;
+
+static SMOKETEST_ELF: &[u8] = include_bytes!("../test_data/smoketest");
+static KERNEL_ELF: &[u8] = include_bytes!("../test_data/kernel.elf");
+
+#[test]
+fn elf_preload_output_conforms_for_specified_start() {
+    let output = run_preload(
+        SMOKETEST_ELF,
+        LayoutStrategy::SpecifiedStart(0x1000_0000),
+        &LayoutConfig::new(),
+    );
+
+    let report = verify_output(&output).expect("Output file invalid");
+
+    assert!(report.is_conforming(), "{}", report);
+}
+
+#[test]
+fn elf_preload_output_conforms_for_from_input() {
+    let output = run_preload(KERNEL_ELF, LayoutStrategy::FromInput, &LayoutConfig::new());
+
+    let report = verify_output(&output).expect("Output file invalid");
+
+    assert!(report.is_conforming(), "{}", report);
+}
+
+#[test]
+fn elf_preload_output_conforms_with_elided_bss_and_preserved_headers() {
+    let config = LayoutConfig::new()
+        .elide_bss(true)
+        .preserve(program_header::PT_NOTE)
+        .preserve(program_header::PT_GNU_STACK);
+    let output = run_preload(
+        SMOKETEST_ELF,
+        LayoutStrategy::SpecifiedStart(0x1000_0000),
+        &config,
+    );
+
+    let report = verify_output(&output).expect("Output file invalid");
+
+    assert!(report.is_conforming(), "{}", report);
+}
+
+#[cfg(feature = "integrity")]
+#[test]
+fn elf_preload_output_conforms_with_digests() {
+    let config = LayoutConfig::new().digests(true);
+    let output = run_preload(KERNEL_ELF, LayoutStrategy::FromInput, &config);
+
+    let report = verify_output(&output).expect("Output file invalid");
+
+    assert!(report.is_conforming(), "{}", report);
+}
+
+#[test]
+fn elf_preload_output_with_trailing_bytes_fails_plenum() {
+    let mut output = run_preload(KERNEL_ELF, LayoutStrategy::FromInput, &LayoutConfig::new());
+    output.extend_from_slice(&[0; 16]);
+
+    let report = verify_output(&output).expect("Output file invalid");
+
+    assert_matches!(report.violation(Constraint::Plenum), Some(_));
+    assert_eq!(report.violations().count(), 1);
+}
+
+#[test]
+fn verify_output_for_input_elf_is_not_conforming() {
+    let report = verify_output(SMOKETEST_ELF).expect("Input file invalid");
+
+    assert_matches!(report.violation(Constraint::ProgramHeader), Some(_));
+}
+
+fn run_preload(input: &[u8], strategy: LayoutStrategy, config: &LayoutConfig) -> Vec<u8> {
+    let input = Input::new(input).expect("Unable to read input file");
+    let layout = input
+        .layout_with(strategy, config)
+        .expect("Unable to layout output file");
+    let mut output = vec![0; layout.required_size()];
+    let mut writer = layout.output(&mut output).expect("Unable to create writer");
+    writer.write().expect("Unable to write output file");
+
+    output
+}